@@ -1,6 +1,14 @@
 use memmap::MmapOptions;
 use tdb_core::dtf::{self, file_format as ff};
+use tdb_core::dtf::update::Update;
+use tdb_core::postprocessing::bbo::{Bbo, BboIter};
+use tdb_core::postprocessing::l3book::L3Book;
+use tdb_core::postprocessing::candle::{Candle, Sampler};
+use tdb_core::postprocessing::candle::bars::{BarBuilder, BarsIter, scan_bars_for_range};
 use tdb_core::postprocessing::candle::time_bars::TimeBars;
+use tdb_core::postprocessing::candle::imbalance_bars::{BarKind, ImbalanceSampler};
+use tdb_core::postprocessing::candle::run_bars::RunSampler;
+use tdb_core::postprocessing::classify::{Classification, TradeClassifier};
 use std::fs::File;
 use indicatif::{ProgressBar, ProgressStyle};
 
@@ -20,6 +28,9 @@ pub fn run(matches: &clap::ArgMatches) {
     let timebars = matches.is_present("timebars");
     let aligned = matches.is_present("aligned");
     let granularity = matches.value_of("minutes").unwrap_or("1");
+    let bars = matches.value_of("bars");
+    let extended = matches.is_present("extended");
+    let bbo = matches.is_present("bbo");
     // misc
     let has_output = matches.is_present("output");
     let print_metadata = matches.is_present("meta");
@@ -28,7 +39,9 @@ pub fn run(matches: &clap::ArgMatches) {
         println!("Either supply a single file with -i or specify range.");
         ::std::process::exit(1);
     }
-    if input != "" {
+    if !input.is_empty() && dtf::l3::is_l3_file(input).unwrap_or(false) {
+        print_l3(matches, input, min, max);
+    } else if input != "" {
         if print_metadata {
             println!("{}", dtf::file_format::read_meta(input).unwrap());
 
@@ -39,13 +52,21 @@ pub fn run(matches: &clap::ArgMatches) {
         } else {
             if timebars {
                 let ups = dtf::file_format::decode(input, None).unwrap();
-                let mut candles = TimeBars::from(ups.as_slice());
+                let mut candles = TimeBars::from_updates_with(ups.as_slice(), classifier(matches));
                 candles.insert_continuation_candles();
                 let rebinned = candles
                     .rebin(aligned, granularity.parse().unwrap())
                     .unwrap()
-                    .to_csv();
+                    .to_csv_with(extended);
                 println!("{}", rebinned)
+            } else if let Some(bars) = bars {
+                let ups = dtf::file_format::decode(input, None).unwrap();
+                let ups = ups.into_iter().filter(|up| up.ts >= min && up.ts <= max);
+                print_bars(matches, bars, ups);
+            } else if bbo {
+                let ups = dtf::file_format::decode(input, None).unwrap();
+                let ups = ups.into_iter().filter(|up| up.ts >= min && up.ts <= max);
+                print_bbo(matches, ups);
             } else {
 
                 let file = File::open(input).unwrap();
@@ -94,13 +115,25 @@ pub fn run(matches: &clap::ArgMatches) {
                     min,
                     max,
                     ).unwrap();
-                let mut candles = TimeBars::from(ups.as_slice());
+                let mut candles = TimeBars::from_updates_with(ups.as_slice(), classifier(matches));
                 candles.insert_continuation_candles();
                 let rebinned = candles
                     .rebin(aligned, granularity.parse().unwrap())
                     .unwrap()
-                    .to_csv();
+                    .to_csv_with(extended);
                 println!("{}", rebinned)
+            } else if let Some(bars) = bars {
+                // streamed file by file, ranges of any length fit in memory
+                let builder = BarBuilder::new(bar_sampler(matches, bars)).with_classifier(classifier(matches));
+                scan_bars_for_range(folder, symbol, min, max, builder, &mut |candle| print_candle(matches, &candle)).unwrap();
+            } else if bbo {
+                let ups = tdb_core::dtf::file_format::scan_files_for_range(
+                    folder,
+                    symbol,
+                    min,
+                    max,
+                    ).unwrap();
+                print_bbo(matches, ups.into_iter());
             } else {
                 let mut ret = vec![];
                 tdb_core::dtf::file_format::scan_files_for_range_for_each(
@@ -128,4 +161,89 @@ pub fn run(matches: &clap::ArgMatches) {
     };
 
 
-}
\ No newline at end of file
+}
+
+fn bar_sampler(matches: &clap::ArgMatches, bars: &str) -> Box<dyn Sampler> {
+    let expected_ticks = matches.value_of("expected_ticks").unwrap().parse().unwrap();
+    let alpha = matches.value_of("alpha").unwrap().parse().unwrap();
+    let kind = match bars.split('-').next() {
+        Some("volume") => BarKind::Volume,
+        Some("dollar") => BarKind::Dollar,
+        _ => BarKind::Tick,
+    };
+    if bars.ends_with("-run") {
+        Box::new(RunSampler::new(kind, expected_ticks, alpha))
+    } else {
+        Box::new(ImbalanceSampler::new(kind, expected_ticks, alpha))
+    }
+}
+
+fn print_candle(matches: &clap::ArgMatches, candle: &Candle) {
+    if matches.is_present("extended") {
+        println!("{}", candle.to_csv_extended());
+    } else {
+        println!("{}", candle.to_csv());
+    }
+}
+
+fn print_bars<I: Iterator<Item=Update>>(matches: &clap::ArgMatches, bars: &str, ups: I) {
+    let candles = BarsIter::with_sampler(ups, bar_sampler(matches, bars)).with_classifier(classifier(matches));
+    for candle in candles {
+        print_candle(matches, &candle);
+    }
+}
+
+/// order-by-order files: metadata, events, or the aggregated book with --bbo
+fn print_l3(matches: &clap::ArgMatches, input: &str, min: u64, max: u64) {
+    if matches.is_present("meta") {
+        println!("{}", dtf::l3::read_meta(input).unwrap());
+        return;
+    }
+    let csv = matches.is_present("csv");
+    let ups = dtf::l3::decode(input).unwrap();
+    let ups = ups.iter().filter(|up| up.ts >= min && up.ts <= max);
+    if matches.is_present("bbo") {
+        let decimals = matches.value_of("decimals").unwrap().parse().unwrap();
+        let mut book = L3Book::with_precision(decimals);
+        let mut last: Option<Bbo> = None;
+        for up in ups {
+            book.process_update(up);
+            let bbo = book.bbo(up.ts);
+            if last.map(|last| !last.same_quotes(&bbo)).unwrap_or(true) {
+                println!("{}", if csv { bbo.to_csv() } else { bbo.as_json() });
+                last = Some(bbo);
+            }
+        }
+    } else {
+        for up in ups {
+            if csv {
+                println!("{}", up.to_csv());
+            } else {
+                println!("[{}]", up.as_json());
+            }
+        }
+    }
+}
+
+fn print_bbo<I: Iterator<Item=Update>>(matches: &clap::ArgMatches, ups: I) {
+    let decimals = matches.value_of("decimals").unwrap().parse().unwrap();
+    let csv = matches.is_present("csv");
+    for bbo in BboIter::new(ups, decimals) {
+        if csv {
+            println!("{}", bbo.to_csv());
+        } else {
+            println!("{}", bbo.as_json());
+        }
+    }
+}
+
+fn classifier(matches: &clap::ArgMatches) -> TradeClassifier {
+    let method = match matches.value_of("classify").unwrap() {
+        "exchange-inverted" => Classification::ExchangeInverted,
+        "tick" => Classification::TickRule,
+        "quote" => Classification::QuoteRule,
+        "lee-ready" => Classification::LeeReady,
+        _ => Classification::Exchange,
+    };
+    TradeClassifier::new(method, matches.value_of("decimals").unwrap().parse().unwrap())
+}
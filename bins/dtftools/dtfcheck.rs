@@ -1,11 +1,21 @@
 use memmap::MmapOptions;
 use tdb_core::dtf;
+use tdb_core::postprocessing::orderbook::{BookOptions, CrossHandling, Orderbook, TradeHandling};
 use std::fs::File;
 use indicatif::{ProgressBar, ProgressStyle};
 
 pub fn run(matches: &clap::ArgMatches) {
     let input = matches.value_of("input").unwrap();
     let threshold: i64 = matches.value_of("threshold").unwrap_or("60").parse().unwrap();
+    let mut book = if matches.is_present("book") {
+        let options = BookOptions {
+            trades: if matches.is_present("ignore_trades") { TradeHandling::Ignore } else { TradeHandling::Subtract },
+            crossed: if matches.is_present("uncross") { CrossHandling::RemoveStale } else { CrossHandling::Keep },
+        };
+        Some(Orderbook::with_options(matches.value_of("decimals").unwrap().parse().unwrap(), options))
+    } else {
+        None
+    };
 
     let file = File::open(input).unwrap();
     let rdr = unsafe { MmapOptions::new().map(&file).unwrap() };
@@ -30,7 +40,14 @@ pub fn run(matches: &clap::ArgMatches) {
                 println!("Gap detected: {} = {} - {}, {} - {}", gap, prev.ts, up.ts, prevts, upts);
             }
         }
+        if let Some(book) = book.as_mut() {
+            book.process_update(&up);
+        }
         prev = Some(up);
     }
     bar.finish();
+
+    if let Some(book) = book {
+        println!("{}", serde_json::to_string_pretty(&book.report).unwrap());
+    }
 }
\ No newline at end of file
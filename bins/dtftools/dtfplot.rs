@@ -0,0 +1,75 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use tdb_core::dtf;
+use tdb_core::postprocessing::candle::time_bars::TimeBars;
+use tdb_core::postprocessing::orderbook::RebinnedOrderbook;
+use tdb_core::postprocessing::plot::{self, ChartOptions};
+
+pub fn run(matches: &clap::ArgMatches) {
+    let min = matches.value_of("min").unwrap_or("0").parse().unwrap();
+    let max = matches.value_of("max").unwrap_or("9999999999999").parse().unwrap();
+    if min > max {
+        println!("min must be smaller than max");
+        ::std::process::exit(1);
+    }
+    let kind = matches.value_of("kind").unwrap();
+    let output = matches.value_of("output").unwrap();
+    let is_svg = Path::new(output).extension().map(|ext| ext == "svg").unwrap_or(false);
+    if is_svg && kind == "both" {
+        println!("an svg file holds a single chart, use --kind candles or --kind heatmap");
+        ::std::process::exit(1);
+    }
+
+    let (ups, title) = match matches.value_of("input") {
+        Some(input) => (dtf::file_format::decode(input, None), input.to_owned()),
+        None => {
+            let folder = matches.value_of("folder").unwrap_or("./");
+            let symbol = matches.value_of("symbol").unwrap();
+            (dtf::file_format::scan_files_for_range(folder, symbol, min, max), symbol.to_owned())
+        }
+    };
+    let ups: Vec<_> = match ups {
+        Ok(ups) => ups.into_iter().filter(|up| up.ts >= min && up.ts <= max).collect(),
+        Err(e) => {
+            println!("{}", e);
+            ::std::process::exit(1);
+        }
+    };
+    if ups.is_empty() {
+        println!("no updates in range");
+        ::std::process::exit(1);
+    }
+
+    let width = matches.value_of("width").unwrap().parse().unwrap();
+    let height = matches.value_of("height").unwrap().parse().unwrap();
+    let mut svgs = vec![];
+
+    if kind != "heatmap" {
+        let minutes = matches.value_of("minutes").unwrap().parse().unwrap();
+        let mut bars = TimeBars::from(ups.as_slice());
+        if bars.get_size() > 0 {
+            bars.insert_continuation_candles();
+        }
+        let mut candles: Vec<_> = bars.rebin(false, minutes).map(|bars| bars.get_candles().cloned().collect()).unwrap_or_default();
+        candles.sort_by_key(|c| c.start);
+        let title = format!("{} {}m candles", title, minutes);
+        svgs.push(plot::candles_svg(&candles, &ChartOptions { width, height, title: Some(title) }));
+    }
+
+    if kind != "candles" {
+        let decimals = matches.value_of("decimals").unwrap().parse().unwrap();
+        let steps = matches.value_of("steps").unwrap().parse().unwrap();
+        let ticks = matches.value_of("ticks").unwrap().parse().unwrap();
+        let ob = RebinnedOrderbook::from(decimals, &ups, steps, ticks, 2.);
+        let title = format!("{} depth", title);
+        svgs.push(plot::heatmap_svg(&ob, &ChartOptions { width, height, title: Some(title) }));
+    }
+
+    let content = if is_svg { svgs.remove(0) } else { plot::html_page(&title, &svgs) };
+    if let Err(e) = File::create(output).and_then(|mut wtr| wtr.write_all(content.as_bytes())) {
+        println!("unable to write {}: {}", output, e);
+        ::std::process::exit(1);
+    }
+}
@@ -0,0 +1,35 @@
+use tdb_core::dtf;
+use tdb_core::postprocessing::stats::Stats;
+
+pub fn run(matches: &clap::ArgMatches) {
+    let min = matches.value_of("min").unwrap_or("0").parse().unwrap();
+    let max = matches.value_of("max").unwrap_or("9999999999999").parse().unwrap();
+    if min > max {
+        println!("min must be smaller than max");
+        ::std::process::exit(1);
+    }
+    let decimals = matches.value_of("decimals").unwrap().parse().unwrap();
+    let every: u64 = matches.value_of("every").unwrap().parse().unwrap();
+
+    let ups = match matches.value_of("input") {
+        Some(input) => dtf::file_format::decode(input, None),
+        None => {
+            let folder = matches.value_of("folder").unwrap_or("./");
+            let symbol = matches.value_of("symbol").unwrap();
+            dtf::file_format::scan_files_for_range(folder, symbol, min, max)
+        }
+    };
+    let ups: Vec<_> = match ups {
+        Ok(ups) => ups.into_iter().filter(|up| up.ts >= min && up.ts <= max).collect(),
+        Err(e) => {
+            println!("{}", e);
+            ::std::process::exit(1);
+        }
+    };
+    if ups.is_empty() {
+        println!("no updates in range");
+        ::std::process::exit(1);
+    }
+    let stats = Stats::from_updates(decimals, ups.into_iter(), every * 1000);
+    println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+}
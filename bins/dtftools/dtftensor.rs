@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::io::{Write, BufWriter};
+use zip::write::{ZipWriter, FileOptions};
+use zip::CompressionMethod;
+
+use std::path::Path;
+use std::fs::File;
+
+use memmap::MmapOptions;
+
+use tdb_core::dtf;
+use tdb_core::postprocessing::tensor::{OrderbookTensor, TensorConfig, Grid, Normalization};
+
+pub fn run(matches: &clap::ArgMatches) -> Option<()> {
+    let input = matches.value_of("input").unwrap();
+    let price_decimals: u8 = parse(matches, "decimals")?;
+    let tick = match matches.value_of("tick") {
+        Some(_) => parse(matches, "tick")?,
+        None => 10f32.powi(-i32::from(price_decimals)),
+    };
+    let grid = match matches.value_of("absolute") {
+        Some(_) => Grid::Absolute { min: parse(matches, "absolute")?, tick },
+        None => Grid::Relative { tick },
+    };
+    let normalization = match matches.value_of("normalize").unwrap() {
+        "total" => Normalization::Total,
+        "max" => Normalization::Max,
+        "log" => Normalization::Log,
+        _ => Normalization::None,
+    };
+    let config = TensorConfig {
+        step: parse(matches, "step")?,
+        levels: parse(matches, "levels")?,
+        grid,
+        normalization,
+    };
+
+    let file = match File::open(input) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("unable to open {}: {}", input, e);
+            ::std::process::exit(1);
+        }
+    };
+    let rdr = match unsafe { MmapOptions::new().map(&file) } {
+        Ok(rdr) => rdr,
+        Err(e) => {
+            println!("unable to map {}: {}", input, e);
+            ::std::process::exit(1);
+        }
+    };
+    let mut rdr = std::io::Cursor::new(rdr);
+    if let Err(e) = dtf::file_format::read_meta_from_buf(&mut rdr) {
+        println!("unable to read {}: {}", input, e);
+        ::std::process::exit(1);
+    }
+    let mut it = dtf::file_format::iterators::DTFBufReader::new(rdr);
+    let tensor = OrderbookTensor::from_updates(price_decimals, &mut it, &config);
+
+    // output file is the same name except with npz extension
+    let out_fname = match matches.value_of("output") {
+        Some(output) => Path::new(output).to_owned(),
+        None => Path::new(input).with_extension("npz"),
+    };
+    let written = if out_fname.extension().map(|ext| ext == "npy").unwrap_or(false) {
+        write_npy(&tensor, &out_fname)
+    } else {
+        let compression = if matches.is_present("compressed") {
+            CompressionMethod::Deflated
+        } else {
+            CompressionMethod::Stored
+        };
+        write_npz(&tensor, &out_fname, compression)
+    };
+    if let Err(e) = written {
+        println!("unable to write {}: {}", out_fname.display(), e);
+        ::std::process::exit(1);
+    }
+
+    let [steps, levels, sides] = tensor.shape();
+    println!("wrote [{} x {} x {}] tensor", steps, levels, sides);
+    Some(())
+}
+
+fn write_npy(tensor: &OrderbookTensor, out_fname: &Path) -> Result<(), Box<dyn Error>> {
+    let mut wtr = BufWriter::new(File::create(out_fname)?);
+    tensor.write_npy(&mut wtr)?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// book, ts and midprice arrays in one npz archive
+fn write_npz(tensor: &OrderbookTensor, out_fname: &Path, compression: CompressionMethod) -> Result<(), Box<dyn Error>> {
+    let options = FileOptions::default().compression_method(compression);
+    let mut zip = BufWriter::new(ZipWriter::new(File::create(out_fname)?));
+
+    zip.get_mut().start_file("book.npy", options)?;
+    tensor.write_npy(&mut zip)?;
+    zip.flush()?;
+    zip.get_mut().start_file("ts.npy", options)?;
+    tensor.write_ts_npy(&mut zip)?;
+    zip.flush()?;
+    zip.get_mut().start_file("midprice.npy", options)?;
+    tensor.write_midprice_npy(&mut zip)?;
+    zip.flush()?;
+    zip.into_inner().map_err(|e| e.into_error())?.finish()?;
+    Ok(())
+}
+
+fn parse<T: std::str::FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> {
+    let value = matches.value_of(name)?;
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            eprintln!("invalid value for --{}: {}", name, value);
+            None
+        }
+    }
+}
@@ -7,11 +7,14 @@ extern crate indoc;
 
 
 mod dtfnumpy;
+mod dtftensor;
+mod dtfstats;
 mod dtfcheck;
 mod dtfcat;
 mod dtfsplit;
 mod dtfconcat;
 mod dtfrepair;
+mod dtfplot;
 use clap::{Arg, App};
 
 fn main() {
@@ -32,6 +35,14 @@ fn main() {
                 dtftools cat --folder ./test/zrx --symbol bnc_zrx_btc --min 1514764800000 --max 1514851200000 -c -r > out
                 # hour candle
                 dtftools cat --folder ./test/zrx --symbol bnc_zrx_btc --min 1514764800000 --max 1514851200000 -c -r -g 60 > out
+                # volume imbalance bars, first bar expected to span 500 trades
+                dtftools cat test.dtf --bars volume-imbalance --expected-ticks 500
+                # minute candles with buy/sell volume, trade count and vwap, sides by Lee-Ready
+                dtftools cat test.dtf -t --extended --classify lee-ready
+                # best bid and offer every time the top of the book changes
+                dtftools cat test.dtf --bbo --csv
+                # order-by-order (L3) files print their events, --bbo aggregates them
+                dtftools cat orders.dtf --csv
                 # read metadata of file
                 dtftools cat -m test.dtf
                 # convert to csv
@@ -126,6 +137,48 @@ fn main() {
                 .value_name("MINUTES")
                 .help("granularity in minute. e.g. -m 60 # hour candle")
                 .takes_value(true))
+            .arg(Arg::with_name("bars")
+                .long("bars")
+                .conflicts_with("timebars")
+                .value_name("KIND")
+                .possible_values(&[
+                    "tick-imbalance", "volume-imbalance", "dollar-imbalance",
+                    "tick-run", "volume-run", "dollar-run",
+                ])
+                .help("output information-driven candles as csv")
+                .takes_value(true))
+            .arg(Arg::with_name("bbo")
+                .long("bbo")
+                .conflicts_with_all(&["timebars", "bars"])
+                .help("output top of book changes (ts,bid,bid_size,ask,ask_size with --csv)"))
+            .arg(Arg::with_name("extended")
+                .long("extended")
+                .help("add buy volume, sell volume, trade count and vwap to candles"))
+            .arg(Arg::with_name("classify")
+                .long("classify")
+                .value_name("METHOD")
+                .possible_values(&["exchange", "exchange-inverted", "tick", "quote", "lee-ready"])
+                .default_value("exchange")
+                .help("how trade sides of candles are determined")
+                .takes_value(true))
+            .arg(Arg::with_name("decimals")
+                .long("decimals")
+                .value_name("DECIMALS")
+                .default_value("8")
+                .help("price precision of the book used by the quote rule and --bbo")
+                .takes_value(true))
+            .arg(Arg::with_name("expected_ticks")
+                .long("expected-ticks")
+                .value_name("TICKS")
+                .default_value("100")
+                .help("number of trades expected in the first bar")
+                .takes_value(true))
+            .arg(Arg::with_name("alpha")
+                .long("alpha")
+                .value_name("ALPHA")
+                .default_value("0.1")
+                .help("weight of the latest bar in the expected thresholds")
+                .takes_value(true))
         )
 
         .subcommand(clap::SubCommand::with_name("check")
@@ -133,6 +186,8 @@ fn main() {
                 Check dtf file for defect
                 Examples:
                 dtftools check 1.dtf -c
+                # count trades without level, crossed books, etc. of the reconstructed book
+                dtftools check 1.dtf --book --decimals 8
                 "))
             .arg(
                 Arg::with_name("threshold")
@@ -144,6 +199,23 @@ fn main() {
                     .required(false)
                     .takes_value(true)
             )
+            .arg(Arg::with_name("book")
+                .long("book")
+                .help("replay the orderbook and print a report of its anomalies"))
+            .arg(Arg::with_name("decimals")
+                .long("decimals")
+                .value_name("DECIMALS")
+                .default_value("8")
+                .help("price precision of the book")
+                .takes_value(true))
+            .arg(Arg::with_name("ignore_trades")
+                .long("ignore-trades")
+                .requires("book")
+                .help("trades do not reduce levels"))
+            .arg(Arg::with_name("uncross")
+                .long("uncross")
+                .requires("book")
+                .help("remove levels crossed by newer updates"))
             .arg(
                 Arg::with_name("input")
                     .value_name("INPUT")
@@ -172,6 +244,258 @@ fn main() {
                     .takes_value(true),
             ))
 
+        .subcommand(clap::SubCommand::with_name("tensor")
+            .about(indoc!("
+                Convert dtf files to [time_steps x price_levels x {bid,ask}] orderbook tensors
+                Examples:
+                # 1 second steps, 50 ticks on each side of the midprice
+                dtftools tensor 1.dtf --decimals 8 --step 1000 --levels 50
+                # absolute grid from 0.0001 in steps of 0.000001, scaled by the largest level
+                dtftools tensor 1.dtf --absolute 0.0001 --tick 0.000001 --normalize max -o book.npy
+                "))
+            .arg(
+                Arg::with_name("input")
+                    .value_name("INPUT")
+                    .help("file to read")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("output file, .npy for the book only, .npz for book, ts and midprice")
+                .required(false)
+                .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("decimals")
+                    .long("decimals")
+                    .value_name("DECIMALS")
+                    .help("price precision of the book")
+                    .default_value("8")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("step")
+                    .long("step")
+                    .value_name("MS")
+                    .help("length of a time step in milliseconds")
+                    .default_value("1000")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("levels")
+                    .long("levels")
+                    .value_name("LEVELS")
+                    .help("number of price levels")
+                    .default_value("50")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("tick")
+                    .long("tick")
+                    .value_name("TICK")
+                    .help("width of a price level, defaults to the price precision")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("absolute")
+                    .long("absolute")
+                    .value_name("MIN")
+                    .help("use an absolute grid starting at MIN instead of ticks from the midprice")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("normalize")
+                    .long("normalize")
+                    .value_name("METHOD")
+                    .possible_values(&["none", "total", "max", "log"])
+                    .default_value("none")
+                    .help("scale the sizes of each time step")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("compressed")
+                    .short("c")
+                    .long("compressed")
+                    .help("use Deflated compression")
+            ))
+
+        .subcommand(clap::SubCommand::with_name("stats")
+            .about(indoc!("
+                Print VWAP, TWAP, realized volatility, spread and trade size statistics as json
+                Examples:
+                dtftools stats test.dtf --every 1
+                dtftools stats --folder ./test/zrx --symbol bnc_zrx_btc --min 1514764800000 --max 1514851200000
+                "))
+            .arg(
+                Arg::with_name("input")
+                    .value_name("INPUT")
+                    .help("file to read")
+                    .required_unless("folder")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("folder")
+                .long("folder")
+                .conflicts_with("input")
+                .requires("symbol")
+                .value_name("FOLDER")
+                .help("folder to search")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("symbol")
+                .long("symbol")
+                .value_name("SYMBOL")
+                .help("symbol too lookup")
+                .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("min")
+                .long("min")
+                .value_name("MIN")
+                .help("minimum value to filter for")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("max")
+                .long("max")
+                .value_name("MAX")
+                .help("maximum value to filter for")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("every")
+                    .long("every")
+                    .value_name("SECONDS")
+                    .help("interval the book is sampled at for twap, volatility and spread")
+                    .default_value("60")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("decimals")
+                    .long("decimals")
+                    .value_name("DECIMALS")
+                    .help("price precision of the book")
+                    .default_value("8")
+                    .takes_value(true),
+            ))
+
+        .subcommand(clap::SubCommand::with_name("plot")
+            .about(indoc!("
+                Render candles with volume and depth heatmaps as standalone svg or html
+                Examples:
+                dtftools plot test.dtf -o test.html
+                dtftools plot test.dtf --kind heatmap --steps 200 --ticks 100 -o depth.svg
+                dtftools plot --folder ./test/zrx --symbol bnc_zrx_btc --min 1514764800000 --max 1514851200000 --minutes 5 -o day.html
+                "))
+            .arg(
+                Arg::with_name("input")
+                    .value_name("INPUT")
+                    .help("file to read")
+                    .required_unless("folder")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("folder")
+                .long("folder")
+                .conflicts_with("input")
+                .requires("symbol")
+                .value_name("FOLDER")
+                .help("folder to search")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("symbol")
+                .long("symbol")
+                .value_name("SYMBOL")
+                .help("symbol too lookup")
+                .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("min")
+                .long("min")
+                .value_name("MIN")
+                .help("minimum value to filter for")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("max")
+                .long("max")
+                .value_name("MAX")
+                .help("maximum value to filter for")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .value_name("OUTPUT")
+                    .help("output file, an .svg file holds a single chart, anything else is written as html")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("kind")
+                    .long("kind")
+                    .value_name("KIND")
+                    .help("charts to render")
+                    .possible_values(&["candles", "heatmap", "both"])
+                    .default_value("both")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("minutes")
+                    .long("minutes")
+                    .value_name("MINUTES")
+                    .help("candle granularity")
+                    .default_value("1")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("decimals")
+                    .long("decimals")
+                    .value_name("DECIMALS")
+                    .help("price precision of the book")
+                    .default_value("8")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("steps")
+                    .long("steps")
+                    .value_name("STEPS")
+                    .help("number of time bins of the heatmap")
+                    .default_value("100")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ticks")
+                    .long("ticks")
+                    .value_name("TICKS")
+                    .help("number of price bins of the heatmap")
+                    .default_value("100")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("width")
+                    .long("width")
+                    .value_name("PIXELS")
+                    .help("chart width")
+                    .default_value("1200")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("height")
+                    .long("height")
+                    .value_name("PIXELS")
+                    .help("chart height")
+                    .default_value("600")
+                    .takes_value(true),
+            ))
+
         .subcommand(clap::SubCommand::with_name("concat")
                 .about(indoc!("
                     Concatenates two DTF files into a single output file.
@@ -252,6 +576,15 @@ fn main() {
         dtfcheck::run(matches);
     } else if let Some(matches) = matches.subcommand_matches("numpy") {
         dtfnumpy::run(matches);
+    } else if let Some(matches) = matches.subcommand_matches("tensor") {
+        // invalid values were already reported
+        if dtftensor::run(matches).is_none() {
+            ::std::process::exit(1);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("stats") {
+        dtfstats::run(matches);
+    } else if let Some(matches) = matches.subcommand_matches("plot") {
+        dtfplot::run(matches);
     } else if let Some(matches) = matches.subcommand_matches("split") {
         dtfsplit::run(matches);
     } else if let Some(matches) = matches.subcommand_matches("concat") {
@@ -3,26 +3,33 @@ extern crate log;
 extern crate fern;
 
 use tdb_server_core::prelude::*;
+use tdb_server_core::config::FileConfig;
 use clap::{Arg, App, ArgMatches};
+use std::fmt::Display;
+use std::str::FromStr;
 
 fn main() {
     // Help detect OpenSSL certificates on Alpine Linux
     openssl_probe::init_ssl_cert_env_vars();
     let matches = get_matches();
 
-    let host = matches
-        .value_of("host")
+    let file = matches
+        .value_of("config")
         .map(String::from)
-        .unwrap_or_else(|| key_or_default("TDB_HOST", "0.0.0.0"));
-    let port = matches
-        .value_of("port")
-        .map(String::from)
-        .unwrap_or_else(|| key_or_default("TDB_PORT", "9001"));
-    let dtf_folder = matches
-        .value_of("dtf_folder")
-        .map(String::from)
-        .unwrap_or_else(|| key_or_default("TDB_DTF_FOLDER", "db"));
-    let verbosity = matches.occurrences_of("v") as u8;
+        .or_else(|| key_or_none("TDB_CONFIG"))
+        .map(|fname| FileConfig::from_file(&fname).unwrap_or_else(exit_with))
+        .unwrap_or_default();
+
+    let host = setting(&matches, "host", "TDB_HOST", file.server.host.clone())
+        .unwrap_or_else(|| "0.0.0.0".into());
+    let port = setting(&matches, "port", "TDB_PORT", file.server.port.map(|p| p.to_string()))
+        .unwrap_or_else(|| "9001".into());
+    let dtf_folder = setting(&matches, "dtf_folder", "TDB_DTF_FOLDER", file.storage.dtf_folder.clone())
+        .unwrap_or_else(|| "db".into());
+    let verbosity = match matches.occurrences_of("v") {
+        0 => file.server.verbosity.unwrap_or(0),
+        v => v as u8,
+    };
     let autoflush = {
         let cli_setting: bool = matches.is_present("autoflush");
         let env_setting = key_or_none("TDB_AUTOFLUSH");
@@ -32,39 +39,59 @@ fn main() {
                 "false" => false,
                 _ => cli_setting,
             },
-            None => cli_setting,
+            None => cli_setting || file.storage.autoflush.unwrap_or(false),
         }
     };
-    let flush_interval = matches
-        .value_of("flush_interval")
-        .map(String::from)
-        .unwrap_or_else(|| key_or_default("TDB_FLUSH_INTERVAL", "1000"));
-    let granularity = matches
-        .value_of("granularity")
-        .map(String::from)
-        .unwrap_or_else(|| key_or_default("TDB_GRANULARITY", "0"));
-    let q_capacity = matches
-        .value_of("q_capacity")
-        .map(String::from)
-        .unwrap_or_else(|| key_or_default("TDB_Q_CAPACITY", "300"));
+    let flush_interval = setting(&matches, "flush_interval", "TDB_FLUSH_INTERVAL",
+        file.storage.flush_interval.map(|i| i.to_string()));
+    let granularity = setting(&matches, "granularity", "TDB_GRANULARITY",
+        file.storage.granularity.map(|g| g.to_string()));
+    let q_capacity = setting(&matches, "q_capacity", "TDB_Q_CAPACITY",
+        file.storage.q_capacity.map(|q| q.to_string()));
+    let memory_limit_mb = setting(&matches, "memory_limit_mb", "TDB_MEMORY_LIMIT_MB",
+        file.storage.memory_limit_mb.map(|m| m.to_string()));
+    let snapshot_interval = setting(&matches, "snapshot_interval", "TDB_SNAPSHOT_INTERVAL",
+        file.storage.snapshot_interval.map(|i| i.to_string()));
+    let snapshot_secs = setting(&matches, "snapshot_secs", "TDB_SNAPSHOT_SECS",
+        file.storage.snapshot_secs.map(|s| s.to_string()));
 
-    let log_file = matches
-        .value_of("log_file")
-        .map(String::from)
-        .unwrap_or_else(|| key_or_default("TDB_LOG_FILE_NAME", "tdb.log"));
+    let log_file = setting(&matches, "log_file", "TDB_LOG_FILE_NAME", file.server.log_file.clone())
+        .unwrap_or_else(|| "tdb.log".into());
+
+    let auth = setting(&matches, "auth_file", "TDB_AUTH_FILE", file.server.auth_file.clone())
+        .map(|fname| tdb_server_core::auth::AuthConfig::from_file(&fname).unwrap_or_else(exit_with));
+
+    let tls = {
+        let cert = setting(&matches, "tls_cert", "TDB_TLS_CERT", file.server.tls_cert.clone());
+        let key = setting(&matches, "tls_key", "TDB_TLS_KEY", file.server.tls_key.clone());
+        match (cert, key) {
+            (Some(cert), Some(key)) => Some(tdb_server_core::settings::TlsSettings { cert, key }),
+            (None, None) => None,
+            _ => exit_with("Both a TLS certificate and a TLS key are required."),
+        }
+    };
+
+    let metrics_addr = setting(&matches, "metrics_addr", "TDB_METRICS_ADDR", file.server.metrics_addr.clone());
 
     let influx = {
         #[cfg(feature = "influx")]
         {
             let influx_host = matches.value_of("influx_host") .map(String::from);
             let influx_db = matches.value_of("influx_db") .map(String::from);
-            let influx_log_interval = matches.value_of("influx_log_interval").unwrap_or("60").parse().unwrap();
-            match (influx_host, influx_db) {
-                (Some(host), Some(db)) =>
+            let influx_log_interval = matches.value_of("influx_log_interval")
+                .map(|i| parse_or_exit("influx-log-interval", i));
+            match (influx_host, influx_db, file.plugins.influx.clone()) {
+                (Some(host), Some(db), file_influx) =>
                     Some(tdb_server_core::settings::InfluxSettings {
                         host,
                         db,
-                        interval: influx_log_interval,
+                        interval: influx_log_interval.or(file_influx.map(|i| i.interval)).unwrap_or(60),
+                    }),
+                (_, _, Some(file_influx)) =>
+                    Some(tdb_server_core::settings::InfluxSettings {
+                        host: file_influx.host,
+                        db: file_influx.db,
+                        interval: influx_log_interval.unwrap_or(file_influx.interval),
                     }),
                 _ => None,
         }
@@ -72,16 +99,26 @@ fn main() {
         #[cfg(not(feature = "influx"))]
         { None }
     };
-    let settings = Arc::new(
-        tdb_server_core::settings::Settings {
-            autoflush,
-            dtf_folder,
-            flush_interval: flush_interval.parse().unwrap(),
-            granularity: granularity.parse().unwrap(),
-            q_capacity: q_capacity.parse().unwrap(),
-            influx,
-        }
-    );
+    let settings = tdb_server_core::settings::Settings {
+        autoflush,
+        dtf_folder,
+        flush_interval: flush_interval.map(|i| parse_or_exit("flush_interval", &i)).unwrap_or(1000),
+        granularity: granularity.map(|g| parse_or_exit("granularity", &g)).unwrap_or(0),
+        q_capacity: q_capacity.map(|q| parse_or_exit("q_capacity", &q)).unwrap_or(300),
+        memory_limit_mb: memory_limit_mb.map(|m| parse_or_exit("memory_limit_mb", &m)).unwrap_or(0),
+        snapshot_interval: snapshot_interval.map(|i| parse_or_exit("snapshot_interval", &i)).unwrap_or(0),
+        snapshot_secs: snapshot_secs.map(|s| parse_or_exit("snapshot_secs", &s)).unwrap_or(0),
+        influx,
+        auth,
+        tls,
+        metrics_addr,
+        books: file.books,
+        gstorage: file.plugins.gstorage,
+    };
+    if let Err(e) = settings.validate() {
+        exit_with::<_, ()>(format!("Invalid configuration: {}", e));
+    }
+    let settings = Arc::new(settings);
 
 
     prepare_logger(verbosity, &log_file);
@@ -96,6 +133,20 @@ fn main() {
     task::block_on(tdb_server_core::server::run_server(&host, &port, settings)).unwrap();
 }
 
+/// CLI argument, then environment variable, then config file value
+fn setting(matches: &ArgMatches, arg: &str, env: &str, file: Option<String>) -> Option<String> {
+    matches.value_of(arg).map(String::from).or_else(|| key_or_none(env)).or(file)
+}
+
+fn parse_or_exit<T: FromStr>(name: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| exit_with(format!("Invalid value for {}: `{}`", name, value)))
+}
+
+fn exit_with<E: Display, T>(e: E) -> T {
+    eprintln!("{}", e);
+    std::process::exit(1);
+}
+
 fn prepare_logger(verbosity: u8, log_file: &str) {
     let level = match verbosity {
         0 => log::LevelFilter::Error,
@@ -132,6 +183,14 @@ fn get_matches<'a>() -> ArgMatches<'a> {
         .version("1.0.0")
         .author("Ricky Han <tectonic@rickyhan.com>")
         .about("tectonic financial datastore")
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("CONFIG_FILE")
+                .help("Sets the TOML config file, flags and TDB_* variables override it")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("host")
                 .short("h")
@@ -179,12 +238,63 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                     "Sets the history record granularity interval. (default 60s)",
                 ),
         )
+        .arg(
+            Arg::with_name("memory_limit_mb")
+                .long("memory-limit-mb")
+                .value_name("MB")
+                .help("Evicts least recently used LOADed books beyond this budget (default unlimited)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snapshot_interval")
+                .long("snapshot-interval")
+                .value_name("UPDATES")
+                .help("Checkpoints orderbooks to FOLDER/[book].snap every n updates (default off)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("snapshot_secs")
+                .long("snapshot-secs")
+                .value_name("SECONDS")
+                .help("Checkpoints orderbooks every n seconds of update timestamps (default off)")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("log_file")
                 .short("l")
                 .long("log_file")
                 .value_name("LOG_FILE")
                 .help("Sets the log file to write to"),
+        )
+        .arg(
+            Arg::with_name("auth_file")
+                .long("auth-file")
+                .value_name("AUTH_FILE")
+                .help("Sets the file with users and tokens, enables AUTH (default off)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls_cert")
+                .long("tls-cert")
+                .value_name("CERT_FILE")
+                .help("Sets the PEM certificate chain, enables TLS together with --tls-key")
+                .requires("tls_key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls_key")
+                .long("tls-key")
+                .value_name("KEY_FILE")
+                .help("Sets the PEM private key, enables TLS together with --tls-cert")
+                .requires("tls_cert")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics_addr")
+                .long("metrics-addr")
+                .value_name("ADDR")
+                .help("Serves unauthenticated prometheus metrics on http://ADDR/metrics, a bare port binds to 127.0.0.1 (default off)")
+                .takes_value(true),
         );
 
         let app = {
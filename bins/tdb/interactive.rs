@@ -1,10 +1,12 @@
 use std::io;
+use std::io::Cursor;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use std::error::Error;
 
 use tdb_cli::client::TectonicClient;
+use tdb_core::dtf::{file_format::decode_buffer, update::UpdateVecConvert};
 
 use linefeed::{Interface, Prompter, ReadResult};
 use linefeed::chars::escape_sequence;
@@ -115,6 +117,19 @@ pub fn run(cli: &mut TectonicClient) -> io::Result<()> {
                 let d = parse_text("<input>", &line);
                 interface.evaluate_directives(d);
             }
+            _ if line.ends_with(" STREAM") => {
+                let text = line.contains(" AS CSV") || line.contains(" AS JSON");
+                let ret = cli.cmd_stream(&line, |chunk| {
+                    if text {
+                        print!("{}", String::from_utf8_lossy(chunk));
+                    } else {
+                        println!("[{}]", decode_buffer(&mut Cursor::new(chunk)).as_json());
+                    }
+                });
+                if let Err(e) = ret {
+                    println!("{}", e);
+                }
+            }
             _ => {
                 match cli.cmd(&line) {
                     Err(e) => {
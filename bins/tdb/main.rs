@@ -53,6 +53,19 @@ fn main() {
                 .help("Sets the port to connect to (default 9001)")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("tls")
+                .long("tls")
+                .help("Connects over TLS, HOST must match the server certificate"),
+        )
+        .arg(
+            Arg::with_name("ca_file")
+                .long("ca-file")
+                .value_name("CA_FILE")
+                .help("Trusts the PEM certificates in CA_FILE instead of the system roots")
+                .requires("tls")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("s")
                 .short("s")
@@ -61,6 +74,12 @@ fn main() {
                 .help("subscribe to the datastore")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("bbo")
+                .long("bbo")
+                .help("with -s, print top of book changes instead of updates")
+                .requires("s"),
+        )
         .arg(
             Arg::with_name("b")
                 .short("b")
@@ -74,7 +93,11 @@ fn main() {
     let host = matches.value_of("host").unwrap_or("0.0.0.0");
     let port = matches.value_of("port").unwrap_or("9001");
 
-    let mut cli = TectonicClient::new(host, port).unwrap();
+    let mut cli = if matches.is_present("tls") {
+        TectonicClient::new_tls(host, port, matches.value_of("ca_file")).unwrap()
+    } else {
+        TectonicClient::new(host, port).unwrap()
+    };
 
     if matches.is_present("b") {
         let times = matches
@@ -85,7 +108,11 @@ fn main() {
         tdb_cli::benchmark(cli, times);
     } else if matches.is_present("s") {
         let dbname = matches.value_of("s").unwrap_or("");
-        subscribe(cli, dbname);
+        if matches.is_present("bbo") {
+            subscribe_bbo(cli, dbname);
+        } else {
+            subscribe(cli, dbname);
+        }
     } else {
         interactive::run(&mut cli).unwrap();
     }
@@ -115,3 +142,10 @@ fn subscribe(cli: TectonicClient, dbname: &str) {
         println!("{:?}", up);
     }
 }
+
+fn subscribe_bbo(cli: TectonicClient, dbname: &str) {
+    println!("Subscribing to {} BBO", dbname);
+    for bbo in cli.subscribe_bbo(dbname).unwrap() {
+        println!("{}", bbo.to_csv());
+    }
+}
@@ -0,0 +1,301 @@
+//! Non-blocking client for async-std based collectors
+//!
+//! Same commands and wire format as `TectonicClient`, over plain TCP or TLS.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use async_std::io::{self, BufReader};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use async_tls::TlsConnector;
+use async_tls::client::TlsStream;
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::stream::{self, Stream};
+use rustls::ClientConfig;
+use tdb_core::dtf::update::{Update, UpdateVecConvert};
+use crate::error::TectonicError;
+use crate::framing;
+use crate::stream::tls_config;
+
+/// Connection to tectonicdb, either plain TCP or TLS
+pub enum AsyncClientStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncClientStream {
+    /// Connects to `host:port`, negotiating TLS when `tls` is given.
+    /// `host` is also the name the server certificate is checked against.
+    pub async fn connect(host: &str, port: &str, tls: Option<&Arc<ClientConfig>>) -> Result<AsyncClientStream, TectonicError> {
+        let sock = TcpStream::connect(format!("{}:{}", host, port)).await
+            .map_err(|_| TectonicError::ConnectionError)?;
+        match tls {
+            None => Ok(AsyncClientStream::Plain(sock)),
+            Some(config) => {
+                let stream = TlsConnector::from(Arc::clone(config)).connect(host, sock).await
+                    .map_err(|e| TectonicError::TlsError(e.to_string()))?;
+                Ok(AsyncClientStream::Tls(Box::new(stream)))
+            }
+        }
+    }
+
+    /// Underlying tcp socket
+    pub fn get_ref(&self) -> &TcpStream {
+        match self {
+            AsyncClientStream::Plain(sock) => sock,
+            AsyncClientStream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl AsyncRead for AsyncClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            AsyncClientStream::Plain(sock) => Pin::new(sock).poll_read(cx, buf),
+            AsyncClientStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AsyncClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            AsyncClientStream::Plain(sock) => Pin::new(sock).poll_write(cx, buf),
+            AsyncClientStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncClientStream::Plain(sock) => Pin::new(sock).poll_flush(cx),
+            AsyncClientStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncClientStream::Plain(sock) => Pin::new(sock).poll_close(cx),
+            AsyncClientStream::Tls(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
+
+pub struct AsyncTectonicClient {
+    pub stream: BufReader<AsyncClientStream>,
+    pub host: String,
+    pub port: String,
+    pub tls: Option<Arc<ClientConfig>>,
+}
+
+impl AsyncTectonicClient {
+    pub async fn new(host: &str, port: &str) -> Result<AsyncTectonicClient, TectonicError> {
+        Self::with_tls_config(host, port, None).await
+    }
+
+    /// Connects over TLS. The server certificate is verified against `ca_file`
+    /// if given, otherwise against the Mozilla root certificates.
+    pub async fn new_tls(host: &str, port: &str, ca_file: Option<&str>) -> Result<AsyncTectonicClient, TectonicError> {
+        Self::with_tls_config(host, port, Some(tls_config(ca_file)?)).await
+    }
+
+    pub async fn with_tls_config(host: &str, port: &str, tls: Option<Arc<ClientConfig>>)
+        -> Result<AsyncTectonicClient, TectonicError>
+    {
+        info!("Connecting to {}:{}{}", host, port, if tls.is_some() { " over TLS" } else { "" });
+        let stream = AsyncClientStream::connect(host, port, tls.as_ref()).await?;
+        Ok(AsyncTectonicClient {
+            stream: BufReader::new(stream),
+            host: host.to_owned(),
+            port: port.to_owned(),
+            tls,
+        })
+    }
+
+    pub async fn reconnect(&mut self) -> Result<(), TectonicError> {
+        info!("Reconnecting to {}:{}", self.host, self.port);
+        let stream = AsyncClientStream::connect(&self.host, &self.port, self.tls.as_ref()).await?;
+        self.stream = BufReader::new(stream);
+        Ok(())
+    }
+
+    async fn send(&mut self, command: &[u8]) -> Result<(), TectonicError> {
+        let stream = self.stream.get_mut();
+        stream.write_all(&framing::frame_command(command)).await?;
+        // TLS streams hold back records until flushed
+        stream.flush().await?;
+        Ok(())
+    }
+
+    /// read one reply, returns the success flag and the payload
+    async fn read_reply(&mut self) -> Result<(bool, Vec<u8>), TectonicError> {
+        let mut header = [0; framing::HEADER_LEN];
+        self.stream.read_exact(&mut header).await.map_err(|_| TectonicError::ConnectionError)?;
+        let (success, size) = framing::parse_header(&header);
+        let mut buf = vec![0; size as usize];
+        self.stream.read_exact(&mut buf).await?;
+        Ok((success, buf))
+    }
+
+    /// payload of a successful reply to `command`
+    async fn request(&mut self, command: &str) -> Result<Vec<u8>, TectonicError> {
+        self.send(command.as_bytes()).await?;
+        match self.read_reply().await? {
+            (true, payload) => Ok(payload),
+            (false, payload) => Err(framing::error_from_reply(&payload)),
+        }
+    }
+
+    pub async fn cmd(&mut self, command: &str) -> Result<String, TectonicError> {
+        let payload = self.request(command).await?;
+        if framing::returns_dtf(command) {
+            Ok(format!("[{}]\n", framing::decode_updates(&payload).as_json()))
+        } else {
+            framing::text_from_reply(payload)
+        }
+    }
+
+    /// Send a `GET` command without `AS CSV` or `AS JSON` and decode the updates
+    pub async fn get(&mut self, command: &str) -> Result<Vec<Update>, TectonicError> {
+        let payload = self.request(command).await?;
+        Ok(framing::decode_updates(&payload))
+    }
+
+    pub async fn create_db(&mut self, book_name: &str) -> Result<String, TectonicError> {
+        info!("Creating db {}", book_name);
+        self.cmd(&format!("CREATE {}\n", book_name)).await
+    }
+
+    pub async fn use_db(&mut self, book_name: &str) -> Result<String, TectonicError> {
+        self.cmd(&format!("USE {}\n", book_name)).await
+    }
+
+    /// send an insert command, the reply is not read when `discard_result` is set
+    pub async fn insert(&mut self, book_name: Option<&str>, update: &Update, discard_result: bool) -> Result<bool, TectonicError> {
+        let buf = tdb_core::utils::encode_insert_into(book_name, update)?;
+        self.send(&buf).await?;
+        if discard_result {
+            return Ok(true);
+        }
+        match self.read_reply().await? {
+            (true, _) => Ok(true),
+            (false, payload) => Err(framing::error_from_reply(&payload)),
+        }
+    }
+
+    pub async fn subscribe(self, book_name: &str) -> Result<impl Stream<Item=Result<Update, TectonicError>>, TectonicError> {
+        self.subscribe_with(&format!("SUBSCRIBE {}\n", book_name)).await
+    }
+
+    /// the book at `ts` arrives first as one update per level, followed by every update since
+    pub async fn subscribe_from(self, book_name: &str, ts: u64) -> Result<impl Stream<Item=Result<Update, TectonicError>>, TectonicError> {
+        self.subscribe_with(&format!("SUBSCRIBE {} FROM {}\n", book_name, ts)).await
+    }
+
+    /// the stream ends after the first connection or server error
+    async fn subscribe_with(mut self, command: &str) -> Result<impl Stream<Item=Result<Update, TectonicError>>, TectonicError> {
+        self.request(command).await?;
+        Ok(stream::unfold(Some(self), |cli| async move {
+            let mut cli = cli?;
+            match cli.read_reply().await {
+                Ok((true, payload)) => Some((framing::decode_subscription(&payload), Some(cli))),
+                Ok((false, payload)) => Some((Err(framing::error_from_reply(&payload)), None)),
+                Err(e) => Some((Err(e), None)),
+            }
+        }))
+    }
+
+    pub async fn shutdown(self) -> Result<(), TectonicError> {
+        let mut stream = self.stream.into_inner();
+        if let AsyncClientStream::Tls(_) = stream {
+            // sends close_notify
+            futures::io::AsyncWriteExt::close(&mut stream).await?;
+        }
+        stream.get_ref().shutdown(std::net::Shutdown::Both)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use async_std::task;
+    use futures::StreamExt;
+    use tdb_core::utils::encode_insert_into;
+    use crate::fake_server::{Behaviour, FakeServer, free_port};
+
+    fn up(ts: u64) -> Update {
+        Update { ts, seq: 0, is_bid: true, is_trade: false, price: 1.0, size: 1.0 }
+    }
+
+    #[test]
+    fn should_send_commands_and_inserts() {
+        let port = free_port();
+        let server = FakeServer::start(port, Behaviour::default());
+        task::block_on(async {
+            let mut cli = AsyncTectonicClient::new("127.0.0.1", &port.to_string()).await.unwrap();
+            assert_eq!(cli.use_db("bnc_btc_eth").await, Ok("".to_owned()));
+            assert_eq!(cli.insert(None, &up(1), false).await, Ok(true));
+            assert_eq!(cli.insert(Some("bnc_btc_eth"), &up(2), true).await, Ok(true));
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.inserted().len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(server.commands()[0], b"USE bnc_btc_eth\n".to_vec());
+        assert_eq!(server.inserted(), vec![1, 2]);
+    }
+
+    #[test]
+    fn should_map_error_replies() {
+        let port = free_port();
+        let _server = FakeServer::start(port, Behaviour {
+            refused: vec![("USE", "ERR: No db named `bnc_btc_eth`\n")],
+            ..Default::default()
+        });
+        task::block_on(async {
+            let mut cli = AsyncTectonicClient::new("127.0.0.1", &port.to_string()).await.unwrap();
+            assert_eq!(cli.use_db("bnc_btc_eth").await, Err(TectonicError::DBNotFoundError("bnc_btc_eth".to_owned())));
+            // the connection is still usable
+            assert_eq!(cli.cmd("PING\n").await, Ok("".to_owned()));
+        });
+    }
+
+    #[test]
+    fn should_end_subscription_on_error_reply() {
+        let port = free_port();
+        let _server = FakeServer::start(port, Behaviour {
+            sub_frames: vec![
+                (true, encode_insert_into(Some("bnc_btc_eth"), &up(5)).unwrap()),
+                (false, b"ERR: Permission denied: `research` cannot access `bnc_btc_eth`.\n".to_vec()),
+            ],
+            ..Default::default()
+        });
+        let received: Vec<_> = task::block_on(async {
+            let cli = AsyncTectonicClient::new("127.0.0.1", &port.to_string()).await.unwrap();
+            cli.subscribe("bnc_btc_eth").await.unwrap().collect().await
+        });
+        assert_eq!(received, vec![
+            Ok(up(5)),
+            Err(TectonicError::PermissionDenied("`research` cannot access `bnc_btc_eth`.".to_owned())),
+        ]);
+    }
+
+    #[test]
+    fn should_fail_tls_handshake_with_plain_server() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            use std::io::Write;
+            let (mut stream, _) = listener.accept().unwrap();
+            // a plain reply instead of a server hello
+            stream.write_all(&[1, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+        let ret = task::block_on(AsyncTectonicClient::new_tls("localhost", &port.to_string(), None));
+        assert!(matches!(ret, Err(TectonicError::TlsError(_))));
+
+        let ret = task::block_on(AsyncTectonicClient::new_tls("localhost", &port.to_string(), Some("/nonexistent.pem")));
+        assert!(matches!(ret, Err(TectonicError::TlsError(_))));
+    }
+}
@@ -1,29 +1,41 @@
-use std::net::TcpStream;
-use std::io::{Read, Write, Cursor};
+use std::io::{Read, Write};
+use std::sync::Arc;
 use std::sync::mpsc::{Receiver, channel};
-use byteorder::{BigEndian, ReadBytesExt};
 use bufstream::BufStream;
 use tdb_core::dtf::update::Update;
 use crate::error::TectonicError;
-use tdb_core::dtf::{update::UpdateVecConvert, file_format::decode_buffer};
+use crate::framing;
+use crate::query::{self, Bars, ServerInfo};
+use crate::stream::{ClientStream, tls_config};
+use rustls::ClientConfig;
+use tdb_core::dtf::update::UpdateVecConvert;
+use tdb_core::postprocessing::bbo::Bbo;
+use tdb_core::postprocessing::candle::Candle;
 use tdb_core::postprocessing::orderbook::Orderbook;
 
 pub struct TectonicClient {
-    pub stream: BufStream<TcpStream>,
+    pub stream: BufStream<ClientStream>,
     pub host: String,
     pub port: String,
+    /// kept around so that reconnects negotiate TLS again
+    pub tls: Option<Arc<ClientConfig>>,
 }
 
 impl TectonicClient {
     pub fn new(host: &str, port: &str) -> Result<TectonicClient, TectonicError> {
-        let addr = format!("{}:{}", host, port);
+        Self::with_tls_config(host, port, None)
+    }
+
+    /// Connects over TLS. The server certificate is verified against `ca_file`
+    /// if given, otherwise against the Mozilla root certificates.
+    pub fn new_tls(host: &str, port: &str, ca_file: Option<&str>) -> Result<TectonicClient, TectonicError> {
+        Self::with_tls_config(host, port, Some(tls_config(ca_file)?))
+    }
 
-        info!("Connecting to {}", addr);
+    pub fn with_tls_config(host: &str, port: &str, tls: Option<Arc<ClientConfig>>) -> Result<TectonicClient, TectonicError> {
+        info!("Connecting to {}:{}{}", host, port, if tls.is_some() { " over TLS" } else { "" });
 
-        let stream = match TcpStream::connect(&addr) {
-            Ok(stm) => stm,
-            Err(_) => return Err(TectonicError::ConnectionError)
-        };
+        let stream = ClientStream::connect(host, port, tls.as_ref())?;
 
         let reader_cap = 1024;
         let writer_cap = 1024;
@@ -33,66 +45,88 @@ impl TectonicClient {
             stream,
             host: host.to_owned(),
             port: port.to_owned(),
+            tls,
         })
     }
 
     pub fn reconnect(&mut self) -> Result<(), TectonicError> {
-        let addr = format!("{}:{}", self.host, self.port);
-        info!("Reconnecting to {}", addr);
-        self.stream = match TcpStream::connect(&addr) {
-            Ok(stm) => BufStream::new(stm),
-            Err(_) => return Err(TectonicError::ConnectionError)
-        };
+        info!("Reconnecting to {}:{}", self.host, self.port);
+        let stream = ClientStream::connect(&self.host, &self.port, self.tls.as_ref())?;
+        self.stream = BufStream::new(stream);
         Ok(())
     }
 
-    pub fn cmd(&mut self, command: &str) -> Result<String, TectonicError> {
-        self.stream.write(&(command.len() as u32).to_be_bytes())?;
-        self.stream.write(command.as_bytes())?;
+    pub(crate) fn send(&mut self, command: &[u8]) -> Result<(), TectonicError> {
+        self.stream.write_all(&framing::frame_command(command))?;
         self.stream.flush()?;
+        Ok(())
+    }
+
+    /// read one reply, returns the success flag and the payload
+    pub(crate) fn read_reply(&mut self) -> Result<(bool, Vec<u8>), TectonicError> {
+        let mut header = [0; framing::HEADER_LEN];
+        self.stream.read_exact(&mut header).map_err(|_| TectonicError::ConnectionError)?;
+        let (success, size) = framing::parse_header(&header);
+        let mut buf = vec![0; size as usize];
+        self.stream.read_exact(&mut buf)?;
+        Ok((success, buf))
+    }
+
+    /// payload of a successful reply to `command`
+    fn request(&mut self, command: &str) -> Result<Vec<u8>, TectonicError> {
+        self.send(command.as_bytes())?;
+        match self.read_reply()? {
+            (true, payload) => Ok(payload),
+            (false, payload) => Err(framing::error_from_reply(&payload)),
+        }
+    }
 
-        let success = self.stream.read_u8()
-            .map(|i| i == 0x1)
-            .map_err(|_| TectonicError::ConnectionError)?;
-
-        if command.starts_with("GET")
-            && !command.contains("AS CSV")
-            && !command.contains("AS JSON")
-            && success
-        {
-            let size = self.stream.read_u64::<BigEndian>()?;
-            let mut buf = vec![0_u8; size as usize];
-            self.stream.read_exact(&mut buf)?;
-
-            let mut buf = Cursor::new(buf.as_slice());
-            let v = decode_buffer(&mut buf);
-            Ok(format!("[{}]\n", v.as_json()))
+    pub fn cmd(&mut self, command: &str) -> Result<String, TectonicError> {
+        let payload = self.request(command)?;
+        if framing::returns_dtf(command) {
+            Ok(format!("[{}]\n", framing::decode_updates(&payload).as_json()))
         } else {
-            let size = self.stream.read_u64::<BigEndian>()?;
-            let mut buf = vec![0; size as usize];
-            self.stream.read_exact(&mut buf)?;
-            let res = std::str::from_utf8(&buf).unwrap().to_owned();
-            if success {
-                Ok(res)
-            } else if res.contains("ERR: DB") {
-                let book_name = res.split(" ").nth(2).unwrap();
-                Err(TectonicError::DBNotFoundError(book_name.to_owned()))
-            } else  {
-                Err(TectonicError::ServerError(res))
+            framing::text_from_reply(payload)
+        }
+    }
+
+    /// Send a `GET` command without `AS CSV` or `AS JSON` and decode the updates
+    pub fn get(&mut self, command: &str) -> Result<Vec<Update>, TectonicError> {
+        let payload = self.request(command)?;
+        Ok(framing::decode_updates(&payload))
+    }
+
+    /// Send a `GET ... STREAM` command and call `f` with every chunk until the end marker.
+    /// Chunks are DTF batches unless the command asks for CSV or JSON.
+    pub fn cmd_stream<F: FnMut(&[u8])>(&mut self, command: &str, mut f: F) -> Result<(), TectonicError> {
+        self.send(command.as_bytes())?;
+
+        loop {
+            let (success, buf) = self.read_reply()?;
+            if !success {
+                return Err(framing::error_from_reply(&buf));
             }
+            if buf.is_empty() {
+                return Ok(());
+            }
+            f(&buf);
         }
     }
 
+    /// Like `cmd_stream` for DTF chunks, calls `f` with the decoded updates.
+    pub fn get_stream<F: FnMut(Vec<Update>)>(&mut self, command: &str, mut f: F) -> Result<(), TectonicError> {
+        self.cmd_stream(command, |chunk| f(framing::decode_updates(chunk)))
+    }
+
     unsafe fn cmd_bytes_no_check(&mut self, command: &[u8], discard_result: bool) -> Result<bool, TectonicError> {
-        self.stream.write(&(command.len() as u32).to_be_bytes())?;
-        self.stream.write(command)?;
-        self.stream.flush()?;
+        self.send(command)?;
         if !discard_result {
-            let _ret = self.stream.read_u8().map(|i| i == 0x1)?;
-            let size = self.stream.read_u64::<BigEndian>()?;
+            let mut header = [0; framing::HEADER_LEN];
+            self.stream.read_exact(&mut header)?;
+            let (_ret, size) = framing::parse_header(&header);
             // ignore bytes
             std::io::copy(
-                &mut self.stream.get_ref().take(size as u64),
+                &mut (&mut self.stream).take(size as u64),
                 &mut std::io::sink()
             )?;
         }
@@ -108,35 +142,94 @@ impl TectonicClient {
         self.cmd(&format!("USE {}\n", book_name))
     }
 
+    /// updates of `book_name` between epochs in seconds, in memory and on disk.
+    /// Switches the connection to `book_name` like `use_db`.
+    pub fn get_range(&mut self, book_name: &str, from: u64, to: u64) -> Result<Vec<Update>, TectonicError> {
+        self.use_db(book_name)?;
+        match self.get(&format!("GET ALL FROM {} TO {}\n", from, to)) {
+            Err(TectonicError::NoData) => Ok(vec![]),
+            res => res,
+        }
+    }
+
+    /// number of updates of `book_name`, in memory and on disk.
+    /// Read from `INFO` so the selected book stays the same.
+    pub fn count(&mut self, book_name: &str) -> Result<u64, TectonicError> {
+        self.info()?
+            .dbs
+            .into_iter()
+            .find(|db| db.name == book_name)
+            .map(|db| db.count)
+            .ok_or_else(|| TectonicError::DBNotFoundError(book_name.to_owned()))
+    }
+
+    /// number of updates of every book, in memory and on disk
+    pub fn count_all(&mut self) -> Result<u64, TectonicError> {
+        query::parse_count(&self.cmd("COUNT ALL\n")?)
+    }
+
+    pub fn info(&mut self) -> Result<ServerInfo, TectonicError> {
+        query::parse_info(&self.cmd("INFO\n")?)
+    }
+
+    pub fn exists(&mut self, book_name: &str) -> Result<bool, TectonicError> {
+        match self.cmd(&format!("EXISTS {}\n", book_name)) {
+            Ok(_) => Ok(true),
+            Err(TectonicError::DBNotFoundError(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// candles of `book_name` between epochs in seconds, without trade flow
+    pub fn candles(&mut self, book_name: &str, bars: Bars, from: u64, to: u64) -> Result<Vec<Candle>, TectonicError> {
+        query::parse_candles(&self.cmd(&format!("CANDLES {} {} FROM {} TO {} AS CSV\n", book_name, bars, from, to))?)
+    }
+
     pub fn orderbook_snapshot(&mut self, book_name: &str) -> Result<Orderbook, TectonicError> {
         let ob_json_str = self.cmd(&format!("OB {}\n", book_name))?;
         let ob = serde_json::from_str::<Orderbook>(&ob_json_str).map_err(|_e| TectonicError::JsonError)?;
         Ok(ob)
     }
 
-    pub fn subscribe(mut self, book_name: &str) -> Result<Receiver<Update>, TectonicError> {
-        self.cmd(&format!("SUBSCRIBE {}\n", book_name))?;
+    pub fn subscribe(self, book_name: &str) -> Result<Receiver<Update>, TectonicError> {
+        self.subscribe_with(&format!("SUBSCRIBE {}\n", book_name))
+    }
+
+    /// the book at `ts` arrives first as one update per level, followed by every update since
+    pub fn subscribe_from(self, book_name: &str, ts: u64) -> Result<Receiver<Update>, TectonicError> {
+        self.subscribe_with(&format!("SUBSCRIBE {} FROM {}\n", book_name, ts))
+    }
+
+    /// receives the current top of the book, then every change of it
+    pub fn subscribe_bbo(mut self, book_name: &str) -> Result<Receiver<Bbo>, TectonicError> {
+        self.cmd(&format!("SUBSCRIBE {} BBO\n", book_name))?;
+
+        let (tx, rx) = channel();
+
+        std::thread::spawn(move || {
+            // ends when the connection drops or the receiver hangs up
+            while let Ok((true, buf)) = self.read_reply() {
+                match serde_json::from_slice::<Bbo>(&buf) {
+                    Ok(bbo) => if tx.send(bbo).is_err() { break },
+                    Err(e) => error!("{}", e),
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    fn subscribe_with(mut self, command: &str) -> Result<Receiver<Update>, TectonicError> {
+        self.cmd(command)?;
 
         let (tx, rx) = channel();
 
         std::thread::spawn(move || {
-            loop {
-                let success = self.stream.read_u8()
-                    .map(|i| i == 0x1)
-                    .map_err(|_| TectonicError::ConnectionError).unwrap();
-
-                if !success { break }
-
-                let size = self.stream.read_u64::<BigEndian>().unwrap();
-                let mut buf = vec![0; size as usize];
-                self.stream.read_exact(&mut buf).unwrap();
-                let decoded = tdb_core::utils::decode_insert_into(&buf);
-                match decoded {
-                    Some((Some(up), Some(_book_name))) => tx.send(up).unwrap(),
-                    e => {
-                        println!("{:#?}", e);
-                        ()
-                    }
+            // ends when the connection drops or the receiver hangs up
+            while let Ok((true, buf)) = self.read_reply() {
+                match framing::decode_subscription(&buf) {
+                    Ok(up) => if tx.send(up).is_err() { break },
+                    Err(e) => error!("{}", e),
                 }
             }
         });
@@ -161,6 +254,28 @@ impl TectonicClient {
     }
 
     pub fn shutdown(self) {
-        self.stream.into_inner().unwrap().shutdown(std::net::Shutdown::Both).unwrap()
+        self.stream.into_inner().unwrap().shutdown().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::{Behaviour, FakeServer, free_port};
+
+    #[test]
+    fn should_map_stream_error_replies() {
+        let port = free_port();
+        let _server = FakeServer::start(port, Behaviour {
+            refused: vec![("GET", "ERR: Permission denied: `research` cannot access `bnc_btc_eth`.\n")],
+            ..Default::default()
+        });
+        let mut cli = TectonicClient::new("127.0.0.1", &port.to_string()).unwrap();
+        let mut chunks = 0;
+        assert_eq!(
+            cli.cmd_stream("GET ALL AS CSV STREAM\n", |_| chunks += 1),
+            Err(TectonicError::PermissionDenied("`research` cannot access `bnc_btc_eth`.".to_owned())),
+        );
+        assert_eq!(chunks, 0);
     }
 }
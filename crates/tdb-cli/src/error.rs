@@ -1,13 +1,28 @@
 use std::error;
 use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum TectonicError {
+    /// any other `ERR:` reply
     ServerError(String),
     DBNotFoundError(String),
     ConnectionError,
     SerialError,
     JsonError,
+    TlsError(String),
+    /// the server has auth enabled and the connection did not `AUTH`
+    AuthRequired,
+    InvalidToken,
+    /// the authenticated user lacks the role or book access
+    PermissionDenied(String),
+    /// the server could not parse the command
+    BadRequest(String),
+    /// nothing to return for a `GET`
+    NoData,
+    /// a successful reply that does not have the expected shape
+    UnexpectedResponse(String),
+    /// a local spill file could not be read or written
+    SpillError(String),
 }
 use self::TectonicError::*;
 
@@ -19,6 +34,14 @@ impl error::Error for TectonicError {
             ConnectionError => "Error connecting to tectonicdb",
             SerialError => "Error serializing/deserializing",
             JsonError => "Error serializing/deserializing json",
+            TlsError(ref msg) => msg,
+            AuthRequired => "Authentication required",
+            InvalidToken => "Invalid token",
+            PermissionDenied(ref msg) => msg,
+            BadRequest(ref msg) => msg,
+            NoData => "Not enough items to return",
+            UnexpectedResponse(ref reply) => reply,
+            SpillError(ref msg) => msg,
         }
     }
 }
@@ -31,6 +54,14 @@ impl fmt::Display for TectonicError {
             ConnectionError => write!(f, "ConnectionError"),
             SerialError => write!(f, "SerialError"),
             JsonError => write!(f, "JsonError"),
+            TlsError(ref msg) => write!(f, "TlsError: {}", msg),
+            AuthRequired => write!(f, "AuthRequired"),
+            InvalidToken => write!(f, "InvalidToken"),
+            PermissionDenied(ref msg) => write!(f, "PermissionDenied: {}", msg),
+            BadRequest(ref msg) => write!(f, "BadRequest: {}", msg),
+            NoData => write!(f, "NoData"),
+            UnexpectedResponse(ref reply) => write!(f, "UnexpectedResponse: {}", reply),
+            SpillError(ref msg) => write!(f, "SpillError: {}", msg),
         }
     }
 }
@@ -0,0 +1,123 @@
+//! In-process stand-in for tdb-server used by the client tests
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use byteorder::{BigEndian, ReadBytesExt};
+use tdb_core::utils::decode_insert_into;
+
+/// In-process stand-in for tdb-server. Every command is recorded and
+/// answered with an empty ok reply, `SUBSCRIBE` is followed by
+/// `sub_frames`. `stop` closes the listener and every connection.
+pub(crate) struct FakeServer {
+    commands: Arc<Mutex<Vec<Vec<u8>>>>,
+    streams: Arc<Mutex<Vec<TcpStream>>>,
+    stopped: Arc<AtomicBool>,
+    accept: Option<JoinHandle<()>>,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Behaviour {
+    /// close the connection instead of answering the insert after this many
+    pub max_inserts: Option<usize>,
+    /// commands answered with an `ERR:` reply, by prefix
+    pub refused: Vec<(&'static str, &'static str)>,
+    pub sub_frames: Vec<(bool, Vec<u8>)>,
+}
+
+fn write_frame(stream: &mut TcpStream, success: bool, payload: &[u8]) -> std::io::Result<()> {
+    stream.write_all(&[success as u8])?;
+    stream.write_all(&(payload.len() as u64).to_be_bytes())?;
+    stream.write_all(payload)
+}
+
+fn serve(mut stream: TcpStream, behaviour: Behaviour, commands: Arc<Mutex<Vec<Vec<u8>>>>) -> std::io::Result<()> {
+    let mut inserts = 0;
+    loop {
+        let len = stream.read_u32::<BigEndian>()?;
+        let mut command = vec![0; len as usize];
+        stream.read_exact(&mut command)?;
+        if command.starts_with(tdb_core::RAW_INSERT_PREFIX) {
+            if behaviour.max_inserts == Some(inserts) {
+                return stream.shutdown(Shutdown::Both);
+            }
+            inserts += 1;
+        }
+        let subscribe = command.starts_with(b"SUBSCRIBE");
+        let refused = behaviour.refused.iter().find(|(prefix, _)| command.starts_with(prefix.as_bytes()));
+        commands.lock().unwrap().push(command);
+        if let Some((_, reply)) = refused {
+            write_frame(&mut stream, false, reply.as_bytes())?;
+            continue;
+        }
+        write_frame(&mut stream, true, b"")?;
+        if subscribe {
+            for (success, payload) in &behaviour.sub_frames {
+                write_frame(&mut stream, *success, payload)?;
+            }
+        }
+    }
+}
+
+impl FakeServer {
+    pub(crate) fn start(port: u16, behaviour: Behaviour) -> Self {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let commands = Arc::new(Mutex::new(vec![]));
+        let streams = Arc::new(Mutex::new(vec![]));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let accept = {
+            let (commands, streams, stopped) = (commands.clone(), streams.clone(), stopped.clone());
+            std::thread::spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            stream.set_nonblocking(false).unwrap();
+                            streams.lock().unwrap().push(stream.try_clone().unwrap());
+                            let (behaviour, commands) = (behaviour.clone(), commands.clone());
+                            std::thread::spawn(move || serve(stream, behaviour, commands));
+                        }
+                        Err(_) => std::thread::sleep(Duration::from_millis(1)),
+                    }
+                }
+            })
+        };
+        FakeServer { commands, streams, stopped, accept: Some(accept) }
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.accept.take().unwrap().join().unwrap();
+        for stream in self.streams.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub(crate) fn commands(&self) -> Vec<Vec<u8>> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    /// ts of the inserts received so far
+    pub(crate) fn inserted(&self) -> Vec<u64> {
+        self.commands().iter()
+            .filter(|cmd| cmd.starts_with(tdb_core::RAW_INSERT_PREFIX))
+            .filter_map(|cmd| decode_insert_into(cmd)?.0)
+            .map(|up| up.ts)
+            .collect()
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        if self.accept.is_some() {
+            self.stop();
+        }
+    }
+}
+
+pub(crate) fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
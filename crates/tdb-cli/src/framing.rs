@@ -0,0 +1,117 @@
+//! Wire format shared by the blocking and async clients
+//!
+//! Commands are sent as `[u32 BE length][command]`, every reply is
+//! `[u8 success][u64 BE length][payload]`. Subscriptions keep replying
+//! with one frame per update.
+
+use std::io::Cursor;
+use byteorder::{BigEndian, ByteOrder};
+use tdb_core::dtf::file_format::decode_buffer;
+use tdb_core::dtf::update::Update;
+use tdb_core::utils::decode_insert_into;
+use crate::error::TectonicError;
+
+/// length of the success flag and payload length of a reply
+pub const HEADER_LEN: usize = 9;
+
+/// length prefixed command
+pub fn frame_command(command: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + command.len());
+    buf.extend_from_slice(&(command.len() as u32).to_be_bytes());
+    buf.extend_from_slice(command);
+    buf
+}
+
+/// success flag and payload length of a reply
+pub fn parse_header(header: &[u8; HEADER_LEN]) -> (bool, u64) {
+    (header[0] == 0x1, BigEndian::read_u64(&header[1..]))
+}
+
+/// whether the server answers `command` with DTF encoded updates
+pub fn returns_dtf(command: &str) -> bool {
+    command.starts_with("GET") && !command.contains("AS CSV") && !command.contains("AS JSON")
+}
+
+/// error of an unsuccessful reply, the payload reads `ERR: [message]`
+pub fn error_from_reply(payload: &[u8]) -> TectonicError {
+    let res = String::from_utf8_lossy(payload).into_owned();
+    let msg = res.trim().trim_start_matches("ERR: ");
+    if let Some(book_name) = msg.strip_prefix("No db named ") {
+        TectonicError::DBNotFoundError(book_name.trim_matches('`').to_owned())
+    } else if let Some(book_name) = msg.strip_prefix("DB ").and_then(|m| m.strip_suffix(" not found.")) {
+        TectonicError::DBNotFoundError(book_name.to_owned())
+    } else if msg.starts_with("Authentication required") {
+        TectonicError::AuthRequired
+    } else if msg == "Invalid token." {
+        TectonicError::InvalidToken
+    } else if let Some(reason) = msg.strip_prefix("Permission denied: ") {
+        TectonicError::PermissionDenied(reason.to_owned())
+    } else if msg == "Bad format." || msg == "Unknown command." || msg == "Unable to parse line" {
+        TectonicError::BadRequest(msg.to_owned())
+    } else if msg == "Not enough items to return" {
+        TectonicError::NoData
+    } else {
+        TectonicError::ServerError(res)
+    }
+}
+
+/// utf8 payload of a successful reply
+pub fn text_from_reply(payload: Vec<u8>) -> Result<String, TectonicError> {
+    String::from_utf8(payload).map_err(|_| TectonicError::SerialError)
+}
+
+/// updates of a DTF encoded reply
+pub fn decode_updates(payload: &[u8]) -> Vec<Update> {
+    decode_buffer(&mut Cursor::new(payload))
+}
+
+/// update of a subscription frame
+pub fn decode_subscription(payload: &[u8]) -> Result<Update, TectonicError> {
+    match decode_insert_into(payload) {
+        Some((Some(up), _)) => Ok(up),
+        _ => Err(TectonicError::SerialError),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_map_server_errors() {
+        use TectonicError::*;
+        let table = [
+            ("ERR: No db named `bnc_btc_eth`\n", DBNotFoundError("bnc_btc_eth".to_owned())),
+            ("ERR: DB bnc_btc_eth not found.\n", DBNotFoundError("bnc_btc_eth".to_owned())),
+            ("ERR: Authentication required. Use `AUTH [token]`.\n", AuthRequired),
+            ("ERR: Invalid token.\n", InvalidToken),
+            (
+                "ERR: Permission denied: `research` requires writer role.\n",
+                PermissionDenied("`research` requires writer role.".to_owned()),
+            ),
+            (
+                "ERR: Permission denied: `research` cannot access `default`.\n",
+                PermissionDenied("`research` cannot access `default`.".to_owned()),
+            ),
+            ("ERR: Bad format.\n", BadRequest("Bad format.".to_owned())),
+            ("ERR: Unknown command.\n", BadRequest("Unknown command.".to_owned())),
+            ("ERR: Unable to parse line\n", BadRequest("Unable to parse line".to_owned())),
+            ("ERR: Not enough items to return\n", NoData),
+            ("ERR: Unable to get count\n", ServerError("ERR: Unable to get count\n".to_owned())),
+        ];
+        for (reply, expected) in table.iter() {
+            assert_eq!(error_from_reply(reply.as_bytes()), *expected, "{:?}", reply);
+        }
+    }
+
+    #[test]
+    fn should_frame_commands_and_headers() {
+        assert_eq!(frame_command(b"PING"), vec![0, 0, 0, 4, b'P', b'I', b'N', b'G']);
+        assert_eq!(parse_header(&[1, 0, 0, 0, 0, 0, 0, 1, 2]), (true, 258));
+        assert_eq!(parse_header(&[0, 0, 0, 0, 0, 0, 0, 0, 0]), (false, 0));
+        assert!(returns_dtf("GET ALL FROM 0 TO 1\n"));
+        assert!(!returns_dtf("GET ALL AS CSV\n"));
+        assert!(!returns_dtf("GET ALL AS JSON\n"));
+        assert!(!returns_dtf("COUNT\n"));
+    }
+}
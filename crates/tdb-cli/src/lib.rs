@@ -3,9 +3,18 @@ extern crate serde;
 extern crate serde_json;
 extern crate tdb_core;
 #[macro_use] extern crate log;
+#[macro_use] extern crate serde_derive;
 
 pub mod error;
 pub mod client;
+pub mod stream;
+pub mod framing;
+pub mod query;
+pub mod resilient;
+#[cfg(feature = "async")]
+pub mod async_client;
+#[cfg(test)]
+mod fake_server;
 
 use std::env;
 use crate::client::TectonicClient;
@@ -20,11 +29,13 @@ fn key_or_default(key: &str, default: &str) -> String {
     }
 }
 
-fn get_tectonic_conf_from_env() -> (String, String) {
+fn get_tectonic_conf_from_env() -> (String, String, bool, Option<String>) {
     let tectonic_hostname: String = key_or_default("TDB_HOSTNAME", "localhost");
     let tectonic_port: String     = key_or_default("TDB_PORT", "9001");
+    let tectonic_tls: bool        = key_or_default("TDB_TLS", "false") == "true";
+    let tectonic_ca_file          = env::var("TDB_TLS_CA_FILE").ok();
 
-    (tectonic_hostname, tectonic_port)
+    (tectonic_hostname, tectonic_port, tectonic_tls, tectonic_ca_file)
 }
 
 /// Creates a new connection to TectonicDB, using configuration values from environment
@@ -32,15 +43,22 @@ fn get_tectonic_conf_from_env() -> (String, String) {
 ///
 /// "TDB_HOSTNAME", "localhost");
 /// "TDB_PORT", "9001");
+/// "TDB_TLS", "false");
+/// "TDB_TLS_CA_FILE", system roots);
 ///
 pub fn client_from_env() -> TectonicClient {
-    let (tectonic_hostname, tectonic_port) = get_tectonic_conf_from_env();
-    match TectonicClient::new(&tectonic_hostname, &tectonic_port) {
+    let (tectonic_hostname, tectonic_port, tls, ca_file) = get_tectonic_conf_from_env();
+    let cli = if tls {
+        TectonicClient::new_tls(&tectonic_hostname, &tectonic_port, ca_file.as_deref())
+    } else {
+        TectonicClient::new(&tectonic_hostname, &tectonic_port)
+    };
+    match cli {
         Ok(cli) => cli,
         Err(TectonicError::ConnectionError) => {
             panic!("DB cannot be connected!");
         },
-        _ => unreachable!(),
+        Err(e) => panic!("{}", e),
     }
 }
 
@@ -0,0 +1,204 @@
+//! Typed replies of the query commands
+
+use std::fmt;
+use tdb_core::postprocessing::candle::{Candle, TradeFlow};
+use crate::error::TectonicError;
+
+/// Reply of `INFO`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ServerInfo {
+    /// server wide numbers
+    pub meta: ServerMeta,
+    /// one entry per orderbook
+    pub dbs: Vec<BookInfo>,
+}
+
+/// Server wide part of `INFO`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ServerMeta {
+    /// connected clients
+    pub clis: usize,
+    /// subscriptions over all books
+    pub subs: usize,
+    /// server time in seconds
+    pub ts: u64,
+    pub autoflush_enabled: bool,
+    pub autoflush_interval: u64,
+    pub dtf_folder: String,
+    /// updates in memory over all books
+    pub total_in_memory_count: u64,
+    /// updates in memory and on disk over all books
+    pub total_count: u64,
+    pub memory_bytes: u64,
+    /// 0 when unlimited
+    pub memory_limit_bytes: u64,
+    /// books evicted to stay under the memory limit
+    pub evictions: u64,
+}
+
+/// Orderbook part of `INFO`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BookInfo {
+    pub name: String,
+    /// updates in memory
+    pub in_memory: u64,
+    /// updates in memory and on disk
+    pub count: u64,
+    /// updates loaded from disk
+    pub loaded: u64,
+    pub memory_bytes: u64,
+}
+
+/// How `CANDLES` samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bars {
+    /// seconds per candle
+    Time(u64),
+    /// trades per candle
+    Ticks(u32),
+    /// traded size per candle
+    Volume(f32),
+    /// traded price * size per candle
+    Dollars(f32),
+}
+
+impl fmt::Display for Bars {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bars::Time(secs) => write!(f, "{}s", secs),
+            Bars::Ticks(n) => write!(f, "TICKS {}", n),
+            Bars::Volume(v) => write!(f, "VOLUME {}", v),
+            Bars::Dollars(d) => write!(f, "DOLLARS {}", d),
+        }
+    }
+}
+
+fn unexpected(reply: &str) -> TectonicError {
+    TectonicError::UnexpectedResponse(reply.trim().to_owned())
+}
+
+/// reply of `INFO`
+pub fn parse_info(reply: &str) -> Result<ServerInfo, TectonicError> {
+    serde_json::from_str(reply).map_err(|_| unexpected(reply))
+}
+
+/// reply of `COUNT`
+pub fn parse_count(reply: &str) -> Result<u64, TectonicError> {
+    reply.trim().parse().map_err(|_| unexpected(reply))
+}
+
+/// csv reply of `CANDLES`, rows are start,end,open,high,low,close,volume
+pub fn parse_candles(reply: &str) -> Result<Vec<Candle>, TectonicError> {
+    reply.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let cols: Vec<&str> = line.split(',').collect();
+            let int = |i: usize| cols.get(i).and_then(|c| c.parse::<u64>().ok()).ok_or_else(|| unexpected(line));
+            let float = |i: usize| cols.get(i).and_then(|c| c.parse::<f32>().ok()).ok_or_else(|| unexpected(line));
+            Ok(Candle {
+                start: int(0)?,
+                end: int(1)?,
+                open: float(2)?,
+                high: float(3)?,
+                low: float(4)?,
+                close: float(5)?,
+                volume: float(6)?,
+                flow: TradeFlow::default(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = r#"{
+  "meta": {
+    "clis": 1,
+    "subs": 0,
+    "ts": 1792368134,
+    "autoflush_enabled": false,
+    "autoflush_interval": 0,
+    "dtf_folder": "db",
+    "total_in_memory_count": 1,
+    "total_count": 4,
+    "memory_bytes": 1572864,
+    "memory_limit_bytes": 0,
+    "evictions": 0
+  },
+  "dbs": [{
+    "name": "default",
+    "in_memory": 1,
+    "count": 4,
+    "loaded": 3,
+    "memory_bytes": 1572864
+  }]
+}
+"#;
+
+    #[test]
+    fn should_parse_info() {
+        let info = parse_info(INFO).unwrap();
+        assert_eq!(info.meta.clis, 1);
+        assert_eq!(info.meta.dtf_folder, "db");
+        assert_eq!(info.meta.total_count, 4);
+        assert_eq!(info.dbs, vec![BookInfo {
+            name: "default".to_owned(),
+            in_memory: 1,
+            count: 4,
+            loaded: 3,
+            memory_bytes: 1572864,
+        }]);
+        assert!(parse_info("PONG").is_err());
+    }
+
+    #[test]
+    fn should_parse_count() {
+        let table = [
+            ("0", Some(0)),
+            ("123", Some(123)),
+            ("123\n", Some(123)),
+            ("", None),
+            ("-1", None),
+            ("SWITCHED TO orderbook `default`.", None),
+        ];
+        for (reply, expected) in table.iter() {
+            assert_eq!(parse_count(reply).ok(), *expected, "{:?}", reply);
+        }
+    }
+
+    #[test]
+    fn should_parse_candles() {
+        let candle = |start, end, open, high, low, close, volume| Candle {
+            start, end, open, high, low, close, volume, flow: TradeFlow::default(),
+        };
+        let table = [
+            ("", Some(vec![])),
+            ("\n", Some(vec![])),
+            (
+                "1513749530000,1513749531000,1,3,1,3,3\n1513749532000,1513749533000,9,9,2,2,3\n",
+                Some(vec![
+                    candle(1513749530000, 1513749531000, 1., 3., 1., 3., 3.),
+                    candle(1513749532000, 1513749533000, 9., 9., 2., 2., 3.),
+                ]),
+            ),
+            ("1513749480000,1513749533000,0.5,3,1,2,4.25", Some(vec![
+                candle(1513749480000, 1513749533000, 0.5, 3., 1., 2., 4.25),
+            ])),
+            ("1513749530000,1513749531000,1,3,1,3\n", None),
+            ("start,end,open,high,low,close,volume\n", None),
+        ];
+        for (reply, expected) in table.iter() {
+            assert_eq!(parse_candles(reply).ok(), *expected, "{:?}", reply);
+        }
+    }
+
+    #[test]
+    fn should_format_bars() {
+        assert_eq!(Bars::Time(60).to_string(), "60s");
+        assert_eq!(Bars::Ticks(100).to_string(), "TICKS 100");
+        assert_eq!(Bars::Volume(1.5).to_string(), "VOLUME 1.5");
+        assert_eq!(Bars::Dollars(1000.).to_string(), "DOLLARS 1000");
+    }
+}
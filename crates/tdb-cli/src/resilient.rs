@@ -0,0 +1,784 @@
+//! Client that survives server restarts
+//!
+//! `ResilientClient` reconnects with exponential backoff. After reconnecting
+//! it sends `AUTH`, re-creates the books it created and re-selects the book
+//! chosen with `use_db`. Inserts made while disconnected are buffered, up to a
+//! bound, and replayed in order once the server is back. A full buffer is
+//! spilled to DTF files in a local folder if one is set, otherwise, or when
+//! spilling fails, the oldest updates are dropped. Spilled files left over by
+//! an earlier process are replayed too, unreadable ones are renamed to
+//! `.dtf.bad`.
+//!
+//! Delivery is at least once: an insert sent just before the connection broke
+//! may be replayed although the server already stored it.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, channel};
+use std::time::{Duration, Instant};
+use rustls::ClientConfig;
+use tdb_core::dtf::file_format::{decode, encode, read_meta, SYMBOL_LEN};
+use tdb_core::dtf::update::Update;
+use crate::client::TectonicClient;
+use crate::error::TectonicError;
+use crate::framing;
+
+/// Where and how to connect, shared with subscription threads
+#[derive(Clone)]
+struct Endpoint {
+    host: String,
+    port: String,
+    tls: Option<Arc<ClientConfig>>,
+    token: Option<String>,
+}
+
+impl Endpoint {
+    /// connect and authenticate
+    fn connect(&self) -> Result<TectonicClient, TectonicError> {
+        let mut cli = TectonicClient::with_tls_config(&self.host, &self.port, self.tls.clone())?;
+        if let Some(token) = &self.token {
+            cli.cmd(&format!("AUTH {}\n", token))?;
+        }
+        Ok(cli)
+    }
+}
+
+/// Exponential backoff between connection attempts
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    /// wait after the first failed attempt
+    pub initial: Duration,
+    /// upper bound of the wait
+    pub max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff { initial, max, current: initial }
+    }
+
+    /// wait before the next attempt, doubles on every call
+    pub fn next_wait(&mut self) -> Duration {
+        let wait = self.current;
+        self.current = (self.current * 2).min(self.max);
+        wait
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(30))
+    }
+}
+
+/// whether `e` means the connection is gone rather than a refused command
+fn is_disconnect(e: &TectonicError) -> bool {
+    matches!(e, TectonicError::ConnectionError | TectonicError::SerialError | TectonicError::TlsError(_))
+}
+
+/// send a raw insert and check the reply
+fn send_insert(cli: &mut TectonicClient, book_name: &str, up: &Update) -> Result<(), TectonicError> {
+    cli.send(&tdb_core::utils::encode_insert_into(Some(book_name), up)?)?;
+    match cli.read_reply()? {
+        (true, _) => Ok(()),
+        (false, payload) => Err(framing::error_from_reply(&payload)),
+    }
+}
+
+/// Updates of a subscription, the subscription thread stops once this is dropped
+pub struct Subscription {
+    rx: Receiver<Update>,
+    alive: Arc<AtomicBool>,
+}
+
+impl Deref for Subscription {
+    type Target = Receiver<Update>;
+    fn deref(&self) -> &Receiver<Update> {
+        &self.rx
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Update;
+    /// blocks until the next update, `None` once the subscription thread stopped
+    fn next(&mut self) -> Option<Update> {
+        self.rx.recv().ok()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::Relaxed);
+    }
+}
+
+pub struct ResilientClient {
+    endpoint: Endpoint,
+    cli: Option<TectonicClient>,
+    backoff: Backoff,
+    /// no connection attempt before then
+    next_attempt: Instant,
+    /// book selected with `use_db`
+    book: Option<String>,
+    /// books created with `create_db`
+    created: Vec<String>,
+    /// inserts waiting for a connection, oldest first
+    buffer: VecDeque<(String, Update)>,
+    capacity: usize,
+    spill_dir: Option<PathBuf>,
+    /// spilled files waiting for a connection, oldest first
+    spilled: VecDeque<PathBuf>,
+    next_spill: u64,
+    dropped: u64,
+}
+
+impl ResilientClient {
+    /// Does not fail when the server is down, the first insert connects.
+    pub fn new(host: &str, port: &str) -> Self {
+        Self::with_tls_config(host, port, None)
+    }
+
+    pub fn with_tls_config(host: &str, port: &str, tls: Option<Arc<ClientConfig>>) -> Self {
+        ResilientClient {
+            endpoint: Endpoint {
+                host: host.to_owned(),
+                port: port.to_owned(),
+                tls,
+                token: None,
+            },
+            cli: None,
+            backoff: Backoff::default(),
+            next_attempt: Instant::now(),
+            book: None,
+            created: vec![],
+            buffer: VecDeque::new(),
+            capacity: 100_000,
+            spill_dir: None,
+            spilled: VecDeque::new(),
+            next_spill: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Send `AUTH [token]` on every connection
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.endpoint.token = Some(token.to_owned());
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Keep at most `capacity` inserts in memory while disconnected
+    pub fn with_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Spill a full buffer to DTF files in `dir` instead of dropping updates.
+    /// Files already in `dir` are replayed first.
+    pub fn with_spill_dir<P: AsRef<Path>>(mut self, dir: P) -> Result<Self, TectonicError> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut files: Vec<(u64, PathBuf)> = std::fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter_map(|path| {
+                let seq = path.file_stem()?.to_str()?.parse().ok()?;
+                if path.extension()? == "dtf" { Some((seq, path)) } else { None }
+            })
+            .collect();
+        files.sort();
+        if let Some((seq, _)) = files.last() {
+            self.next_spill = seq + 1;
+        }
+        if !files.is_empty() {
+            info!("Found {} spilled files in {}", files.len(), dir.display());
+        }
+        self.spilled = files.into_iter().map(|(_, path)| path).collect();
+        self.spill_dir = Some(dir);
+        Ok(self)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.cli.is_some()
+    }
+
+    /// inserts waiting in memory
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// spilled files waiting to be replayed
+    pub fn spilled_files(&self) -> usize {
+        self.spilled.len()
+    }
+
+    /// inserts dropped because the buffer was full and could not be spilled
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Connect unless connected already or still backing off, then replay
+    /// buffered inserts. Returns whether the client is connected.
+    pub fn try_connect(&mut self) -> bool {
+        if self.cli.is_none() && Instant::now() >= self.next_attempt {
+            match self.connect() {
+                Ok(cli) => {
+                    info!("Connected to {}:{}", self.endpoint.host, self.endpoint.port);
+                    self.backoff.reset();
+                    self.cli = Some(cli);
+                }
+                Err(e) => {
+                    let wait = self.backoff.next_wait();
+                    warn!("Unable to connect to {}:{} ({}), retrying in {:?}", self.endpoint.host, self.endpoint.port, e, wait);
+                    self.next_attempt = Instant::now() + wait;
+                }
+            }
+        }
+        self.cli.is_some() && self.replay()
+    }
+
+    /// Block until connected, sleeping between attempts
+    pub fn wait_connected(&mut self) {
+        while !self.try_connect() {
+            std::thread::sleep(self.next_attempt.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    /// connect and restore the connection state
+    fn connect(&mut self) -> Result<TectonicClient, TectonicError> {
+        let mut cli = self.endpoint.connect()?;
+        for book_name in &self.created {
+            match cli.cmd(&format!("CREATE {}\n", book_name)) {
+                Err(e) if is_disconnect(&e) => return Err(e),
+                // exists already
+                _ => (),
+            }
+        }
+        if let Some(book_name) = &self.book {
+            match cli.use_db(book_name) {
+                Ok(_) => (),
+                Err(e) if is_disconnect(&e) => return Err(e),
+                // e.g. dropped in the meantime, inserts without a book go to `default`
+                Err(e) => {
+                    warn!("Unable to select {} again: {}", book_name, e);
+                    self.book = None;
+                }
+            }
+        }
+        Ok(cli)
+    }
+
+    fn disconnect(&mut self, e: &TectonicError) {
+        warn!("Lost connection to {}:{} ({})", self.endpoint.host, self.endpoint.port, e);
+        self.cli = None;
+        self.next_attempt = Instant::now();
+    }
+
+    /// replay spilled files then the buffer, returns false when the connection broke
+    fn replay(&mut self) -> bool {
+        while let Some(path) = self.spilled.front().cloned() {
+            if let Err(e) = self.replay_file(&path) {
+                if is_disconnect(&e) {
+                    self.disconnect(&e);
+                    return false;
+                }
+                // e.g. a file torn by a crash while spilling, retrying cannot help
+                let bad = path.with_extension("dtf.bad");
+                error!("Moving unreadable spilled file {} to {}: {}", path.display(), bad.display(), e);
+                if let Err(e) = std::fs::rename(&path, &bad) {
+                    error!("Unable to move {}: {}", path.display(), e);
+                }
+            }
+            self.spilled.pop_front();
+        }
+        while let Some((book_name, up)) = self.buffer.front() {
+            let cli = self.cli.as_mut().unwrap();
+            match send_insert(cli, book_name, up) {
+                Ok(()) => (),
+                Err(e) if is_disconnect(&e) => {
+                    self.disconnect(&e);
+                    return false;
+                }
+                Err(e) => error!("Dropping buffered insert into {}: {}", book_name, e),
+            }
+            self.buffer.pop_front();
+        }
+        true
+    }
+
+    /// insert every update of a spilled file then remove it, on a broken
+    /// connection the file is rewritten with the updates not sent yet.
+    /// Local file errors are `SpillError`s.
+    fn replay_file(&mut self, path: &Path) -> Result<(), TectonicError> {
+        let spill_error = |e: std::io::Error| TectonicError::SpillError(format!("{}: {}", path.display(), e));
+        let fname = path.to_str().ok_or_else(|| TectonicError::SpillError(format!("{}: not utf8", path.display())))?;
+        let symbol = read_meta(fname).map_err(spill_error)?.symbol;
+        let ups = decode(fname, None).map_err(spill_error)?;
+        info!("Replaying {} updates of {} from {}", ups.len(), symbol, fname);
+        let cli = self.cli.as_mut().unwrap();
+        for (i, up) in ups.iter().enumerate() {
+            match send_insert(cli, &symbol, up) {
+                Ok(()) => (),
+                Err(e) if is_disconnect(&e) => {
+                    if let Err(write_err) = encode(fname, &symbol, &ups[i..]) {
+                        error!("Unable to rewrite {}, its updates may be replayed twice: {}", fname, write_err);
+                    }
+                    return Err(e);
+                }
+                Err(e) => error!("Dropping spilled insert into {}: {}", symbol, e),
+            }
+        }
+        if let Err(e) = std::fs::remove_file(path) {
+            error!("Unable to remove replayed file {}, its updates may be replayed twice: {}", fname, e);
+        }
+        Ok(())
+    }
+
+    /// write the buffer to one file per book, the updates of a book leave
+    /// the buffer only once its file is written
+    fn spill(&mut self) -> Result<(), TectonicError> {
+        let dir = match &self.spill_dir {
+            Some(dir) => dir.clone(),
+            None => return Ok(()),
+        };
+        let books: BTreeSet<String> = self.buffer.iter().map(|(book_name, _)| book_name.clone()).collect();
+        for book_name in books {
+            let ups: Vec<Update> = self.buffer.iter()
+                .filter(|(b, _)| *b == book_name)
+                .map(|(_, up)| *up)
+                .collect();
+            let path = dir.join(format!("{:010}.dtf", self.next_spill));
+            let fname = path.to_str().ok_or_else(|| TectonicError::SpillError(format!("{}: not utf8", path.display())))?;
+            if let Err(e) = encode(fname, &book_name, &ups) {
+                let _ = std::fs::remove_file(&path);
+                return Err(TectonicError::SpillError(format!("{}: {}", fname, e)));
+            }
+            info!("Spilled {} updates of {} to {}", ups.len(), book_name, fname);
+            self.next_spill += 1;
+            self.buffer.retain(|(b, _)| *b != book_name);
+            self.spilled.push_back(path);
+        }
+        Ok(())
+    }
+
+    /// Insert into `book_name`, or the book selected with `use_db`. While
+    /// disconnected the update is buffered and `Ok` returned, errors are
+    /// replies of a connected server. With a spill folder, book names longer
+    /// than a DTF symbol are rejected with `SpillError`. When spilling fails
+    /// the oldest updates are dropped.
+    pub fn insert(&mut self, book_name: Option<&str>, update: &Update) -> Result<(), TectonicError> {
+        let book_name = book_name
+            .or(self.book.as_deref())
+            .unwrap_or("default")
+            .to_owned();
+        if self.spill_dir.is_some() && book_name.len() > SYMBOL_LEN {
+            return Err(TectonicError::SpillError(format!(
+                "book name `{}` is longer than {} bytes and cannot be spilled", book_name, SYMBOL_LEN)));
+        }
+        if self.try_connect() {
+            match send_insert(self.cli.as_mut().unwrap(), &book_name, update) {
+                Err(e) if is_disconnect(&e) => self.disconnect(&e),
+                res => return res,
+            }
+        }
+        if self.buffer.len() >= self.capacity && self.spill_dir.is_some() {
+            if let Err(e) = self.spill() {
+                error!("Unable to spill the insert buffer: {}", e);
+            }
+        }
+        if self.buffer.len() >= self.capacity {
+            self.buffer.pop_front();
+            self.dropped += 1;
+            if self.dropped % 10_000 == 1 {
+                warn!("Insert buffer full, {} updates dropped so far", self.dropped);
+            }
+        }
+        self.buffer.push_back((book_name, *update));
+        Ok(())
+    }
+
+    /// Create `book_name`, it is created again after every reconnect
+    pub fn create_db(&mut self, book_name: &str) -> Result<String, TectonicError> {
+        if !self.created.iter().any(|b| b == book_name) {
+            self.created.push(book_name.to_owned());
+        }
+        self.cmd(&format!("CREATE {}\n", book_name))
+    }
+
+    /// Select `book_name`, it is selected again after every reconnect
+    pub fn use_db(&mut self, book_name: &str) -> Result<String, TectonicError> {
+        let res = self.cmd(&format!("USE {}\n", book_name))?;
+        self.book = Some(book_name.to_owned());
+        Ok(res)
+    }
+
+    /// Send a command, fails with `ConnectionError` while disconnected
+    pub fn cmd(&mut self, command: &str) -> Result<String, TectonicError> {
+        if !self.try_connect() {
+            return Err(TectonicError::ConnectionError);
+        }
+        let res = self.cli.as_mut().unwrap().cmd(command);
+        if let Err(e) = &res {
+            if is_disconnect(e) {
+                self.disconnect(e);
+            }
+        }
+        res
+    }
+
+    /// Updates of `book_name`, on its own connection. After a reconnect the
+    /// subscription resumes with `SUBSCRIBE [db] FROM [last ts]`, so the book at
+    /// that time arrives again before the updates missed in between. The
+    /// subscription ends on an `ERR:` reply.
+    pub fn subscribe(&self, book_name: &str) -> Subscription {
+        let endpoint = self.endpoint.clone();
+        let mut backoff = self.backoff;
+        let book_name = book_name.to_owned();
+        let (tx, rx) = channel();
+        let alive = Arc::new(AtomicBool::new(true));
+        let subscribed = alive.clone();
+
+        std::thread::spawn(move || {
+            let mut last_ts = None;
+            while subscribed.load(Ordering::Relaxed) {
+                let command = match last_ts {
+                    None => format!("SUBSCRIBE {}\n", book_name),
+                    Some(ts) => format!("SUBSCRIBE {} FROM {}\n", book_name, ts),
+                };
+                let mut cli = match endpoint.connect().and_then(|mut cli| cli.cmd(&command).map(|_| cli)) {
+                    Ok(cli) => cli,
+                    Err(e) if is_disconnect(&e) => {
+                        let wait = backoff.next_wait();
+                        warn!("Unable to subscribe to {} ({}), retrying in {:?}", book_name, e, wait);
+                        std::thread::sleep(wait);
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Unable to subscribe to {}: {}", book_name, e);
+                        return;
+                    }
+                };
+                info!("Subscribed to {}", book_name);
+                // ends when the connection drops
+                loop {
+                    let buf = match cli.read_reply() {
+                        Ok((true, buf)) => buf,
+                        Ok((false, payload)) => {
+                            error!("Subscription to {} ended: {}", book_name, framing::error_from_reply(&payload));
+                            return;
+                        }
+                        Err(_) => break,
+                    };
+                    match framing::decode_subscription(&buf) {
+                        Ok(up) => {
+                            // only a subscription that delivered resets the
+                            // backoff, a server dropping every connection
+                            // right away is retried with growing waits
+                            backoff.reset();
+                            last_ts = Some(up.ts);
+                            if tx.send(up).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => error!("{}", e),
+                    }
+                }
+                let wait = backoff.next_wait();
+                warn!("Lost subscription to {}, resubscribing in {:?}", book_name, wait);
+                std::thread::sleep(wait);
+            }
+        });
+
+        Subscription { rx, alive }
+    }
+}
+
+impl Drop for ResilientClient {
+    /// keep inserts that never made it to the server
+    fn drop(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        if self.spill_dir.is_some() {
+            if let Err(e) = self.spill() {
+                error!("Unable to spill {} buffered inserts: {}", self.buffer.len(), e);
+            }
+        } else {
+            warn!("Dropping {} buffered inserts", self.buffer.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::{Behaviour, FakeServer, free_port};
+    use tdb_core::utils::{decode_insert_into, encode_insert_into};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn client(port: u16) -> ResilientClient {
+        ResilientClient::new("127.0.0.1", &port.to_string())
+            .with_backoff(Backoff::new(Duration::from_millis(1), Duration::from_millis(10)))
+    }
+
+    fn up(ts: u64) -> Update {
+        Update { ts, seq: ts as u32, is_bid: true, is_trade: false, price: 1.0 + ts as f32, size: 1.0 }
+    }
+
+    fn spill_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tdb-resilient-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        let waits: Vec<u64> = (0..5).map(|_| backoff.next_wait().as_millis() as u64).collect();
+        assert_eq!(waits, vec![100, 200, 400, 500, 500]);
+        backoff.reset();
+        assert_eq!(backoff.next_wait(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn should_drop_oldest_without_spill_dir() {
+        let port = free_port();
+        let mut cli = client(port).with_buffer_capacity(3);
+        for ts in 0..5 {
+            cli.insert(Some("bnc_btc_eth"), &up(ts)).unwrap();
+        }
+        assert!(!cli.is_connected());
+        assert_eq!(cli.buffered(), 3);
+        assert_eq!(cli.dropped(), 2);
+
+        let server = FakeServer::start(port, Behaviour::default());
+        cli.wait_connected();
+        assert_eq!(cli.buffered(), 0);
+        assert_eq!(server.inserted(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn should_spill_and_replay_in_order() {
+        let port = free_port();
+        let dir = spill_dir("spill");
+        {
+            let mut cli = client(port).with_buffer_capacity(2).with_spill_dir(&dir).unwrap();
+            for ts in 0..5 {
+                cli.insert(Some("bnc_btc_eth"), &up(ts)).unwrap();
+            }
+            assert_eq!(cli.spilled_files(), 2);
+            assert_eq!(cli.buffered(), 1);
+            assert_eq!(cli.dropped(), 0);
+            // dropping spills the rest
+        }
+
+        let mut cli = client(port).with_spill_dir(&dir).unwrap();
+        assert_eq!(cli.spilled_files(), 3);
+        let server = FakeServer::start(port, Behaviour::default());
+        cli.wait_connected();
+        assert_eq!(cli.spilled_files(), 0);
+        assert_eq!(server.inserted(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_keep_updates_that_could_not_be_spilled() {
+        let port = free_port();
+        let dir = spill_dir("unwritable");
+        let mut cli = client(port).with_buffer_capacity(2).with_spill_dir(&dir).unwrap();
+        assert_eq!(
+            cli.insert(Some("a_book_name_longer_than_20"), &up(0)),
+            Err(TectonicError::SpillError("book name `a_book_name_longer_than_20` is longer than 20 bytes and cannot be spilled".to_owned())),
+        );
+        cli.insert(Some("bnc_btc_eth"), &up(1)).unwrap();
+        cli.insert(Some("bnc_xrp_eth"), &up(2)).unwrap();
+
+        // spilling fails, the oldest update makes room
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::write(&dir, b"").unwrap();
+        cli.insert(Some("bnc_btc_eth"), &up(3)).unwrap();
+        assert_eq!(cli.spilled_files(), 0);
+        assert_eq!(cli.buffered(), 2);
+        assert_eq!(cli.dropped(), 1);
+
+        std::fs::remove_file(&dir).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        cli.insert(Some("bnc_btc_eth"), &up(4)).unwrap();
+        assert_eq!(cli.spilled_files(), 2);
+        assert_eq!(cli.buffered(), 1);
+        assert_eq!(cli.dropped(), 1);
+
+        let server = FakeServer::start(port, Behaviour::default());
+        cli.wait_connected();
+        // one file per book, in book name order
+        assert_eq!(server.inserted(), vec![3, 2, 4]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_rewrite_partly_replayed_spill_file() {
+        let port = free_port();
+        let dir = spill_dir("rewrite");
+        std::fs::create_dir_all(&dir).unwrap();
+        let fname = dir.join("0000000000.dtf");
+        let ups: Vec<Update> = (0..5).map(up).collect();
+        encode(fname.to_str().unwrap(), "bnc_btc_eth", &ups).unwrap();
+
+        let mut cli = client(port).with_spill_dir(&dir).unwrap();
+        let mut server = FakeServer::start(port, Behaviour { max_inserts: Some(2), ..Default::default() });
+        assert!(!cli.try_connect());
+        assert_eq!(server.inserted(), vec![0, 1]);
+        assert_eq!(cli.spilled_files(), 1);
+        assert_eq!(decode(fname.to_str().unwrap(), None).unwrap(), ups[2..].to_vec());
+        server.stop();
+
+        let server = FakeServer::start(port, Behaviour::default());
+        cli.wait_connected();
+        assert_eq!(server.inserted(), vec![2, 3, 4]);
+        assert!(!fname.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_move_unreadable_spill_file_aside() {
+        let port = free_port();
+        let dir = spill_dir("empty");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0000000000.dtf"), b"").unwrap();
+        encode(dir.join("0000000001.dtf").to_str().unwrap(), "bnc_btc_eth", &[up(0)]).unwrap();
+
+        let mut cli = client(port).with_spill_dir(&dir).unwrap();
+        cli.insert(Some("bnc_btc_eth"), &up(1)).unwrap();
+        assert_eq!(cli.spilled_files(), 2);
+        let server = FakeServer::start(port, Behaviour::default());
+        cli.wait_connected();
+        assert_eq!(cli.spilled_files(), 0);
+        assert_eq!(cli.buffered(), 0);
+        assert_eq!(server.inserted(), vec![0, 1]);
+        assert!(dir.join("0000000000.dtf.bad").exists());
+        assert!(!dir.join("0000000000.dtf").exists());
+
+        // not picked up again
+        assert_eq!(client(port).with_spill_dir(&dir).unwrap().spilled_files(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_restore_state_after_server_restart() {
+        let port = free_port();
+        let mut server = FakeServer::start(port, Behaviour::default());
+        let mut cli = client(port).with_token("w-token");
+        cli.create_db("bnc_btc_eth").unwrap();
+        cli.use_db("bnc_btc_eth").unwrap();
+        cli.insert(None, &up(0)).unwrap();
+        assert!(cli.is_connected());
+        assert_eq!(server.inserted(), vec![0]);
+
+        server.stop();
+        cli.insert(None, &up(1)).unwrap();
+        cli.insert(None, &up(2)).unwrap();
+        assert!(!cli.is_connected());
+        assert_eq!(cli.buffered(), 2);
+
+        let server = FakeServer::start(port, Behaviour::default());
+        cli.wait_connected();
+        let commands = server.commands();
+        assert_eq!(&commands[..3], &[
+            b"AUTH w-token\n".to_vec(),
+            b"CREATE bnc_btc_eth\n".to_vec(),
+            b"USE bnc_btc_eth\n".to_vec(),
+        ]);
+        assert_eq!(server.inserted(), vec![1, 2]);
+        assert_eq!(decode_insert_into(&commands[3]).unwrap().1.unwrap().as_str(), "bnc_btc_eth");
+    }
+
+    #[test]
+    fn should_only_reselect_existing_books() {
+        let port = free_port();
+        let mut server = FakeServer::start(port, Behaviour {
+            refused: vec![("USE missing", "ERR: No db named `missing`\n")],
+            ..Default::default()
+        });
+        let mut cli = client(port);
+        cli.use_db("bnc_btc_eth").unwrap();
+        assert_eq!(cli.use_db("missing"), Err(TectonicError::DBNotFoundError("missing".to_owned())));
+        cli.insert(None, &up(0)).unwrap();
+        assert_eq!(decode_insert_into(&server.commands()[2]).unwrap().1.unwrap().as_str(), "bnc_btc_eth");
+
+        // dropped while disconnected
+        server.stop();
+        cli.insert(None, &up(1)).unwrap();
+        let server = FakeServer::start(port, Behaviour {
+            refused: vec![("USE bnc_btc_eth", "ERR: No db named `bnc_btc_eth`\n")],
+            ..Default::default()
+        });
+        cli.wait_connected();
+        assert!(cli.is_connected());
+        assert_eq!(server.commands()[0], b"USE bnc_btc_eth\n".to_vec());
+        // the insert buffered for bnc_btc_eth keeps its book
+        assert_eq!(server.inserted(), vec![1]);
+        assert_eq!(decode_insert_into(&server.commands()[1]).unwrap().1.unwrap().as_str(), "bnc_btc_eth");
+        cli.insert(None, &up(2)).unwrap();
+        assert_eq!(decode_insert_into(&server.commands()[2]).unwrap().1.unwrap().as_str(), "default");
+        assert_eq!(server.commands().len(), 3);
+    }
+
+    #[test]
+    fn should_resubscribe_from_last_ts() {
+        let port = free_port();
+        let behaviour = Behaviour {
+            sub_frames: vec![(true, encode_insert_into(Some("bnc_btc_eth"), &up(5)).unwrap())],
+            ..Default::default()
+        };
+        let mut server = FakeServer::start(port, behaviour.clone());
+        let sub = client(port).subscribe("bnc_btc_eth");
+        assert_eq!(sub.recv_timeout(TIMEOUT).unwrap(), up(5));
+        assert_eq!(server.commands(), vec![b"SUBSCRIBE bnc_btc_eth\n".to_vec()]);
+
+        server.stop();
+        let server = FakeServer::start(port, behaviour);
+        assert_eq!(sub.recv_timeout(TIMEOUT).unwrap(), up(5));
+        assert_eq!(server.commands(), vec![b"SUBSCRIBE bnc_btc_eth FROM 5\n".to_vec()]);
+    }
+
+    #[test]
+    fn should_end_subscription_on_error_reply() {
+        let port = free_port();
+        let server = FakeServer::start(port, Behaviour {
+            sub_frames: vec![(false, b"ERR: Permission denied: `research` cannot access `bnc_btc_eth`.\n".to_vec())],
+            ..Default::default()
+        });
+        let sub = client(port).subscribe("bnc_btc_eth");
+        assert!(sub.recv_timeout(TIMEOUT).is_err());
+        assert_eq!(server.commands().len(), 1);
+    }
+
+    #[test]
+    fn should_stop_resubscribing_once_dropped() {
+        let port = free_port();
+        let mut server = FakeServer::start(port, Behaviour::default());
+        let sub = client(port).subscribe("bnc_btc_eth");
+        while server.commands().is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(sub);
+        server.stop();
+
+        let server = FakeServer::start(port, Behaviour::default());
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(server.commands().is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use rustls::{ClientConfig, ClientSession, Session, StreamOwned};
+use crate::error::TectonicError;
+
+/// Connection to tectonicdb, either plain TCP or TLS
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientSession, TcpStream>>),
+}
+
+impl std::fmt::Debug for ClientStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ClientStream::Plain(sock) => write!(f, "Plain({:?})", sock),
+            ClientStream::Tls(stream) => write!(f, "Tls({:?})", stream.sock),
+        }
+    }
+}
+
+impl ClientStream {
+    /// Connects to `host:port`, negotiating TLS when `tls` is given.
+    /// `host` is also the name the server certificate is checked against.
+    pub fn connect(host: &str, port: &str, tls: Option<&Arc<ClientConfig>>) -> Result<ClientStream, TectonicError> {
+        let addr = format!("{}:{}", host, port);
+        let sock = TcpStream::connect(&addr).map_err(|_| TectonicError::ConnectionError)?;
+        match tls {
+            None => Ok(ClientStream::Plain(sock)),
+            Some(config) => {
+                let dns_name = webpki::DNSNameRef::try_from_ascii_str(host)
+                    .map_err(|_| TectonicError::TlsError(format!("Invalid DNS name: {}", host)))?;
+                let session = ClientSession::new(config, dns_name);
+                let mut stream = StreamOwned::new(session, sock);
+                // drive the handshake now so certificate errors surface on connect
+                while stream.sess.is_handshaking() {
+                    stream.sess.complete_io(&mut stream.sock)
+                        .map_err(|e| TectonicError::TlsError(e.to_string()))?;
+                }
+                Ok(ClientStream::Tls(Box::new(stream)))
+            }
+        }
+    }
+
+    /// Underlying tcp socket
+    pub fn get_ref(&self) -> &TcpStream {
+        match self {
+            ClientStream::Plain(sock) => sock,
+            ClientStream::Tls(stream) => &stream.sock,
+        }
+    }
+
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        if let ClientStream::Tls(stream) = self {
+            stream.sess.send_close_notify();
+            stream.flush()?;
+        }
+        self.get_ref().shutdown(Shutdown::Both)
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(sock) => sock.read(buf),
+            ClientStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(sock) => sock.write(buf),
+            ClientStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Plain(sock) => sock.flush(),
+            ClientStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// TLS client configuration trusting the PEM certificates in `ca_file`,
+/// or the Mozilla root certificates when none is given.
+pub fn tls_config(ca_file: Option<&str>) -> Result<Arc<ClientConfig>, TectonicError> {
+    let mut config = ClientConfig::new();
+    match ca_file {
+        Some(fname) => {
+            let f = File::open(fname)
+                .map_err(|e| TectonicError::TlsError(format!("Unable to open {}: {}", fname, e)))?;
+            let (valid, _invalid) = config.root_store.add_pem_file(&mut BufReader::new(f))
+                .map_err(|()| TectonicError::TlsError(format!("Invalid PEM file: {}", fname)))?;
+            if valid == 0 {
+                return Err(TectonicError::TlsError(format!("No certificate found in {}", fname)));
+            }
+        }
+        None => config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+    }
+    Ok(Arc::new(config))
+}
@@ -38,16 +38,13 @@ use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
 use std::io::{self, Write, Read, Seek, BufWriter, BufReader, SeekFrom};
 
 use std::iter::Peekable;
-use std::io::Cursor;
-use std::sync::Mutex;
-use std::cell::RefCell;
-use std::ops::DerefMut;
 
 use crate::dtf::update::*;
 use crate::utils::epoch_to_human;
 
 static MAGIC_VALUE: &[u8] = &[0x44, 0x54, 0x46, 0x90, 0x01]; // DTF9001
-const SYMBOL_LEN: usize = 20;
+/// longest symbol a DTF file can hold
+pub const SYMBOL_LEN: usize = 20;
 static SYMBOL_OFFSET: u64 = 5;
 static LEN_OFFSET: u64 = 25;
 static MAX_TS_OFFSET: u64 = 33;
@@ -139,6 +136,17 @@ pub fn write_symbol(wtr: &mut dyn Write, symbol: &str) -> Result<usize, io::Erro
     wtr.write(padded_symbol.as_bytes())
 }
 
+/// Overwrite the symbol stored in the header of an existing file
+pub fn rewrite_symbol(fname: &str, symbol: &str) -> Result<(), io::Error> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(fname)?;
+    if !read_magic_value(&mut file)? {
+        return Err(io::Error::new(InvalidData, format!("{} is not a DTF file", fname)));
+    }
+    file.seek(SeekFrom::Start(SYMBOL_OFFSET))?;
+    write_symbol(&mut file, symbol)?;
+    file.flush()
+}
+
 /// write length in header
 pub fn write_len<T: Write + Seek>(wtr: &mut T, len: u64) -> Result<(), io::Error> {
     let _ = wtr.seek(SeekFrom::Start(LEN_OFFSET));
@@ -167,13 +175,8 @@ use std::ops::Deref;
 /// write a list of updates as batches
 #[cfg_attr(feature="count_alloc", count_alloc)]
 pub fn write_batches<U: Deref<Target=Update>, I: Iterator<Item=U>>(mut wtr: &mut dyn Write, mut ups: Peekable<I>) -> Result<(), io::Error> {
-    lazy_static! {
-        static ref BUF: Mutex<RefCell<Vec<u8>>> = Mutex::new(RefCell::new(vec![0; 100_000_000]));
-    }
-    let mut b = BUF.lock().unwrap();
-    let mut c = b.deref_mut().borrow_mut();
-    let mut buf = Cursor::new(&mut c[..]);
-    // let mut buf = Vec::new();
+    // holds one batch, at most 0xFFFF rows
+    let mut buf: Vec<u8> = Vec::with_capacity(BYTES_PER_ROW * 1024);
     let head = ups.peek().unwrap();
     let mut ref_ts = head.ts;
     let mut ref_seq = head.seq;
@@ -190,10 +193,8 @@ pub fn write_batches<U: Deref<Target=Update>, I: Iterator<Item=U>>(mut wtr: &mut
          )
         {
             write_reference(&mut wtr, ref_ts, ref_seq, count)?;
-            let _ = wtr.write(&buf.get_ref()[0..(buf.position() as usize)]);
-            buf.set_position(0);
-            // let _ = wtr.write(buf.as_slice());
-            // buf.clear();
+            wtr.write_all(&buf)?;
+            buf.clear();
 
             ref_ts = elem.ts;
             ref_seq = elem.seq;
@@ -206,8 +207,7 @@ pub fn write_batches<U: Deref<Target=Update>, I: Iterator<Item=U>>(mut wtr: &mut
     }
 
     write_reference(&mut wtr, ref_ts, ref_seq, count)?;
-    wtr.write_all(&buf.get_ref()[0..(buf.position() as usize)])
-    // wtr.write_all(buf.as_slice())
+    wtr.write_all(&buf)
 }
 
 /// write main section
@@ -304,111 +304,44 @@ pub fn range<T: Read + Seek>(rdr: &mut T, min_ts: u64, max_ts: u64) -> Result<Ve
 }
 
 fn range_for_each<T: Read + Seek, F: for<'a> FnMut(&'a Update)>(rdr: &mut T, min_ts: u64, max_ts: u64, f: &mut F) -> Result<(), io::Error> {
-    // convert ts to match the dtf file format (in ms)
-
     // can't go back in time
     if min_ts > max_ts {
         return Ok(());
     }
     // go to beginning of main section
-    rdr.seek(SeekFrom::Start(MAIN_OFFSET)).expect("SEEKING");
+    rdr.seek(SeekFrom::Start(MAIN_OFFSET))?;
 
     loop {
-        // read marker byte
-        match rdr.read_u8() {
-            Ok(byte) => {
-                if byte != 0x1 {
-                    return Ok(());
-                }
-            }   // 0x1 indicates a batch
-            Err(_e) => {
-                return Ok(());
-            }                        // EOF
-        };
-
-        // read the metadata of the current batch
-        let current_meta = read_one_batch_meta(rdr);
-        let current_ref_ts = current_meta.ref_ts;
-
-        // skip a few bytes and read the next metadata
-        let bytes_to_skip = current_meta.count as usize * BYTES_PER_ROW;
-        rdr.seek(SeekFrom::Current(bytes_to_skip as i64)).expect(
-            &format!(
-                "Skipping {} rows",
-                current_meta.count
-            ),
-        );
-
-        // must be a batch
+        // 0x1 indicates a batch, anything else or EOF ends the main section
         match rdr.read_u8() {
-            Ok(byte) => {
-                if byte != 0x1 {
-                    return Ok(());
-                }
-            }   // is a batch
-            Err(_e) => {
-                return Ok(());
-            }                        // EOF
-        };
-        let next_meta = read_one_batch_meta(rdr);
-        let next_ref_ts = next_meta.ref_ts;
-
-        // legend:
-        // `|`: meta data
-        // `1`: indicator byte
-        // `-`: updates
-
-        //     |1-----|1*---      <- we are here
-
-        //  [ ]                   <- requested
-        //     |1-----|1---
-        //
-        if min_ts <= current_ref_ts && max_ts <= current_ref_ts {
+            Ok(0x1) => (),
+            _ => return Ok(()),
+        }
+        let meta = read_one_batch_meta(rdr);
+        // batches are sorted, the rest of the file is after the range
+        if meta.ref_ts > max_ts {
             return Ok(());
-        } else
-        // [    ]
-        //   |1*-----|1---
-        //
-        // or
-        //
-        //         [     ]
-        //         [          ]
-        //   |1*-----|1----|1---
-        //
-        if (min_ts <= current_ref_ts && max_ts <= next_ref_ts)
-                   || (min_ts < next_ref_ts && max_ts >= next_ref_ts)
-                   || (min_ts > current_ref_ts && max_ts < next_ref_ts)
-        {
-            // seek back
-            let bytes_to_scrollback = - (bytes_to_skip as i64) - 14 /* metadata */ - 1 /* indicator byte */ ;
-            rdr.seek(SeekFrom::Current(bytes_to_scrollback)).expect(
-                "scrolling back",
-            );
-            //   |1*------|1--          <- we are here
-            // read and filter current batch
-            if min_ts <= current_ref_ts && max_ts >= next_ref_ts {
-                read_one_batch_main_for_each(rdr, current_meta, f)?;
-            } else {
-                read_one_batch_main_for_each(rdr, current_meta, &mut |up| {
-                    if up.ts <= max_ts && up.ts >= min_ts {
-                        f(up);
-                    }
-                })?;
-            }
-
-        //               [      ]
-        // |1----|1---|1----
-        //
-        } else if min_ts >= next_ref_ts {
-            // simply skip back to the beginning of the second batch
-            // |1----*|1---|1---
-            let bytes_to_scrollback = - 14 /* metadata */ - 1 /* indicator byte */ ;
-            rdr.seek(SeekFrom::Current(bytes_to_scrollback)).expect(
-                "SKIPPING n ROWS",
-            );
-        } else {
-            panic!("{}, {}, {}, {}..... Should have covered all the cases.", min_ts, max_ts, current_ref_ts, next_ref_ts);
         }
+        let batch_start = rdr.stream_position()?;
+        let batch_end = batch_start + meta.count as u64 * BYTES_PER_ROW as u64;
+
+        // every update of a batch is before the ref_ts of the next one,
+        // the last batch has no next one and is always read
+        rdr.seek(SeekFrom::Start(batch_end))?;
+        let next_ref_ts = match rdr.read_u8() {
+            Ok(0x1) => Some(read_one_batch_meta(rdr).ref_ts),
+            _ => None,
+        };
+        if next_ref_ts.is_some_and(|next| next < min_ts) {
+            rdr.seek(SeekFrom::Start(batch_end))?;
+            continue;
+        }
+        rdr.seek(SeekFrom::Start(batch_start))?;
+        read_one_batch_main_for_each(rdr, meta, &mut |up| {
+            if up.ts <= max_ts && up.ts >= min_ts {
+                f(up);
+            }
+        })?;
     }
 }
 
@@ -766,6 +699,24 @@ pub fn decode_for_each<F: for<'a> FnMut(&'a Update)>(fname: &str, num_rows: Opti
     }
 }
 
+/// Decode the main section in a dtf file until `f` returns false.
+/// Returns whether every update was decoded.
+pub fn decode_while<F: for<'a> FnMut(&'a Update) -> bool>(fname: &str, f: &mut F) -> Result<bool, io::Error> {
+    let mut rdr = file_reader(fname)?;
+    rdr.seek(SeekFrom::Start(MAIN_OFFSET))?;
+    while let Ok(is_ref) = rdr.read_u8() {
+        if is_ref == 0x1 {
+            let meta = read_one_batch_meta(&mut rdr);
+            for _i in 0..meta.count {
+                if !f(&read_one_update(&mut rdr, &meta)?) {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+    Ok(true)
+}
+
 /// Decode an entire buffer to Updates
 pub fn decode_buffer(mut buf: &mut (impl Read + Seek)) -> Vec<Update> {
     let mut v = vec![];
@@ -818,6 +769,24 @@ pub fn append(fname: &str, ups: &[Update]) -> Result<(), io::Error> {
     Ok(())
 }
 
+/// Paths of every dtf file under folder whose header symbol is `symbol`
+pub fn find_files_for_symbol(folder: &str, symbol: &str) -> Result<Vec<String>, io::Error> {
+    let mut ret = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let fname = format!("{}/{}", folder, entry?.file_name().to_string_lossy());
+        if !is_dtf(&fname).unwrap_or(false) {
+            continue;
+        }
+        // only the header, so files with a damaged body are still found
+        match file_reader(&fname).and_then(|mut rdr| read_symbol(&mut rdr)) {
+            Ok(sym) if sym == symbol => ret.push(fname),
+            _ => (),
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
 /// search every matching dtf file under folder for timestamp range
 pub fn scan_files_for_range(
     folder: &str,
@@ -876,6 +845,7 @@ pub fn scan_files_for_range_for_each<F: for<'a> FnMut(&'a Update)>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Cursor;
 
     #[cfg(test)]
     fn real_sample_data() -> Vec<Update> {
@@ -1024,6 +994,20 @@ mod tests {
         assert_eq!(decoded_updates, ts);
     }
 
+    #[test]
+    fn should_rewrite_symbol() {
+        let ts = sample_data();
+        let fname = "test_rewrite_symbol.dtf";
+        encode(fname, "NEO_BTC", &ts).unwrap();
+        rewrite_symbol(fname, "bnc_neo_btc").unwrap();
+        let meta = read_meta(fname).unwrap();
+        assert_eq!(meta.symbol, "bnc_neo_btc");
+        assert_eq!(meta.count, ts.len() as u64);
+        assert_eq!(decode(fname, None).unwrap(), ts);
+        assert!(rewrite_symbol(fname, "a_symbol_longer_than_twenty").is_err());
+        std::fs::remove_file(fname).unwrap();
+    }
+
     #[test]
     fn should_encode_and_decode_file() {
         let ts = init();
@@ -1092,7 +1076,7 @@ mod tests {
 
         let mut rdr = file_reader(fname).unwrap();
         assert_eq!(
-            (1..999)
+            (1..1000)
                 .map(|i| {
                     Update {
                         ts: i * 1000 as u64,
@@ -1105,7 +1089,30 @@ mod tests {
                 })
                 .collect::<Vec<Update>>(),
             range(&mut rdr, 1000, 999000).unwrap()
-        ); // ???
+        );
+    }
+
+    #[test]
+    fn should_range_over_the_last_batch() {
+        let fname = "test-last-batch.dtf";
+        let ups = (0..10)
+            .map(|i| Update { ts: 1000 + i, seq: i as u32, price: 1., size: 1., is_bid: true, is_trade: false })
+            .collect::<Vec<Update>>();
+        encode(fname, "test", &ups).unwrap();
+        let single = range(&mut file_reader(fname).unwrap(), 0, 2000).unwrap();
+
+        // a new batch starts whenever the ts delta no longer fits a u16
+        let ups = (0..10)
+            .map(|i| Update { ts: i * 100_000, seq: i as u32, price: 1., size: 1., is_bid: true, is_trade: false })
+            .collect::<Vec<Update>>();
+        encode(fname, "test", &ups).unwrap();
+        let many = range(&mut file_reader(fname).unwrap(), 0, 900_000).unwrap();
+        let tail = range(&mut file_reader(fname).unwrap(), 850_000, 2_000_000).unwrap();
+        std::fs::remove_file(fname).unwrap();
+
+        assert_eq!(single.len(), 10);
+        assert_eq!(many, ups);
+        assert_eq!(tail, &ups[9..]);
     }
 
     #[test]
@@ -0,0 +1,301 @@
+//!
+//! Level-3 (order-by-order) records and their DTF encoding
+//!
+//! L3 files share the header layout of L2 DTF files but have their own magic
+//! value, so readers of one format reject the other.
+//!
+//! File Spec:
+//! Offset 00: ([u8; 5]) magic value 0x4454469003
+//! Offset 05: ([u8; 20]) Symbol
+//! Offset 25: (u64) number of records
+//! Offset 33: (u64) max ts
+//! Offset 80: -- batches - see below --
+//!
+//! Batch Spec:
+//!        marker (u8): 0x1
+//!        reference ts (u64)
+//!        reference seq (u32)
+//!        count (u16): number of records in the batch
+//! Record Spec:
+//!        dts (u16): $ts - reference ts$
+//!        dseq (u16): $seq - reference seq$
+//!        flags (u8): is_bid in bit 0, action in bits 1-2
+//!        order id (u64)
+//!        price (f32)
+//!        size (f32)
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::ErrorKind::InvalidData;
+use std::str;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::dtf::file_format::{self, Metadata};
+
+static MAGIC_VALUE: &[u8] = &[0x44, 0x54, 0x46, 0x90, 0x03]; // DTF9003
+const SYMBOL_LEN: usize = 20;
+static SYMBOL_OFFSET: u64 = 5;
+static LEN_OFFSET: u64 = 25;
+static MAIN_OFFSET: u64 = 80;
+
+/// What happened to an order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum L3Action {
+    /// new order, `size` is its size
+    Add,
+    /// changed order, `price` and `size` are the new values
+    Modify,
+    /// removed order
+    Cancel,
+    /// order (partially) filled, `size` is the executed size
+    Execute,
+}
+
+impl L3Action {
+    fn to_bits(self) -> u8 {
+        match self {
+            L3Action::Add => 0,
+            L3Action::Modify => 1,
+            L3Action::Cancel => 2,
+            L3Action::Execute => 3,
+        }
+    }
+
+    fn from_bits(bits: u8) -> L3Action {
+        match bits & 0b11 {
+            0 => L3Action::Add,
+            1 => L3Action::Modify,
+            2 => L3Action::Cancel,
+            _ => L3Action::Execute,
+        }
+    }
+
+    /// lowercase name
+    pub fn as_str(self) -> &'static str {
+        match self {
+            L3Action::Add => "add",
+            L3Action::Modify => "modify",
+            L3Action::Cancel => "cancel",
+            L3Action::Execute => "execute",
+        }
+    }
+}
+
+/// Represents an L3 order event
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct L3Update {
+    /// time stamp
+    pub ts: u64,
+    /// sequence number
+    pub seq: u32,
+    /// exchange order id
+    pub order_id: u64,
+    /// what happened to the order
+    pub action: L3Action,
+    /// is the order on the bid or ask side
+    pub is_bid: bool,
+    /// price of the order
+    pub price: f32,
+    /// size, see `L3Action`
+    pub size: f32,
+}
+
+impl L3Update {
+    /// Serialize to bytearray relative to the batch reference
+    pub fn serialize_to_buffer(&self, buf: &mut dyn Write, ref_ts: u64, ref_seq: u32) -> Result<(), io::Error> {
+        buf.write_u16::<BigEndian>((self.ts - ref_ts) as u16)?;
+        buf.write_u16::<BigEndian>((self.seq - ref_seq) as u16)?;
+        buf.write_u8(self.is_bid as u8 | self.action.to_bits() << 1)?;
+        buf.write_u64::<BigEndian>(self.order_id)?;
+        buf.write_f32::<BigEndian>(self.price)?;
+        buf.write_f32::<BigEndian>(self.size)
+    }
+
+    /// Convert to json string
+    pub fn as_json(&self) -> String {
+        format!(
+            r#"{{"ts":{},"seq":{},"order_id":{},"action":"{}","is_bid":{},"price":{},"size":{}}}"#,
+            (self.ts as f64) / 1000_f64,
+            self.seq,
+            self.order_id,
+            self.action.as_str(),
+            self.is_bid,
+            self.price,
+            self.size
+        )
+    }
+
+    /// Convert to csv string
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            (self.ts as f64) / 1000_f64,
+            self.seq,
+            self.order_id,
+            self.action.as_str(),
+            if self.is_bid {"t"} else {"f"},
+            self.price,
+            self.size
+        )
+    }
+}
+
+fn write_batch(wtr: &mut dyn Write, ref_ts: u64, ref_seq: u32, count: u16, buf: &[u8]) -> Result<(), io::Error> {
+    wtr.write_u8(0x1)?;
+    wtr.write_u64::<BigEndian>(ref_ts)?;
+    wtr.write_u32::<BigEndian>(ref_seq)?;
+    wtr.write_u16::<BigEndian>(count)?;
+    wtr.write_all(buf)
+}
+
+/// write a list of L3 updates as batches
+pub fn write_batches(wtr: &mut dyn Write, ups: &[L3Update]) -> Result<(), io::Error> {
+    let head = match ups.first() {
+        Some(head) => head,
+        None => return Ok(()),
+    };
+    let mut buf = Vec::with_capacity(21 * 1024);
+    let (mut ref_ts, mut ref_seq) = (head.ts, head.seq);
+    let mut count: u16 = 0;
+    for up in ups {
+        if count != 0 && (
+            up.ts < ref_ts || up.ts >= ref_ts + 0xFFFF
+            || up.seq < ref_seq || up.seq >= ref_seq + 0xFFFF
+            || count == 0xFFFF
+        ) {
+            write_batch(wtr, ref_ts, ref_seq, count, &buf)?;
+            buf.clear();
+            ref_ts = up.ts;
+            ref_seq = up.seq;
+            count = 0;
+        }
+        up.serialize_to_buffer(&mut buf, ref_ts, ref_seq)?;
+        count += 1;
+    }
+    write_batch(wtr, ref_ts, ref_seq, count, &buf)
+}
+
+/// encode L3 updates into a buffer, complete with magic value, symbol, metadata
+pub fn encode_buffer<T: Write + Seek>(wtr: &mut T, symbol: &str, ups: &[L3Update]) -> Result<(), io::Error> {
+    if ups.is_empty() {
+        return Ok(());
+    }
+    wtr.write_all(MAGIC_VALUE)?;
+    file_format::write_symbol(wtr, symbol)?;
+    file_format::write_len(wtr, ups.len() as u64)?;
+    file_format::write_max_ts(wtr, ups.iter().map(|up| up.ts).max().unwrap())?;
+    wtr.seek(SeekFrom::Start(MAIN_OFFSET))?;
+    write_batches(wtr, ups)
+}
+
+/// write a list of L3 updates to file
+pub fn encode(fname: &str, symbol: &str, ups: &[L3Update]) -> Result<(), io::Error> {
+    let mut wtr = BufWriter::new(File::create(fname)?);
+    encode_buffer(&mut wtr, symbol, ups)?;
+    wtr.flush()
+}
+
+/// check magic value
+pub fn is_l3<T: Read + Seek>(rdr: &mut T) -> Result<bool, io::Error> {
+    rdr.seek(SeekFrom::Start(0))?;
+    let mut magic = [0; 5];
+    rdr.read_exact(&mut magic)?;
+    Ok(magic == MAGIC_VALUE)
+}
+
+/// check magic value of a file
+pub fn is_l3_file(fname: &str) -> Result<bool, io::Error> {
+    is_l3(&mut BufReader::new(File::open(fname)?))
+}
+
+/// Read Metadata block from buffer
+pub fn read_meta_from_buf<T: Read + Seek>(rdr: &mut T) -> Result<Metadata, io::Error> {
+    if !is_l3(rdr)? {
+        return Err(io::Error::new(InvalidData, "Magic Value incorrect"));
+    }
+    rdr.seek(SeekFrom::Start(SYMBOL_OFFSET))?;
+    let mut symbol = [0; SYMBOL_LEN];
+    rdr.read_exact(&mut symbol)?;
+    let symbol = str::from_utf8(&symbol).map_err(|_| InvalidData)?.trim().to_owned();
+    rdr.seek(SeekFrom::Start(LEN_OFFSET))?;
+    let count = rdr.read_u64::<BigEndian>()?;
+    let max_ts = rdr.read_u64::<BigEndian>()?;
+    let min_ts = if count > 0 {
+        // reference ts of the first batch plus dts of its first record
+        rdr.seek(SeekFrom::Start(MAIN_OFFSET + 1))?;
+        let ref_ts = rdr.read_u64::<BigEndian>()?;
+        rdr.seek(SeekFrom::Current(6))?;
+        ref_ts + u64::from(rdr.read_u16::<BigEndian>()?)
+    } else {
+        max_ts
+    };
+    Ok(Metadata { symbol, count, max_ts, min_ts })
+}
+
+/// Read Metadata of a file
+pub fn read_meta(fname: &str) -> Result<Metadata, io::Error> {
+    read_meta_from_buf(&mut BufReader::new(File::open(fname)?))
+}
+
+/// decode L3 updates from a buffer
+pub fn decode_buffer<T: Read + Seek>(rdr: &mut T) -> Result<Vec<L3Update>, io::Error> {
+    let meta = read_meta_from_buf(rdr)?;
+    let mut ret = Vec::with_capacity(meta.count as usize);
+    rdr.seek(SeekFrom::Start(MAIN_OFFSET))?;
+    while (ret.len() as u64) < meta.count {
+        if rdr.read_u8()? != 0x1 {
+            return Err(io::Error::new(InvalidData, "Expected a batch"));
+        }
+        let ref_ts = rdr.read_u64::<BigEndian>()?;
+        let ref_seq = rdr.read_u32::<BigEndian>()?;
+        let count = rdr.read_u16::<BigEndian>()?;
+        for _ in 0..count {
+            let ts = ref_ts + u64::from(rdr.read_u16::<BigEndian>()?);
+            let seq = ref_seq + u32::from(rdr.read_u16::<BigEndian>()?);
+            let flags = rdr.read_u8()?;
+            ret.push(L3Update {
+                ts,
+                seq,
+                is_bid: flags & 1 == 1,
+                action: L3Action::from_bits(flags >> 1),
+                order_id: rdr.read_u64::<BigEndian>()?,
+                price: rdr.read_f32::<BigEndian>()?,
+                size: rdr.read_f32::<BigEndian>()?,
+            });
+        }
+    }
+    Ok(ret)
+}
+
+/// decode L3 updates from file
+pub fn decode(fname: &str) -> Result<Vec<L3Update>, io::Error> {
+    decode_buffer(&mut BufReader::new(File::open(fname)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn should_encode_decode_l3() {
+        let up = |ts, seq, order_id, action, price, size| L3Update { ts, seq, order_id, action, is_bid: order_id % 2 == 0, price, size };
+        let ups = vec![
+            up(1000, 0, 1, L3Action::Add, 1.5, 2.),
+            up(1001, 1, 2, L3Action::Add, 1.4, 1.),
+            up(1001, 2, 1, L3Action::Execute, 1.5, 0.5),
+            // starts a new batch
+            up(100_000, 3, 2, L3Action::Modify, 1.3, 1.),
+            up(100_000, 4, 1, L3Action::Cancel, 1.5, 0.),
+        ];
+        let mut buf = Cursor::new(vec![]);
+        encode_buffer(&mut buf, "bnc_btc_eth", &ups).unwrap();
+        assert_eq!(decode_buffer(&mut buf).unwrap(), ups);
+
+        let meta = read_meta_from_buf(&mut buf).unwrap();
+        assert_eq!((meta.symbol.as_str(), meta.count, meta.min_ts, meta.max_ts), ("bnc_btc_eth", 5, 1000, 100_000));
+        assert!(file_format::read_magic_value(&mut buf).map(|is_l2| !is_l2).unwrap());
+        assert_eq!(ups[2].to_csv(), "1.001,2,1,execute,f,1.5,0.5");
+    }
+}
@@ -2,7 +2,11 @@
 pub mod file_format;
 /// Represents a single row of orderbook update
 pub mod update;
+/// Order-by-order records and their file format
+pub mod l3;
 /// Financial symbol
 pub mod symbol;
 /// C FFI structs and functions
-pub mod ffi;
\ No newline at end of file
+pub mod ffi;
+/// Orderbook checkpoints stored next to DTF files
+pub mod snapshot;
@@ -0,0 +1,232 @@
+//!
+//! Orderbook checkpoints stored next to a DTF file (`{symbol}.snap`)
+//!
+//! Replaying a book from the start of a file is slow, so the server appends
+//! the full orderbook every so often. Records are appended in time order.
+//!
+//! File Spec:
+//! Offset 00: ([u8; 5]) magic value 0x534e509001
+//! Offset 05: -- records - see below --
+//!
+//! Record Spec:
+//!        ts (u64): time of the last update included in the book
+//!        len (u32): number of bytes until the next record
+//!        price_decimals (u8)
+//!        number of bids (u32), then (price: u64, size: f64) for each
+//!        number of asks (u32), then (price: u64, size: f64) for each
+//!
+//! A record is only read if it ends within the file and its len matches its
+//! bid and ask counts, so a record torn by a crash ends the file. Appending
+//! trims it first.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::ErrorKind::InvalidData;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::postprocessing::orderbook::Orderbook;
+
+static MAGIC_VALUE: &[u8] = &[0x53, 0x4e, 0x50, 0x90, 0x01]; // SNP9001
+
+/// Orderbook as of `ts`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// time of the last update applied to `orderbook`
+    pub ts: u64,
+    /// the book
+    pub orderbook: Orderbook,
+}
+
+/// name of the sidecar file of `symbol` in `folder`
+pub fn snapshot_fname(folder: &str, symbol: &str) -> String {
+    format!("{}/{}.snap", folder, symbol)
+}
+
+/// ts and len fields before every record
+const RECORD_HEADER: u64 = 12;
+/// price_decimals and the bid and ask counts
+const MIN_RECORD_LEN: u64 = 9;
+
+/// append `snapshot` to the file, creating it if needed. A torn record at
+/// the end of the file is cut off first.
+pub fn append(fname: &str, snapshot: &Snapshot) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(fname)?;
+    let file_len = file.metadata()?.len();
+    let end = valid_len(&mut BufReader::new(&mut file), file_len)?;
+    if end < file_len {
+        file.set_len(end)?;
+    }
+    let mut wtr = BufWriter::new(&mut file);
+    if end == 0 {
+        wtr.write_all(MAGIC_VALUE)?;
+    }
+    let ob = &snapshot.orderbook;
+    let len = 1 + 4 + 4 + 16 * (ob.bids.len() + ob.asks.len());
+    wtr.write_u64::<BigEndian>(snapshot.ts)?;
+    wtr.write_u32::<BigEndian>(len as u32)?;
+    wtr.write_u8(ob.price_decimals)?;
+    for side in &[&ob.bids, &ob.asks] {
+        wtr.write_u32::<BigEndian>(side.len() as u32)?;
+        for (&price, &size) in side.iter() {
+            wtr.write_u64::<BigEndian>(price)?;
+            wtr.write_f64::<BigEndian>(size)?;
+        }
+    }
+    wtr.flush()
+}
+
+fn read_magic(rdr: &mut impl Read) -> Result<(), io::Error> {
+    let mut magic = [0; 5];
+    rdr.read_exact(&mut magic)?;
+    if magic != MAGIC_VALUE {
+        return Err(io::Error::new(InvalidData, "Magic Value incorrect"));
+    }
+    Ok(())
+}
+
+fn file_reader(fname: &str) -> Result<BufReader<File>, io::Error> {
+    let mut rdr = BufReader::new(File::open(fname)?);
+    read_magic(&mut rdr)?;
+    Ok(rdr)
+}
+
+/// ts and len of the record at `pos`, none at the end of the file or when the
+/// record is torn: it runs past `file_len` or its len does not match its bid
+/// and ask counts
+fn read_record_header<R: Read + Seek>(rdr: &mut R, pos: u64, file_len: u64) -> Result<Option<(u64, u32)>, io::Error> {
+    if pos + RECORD_HEADER + MIN_RECORD_LEN > file_len {
+        return Ok(None);
+    }
+    rdr.seek(SeekFrom::Start(pos))?;
+    let ts = rdr.read_u64::<BigEndian>()?;
+    let len = rdr.read_u32::<BigEndian>()?;
+    let body = pos + RECORD_HEADER;
+    let end = body + u64::from(len);
+    if u64::from(len) < MIN_RECORD_LEN || end > file_len {
+        return Ok(None);
+    }
+    let _price_decimals = rdr.read_u8()?;
+    let bids = u64::from(rdr.read_u32::<BigEndian>()?);
+    let asks_at = body + 5 + 16 * bids;
+    if asks_at + 4 > end {
+        return Ok(None);
+    }
+    rdr.seek(SeekFrom::Start(asks_at))?;
+    let asks = u64::from(rdr.read_u32::<BigEndian>()?);
+    if MIN_RECORD_LEN + 16 * (bids + asks) != u64::from(len) {
+        return Ok(None);
+    }
+    Ok(Some((ts, len)))
+}
+
+/// length of the file up to the end of its last complete record, 0 when even
+/// the magic value is incomplete
+fn valid_len<R: Read + Seek>(rdr: &mut R, file_len: u64) -> Result<u64, io::Error> {
+    if file_len < MAGIC_VALUE.len() as u64 {
+        return Ok(0);
+    }
+    rdr.seek(SeekFrom::Start(0))?;
+    read_magic(rdr)?;
+    let mut pos = MAGIC_VALUE.len() as u64;
+    while let Some((_, len)) = read_record_header(rdr, pos, file_len)? {
+        pos += RECORD_HEADER + u64::from(len);
+    }
+    Ok(pos)
+}
+
+fn read_side(rdr: &mut impl Read) -> Result<BTreeMap<u64, f64>, io::Error> {
+    let n = rdr.read_u32::<BigEndian>()?;
+    (0..n).map(|_| Ok((rdr.read_u64::<BigEndian>()?, rdr.read_f64::<BigEndian>()?))).collect()
+}
+
+/// the latest snapshot taken at or before `ts`, skipping the books of older records
+pub fn nearest(fname: &str, ts: u64) -> Result<Option<Snapshot>, io::Error> {
+    let mut rdr = file_reader(fname)?;
+    let file_len = rdr.get_ref().metadata()?.len();
+    let mut pos = MAGIC_VALUE.len() as u64;
+    let mut found = None;
+    while let Some((record_ts, len)) = read_record_header(&mut rdr, pos, file_len)? {
+        if record_ts > ts {
+            break;
+        }
+        found = Some((record_ts, pos + RECORD_HEADER));
+        pos += RECORD_HEADER + u64::from(len);
+    }
+
+    match found {
+        None => Ok(None),
+        Some((ts, pos)) => {
+            rdr.seek(SeekFrom::Start(pos))?;
+            let price_decimals = rdr.read_u8()?;
+            let bids = read_side(&mut rdr)?;
+            let asks = read_side(&mut rdr)?;
+            Ok(Some(Snapshot {
+                ts,
+                orderbook: Orderbook { bids, asks, ..Orderbook::with_precision(price_decimals) },
+            }))
+        }
+    }
+}
+
+/// the most recent snapshot in the file
+pub fn latest(fname: &str) -> Result<Option<Snapshot>, io::Error> {
+    nearest(fname, u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtf::update::Update;
+
+    #[test]
+    fn should_find_nearest_snapshot() {
+        let fname = "test_nearest.snap";
+        let _ = std::fs::remove_file(fname);
+        let mut ob = Orderbook::with_precision(2);
+        for (i, ts) in [1000, 2000, 3000].iter().enumerate() {
+            ob.process_update(&Update { ts: *ts, seq: 0, is_trade: false, is_bid: true, price: i as f32 + 1., size: 1. });
+            append(fname, &Snapshot { ts: *ts, orderbook: ob.clone() }).unwrap();
+        }
+
+        assert_eq!(nearest(fname, 999).unwrap(), None);
+        let snap = nearest(fname, 2500).unwrap().unwrap();
+        assert_eq!(snap.ts, 2000);
+        assert_eq!(snap.orderbook.bids.len(), 2);
+        assert_eq!(latest(fname).unwrap().unwrap(), Snapshot { ts: 3000, orderbook: ob });
+        std::fs::remove_file(fname).unwrap();
+    }
+
+    #[test]
+    fn should_trim_torn_record() {
+        let fname = "test_torn.snap";
+        let _ = std::fs::remove_file(fname);
+        let mut ob = Orderbook::with_precision(2);
+        ob.process_update(&Update { ts: 1000, seq: 0, is_trade: false, is_bid: true, price: 1., size: 1. });
+        let first = Snapshot { ts: 1000, orderbook: ob.clone() };
+        append(fname, &first).unwrap();
+        let good_len = std::fs::metadata(fname).unwrap().len();
+
+        // a crash while writing the second record leaves part of it behind
+        ob.process_update(&Update { ts: 2000, seq: 0, is_trade: false, is_bid: false, price: 2., size: 1. });
+        append(fname, &Snapshot { ts: 2000, orderbook: ob.clone() }).unwrap();
+        std::fs::OpenOptions::new().write(true).open(fname).unwrap().set_len(good_len + 20).unwrap();
+        assert_eq!(latest(fname).unwrap(), Some(first.clone()));
+
+        // a len that disagrees with the bid and ask counts is torn as well
+        let mut bytes = std::fs::read(fname).unwrap();
+        bytes.truncate(good_len as usize);
+        bytes.extend_from_slice(&3000u64.to_be_bytes());
+        bytes.extend_from_slice(&25u32.to_be_bytes());
+        bytes.extend_from_slice(&[2, 0, 0, 0, 5]);
+        bytes.extend_from_slice(&[0; 20]);
+        std::fs::write(fname, &bytes).unwrap();
+        assert_eq!(latest(fname).unwrap(), Some(first.clone()));
+
+        let third = Snapshot { ts: 3000, orderbook: ob };
+        append(fname, &third).unwrap();
+        assert_eq!(nearest(fname, 2999).unwrap(), Some(first));
+        assert_eq!(latest(fname).unwrap(), Some(third));
+        std::fs::remove_file(fname).unwrap();
+    }
+}
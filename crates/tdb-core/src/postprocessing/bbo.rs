@@ -0,0 +1,108 @@
+//! Top of book (best bid and offer) change stream
+
+use crate::dtf::update::Update;
+use crate::postprocessing::orderbook::Orderbook;
+
+/// Best bid and ask as of `ts`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bbo {
+    /// ts of the update that changed the top of the book
+    pub ts: u64,
+    /// best bid price and size
+    pub bid: Option<(f32, f64)>,
+    /// best ask price and size
+    pub ask: Option<(f32, f64)>,
+}
+
+impl Bbo {
+    /// top of `book`
+    pub fn of(ts: u64, book: &Orderbook) -> Bbo {
+        Bbo {
+            ts,
+            bid: book.bids.iter().next_back().map(|(&p, &s)| (book.undiscretize(p), s)),
+            ask: book.asks.iter().next().map(|(&p, &s)| (book.undiscretize(p), s)),
+        }
+    }
+
+    /// same prices and sizes, regardless of time
+    pub fn same_quotes(&self, other: &Bbo) -> bool {
+        self.bid == other.bid && self.ask == other.ask
+    }
+
+    /// convert to csv, empty sides are left blank
+    /// Format:
+    ///     ts,bid,bid_size,ask,ask_size
+    pub fn to_csv(&self) -> String {
+        let side = |level: Option<(f32, f64)>| match level {
+            Some((price, size)) => format!("{},{}", price, size),
+            None => ",".to_owned(),
+        };
+        format!("{},{},{}", self.ts, side(self.bid), side(self.ask))
+    }
+
+    /// convert to json object
+    pub fn as_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Iterator of top of book changes of the book driven by an update stream
+pub struct BboIter<I: Iterator<Item=Update>> {
+    it: I,
+    book: Orderbook,
+    last: Option<Bbo>,
+}
+
+impl<I: Iterator<Item=Update>> BboIter<I> {
+    /// Create a new iterator starting from an empty book
+    pub fn new(it: I, price_decimals: u8) -> Self {
+        Self::with_book(it, Orderbook::with_precision(price_decimals))
+    }
+
+    /// Create a new iterator continuing from `book`, changes are relative to its top
+    pub fn with_book(it: I, book: Orderbook) -> Self {
+        let last = Some(Bbo::of(0, &book));
+        Self { it, book, last }
+    }
+}
+
+impl<I: Iterator<Item=Update>> Iterator for BboIter<I> {
+    type Item = Bbo;
+    fn next(&mut self) -> Option<Self::Item> {
+        for up in self.it.by_ref() {
+            self.book.process_update(&up);
+            let bbo = Bbo::of(up.ts, &self.book);
+            if self.last.map(|last| !last.same_quotes(&bbo)).unwrap_or(true) {
+                self.last = Some(bbo);
+                return Some(bbo);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(ts: u64, is_bid: bool, price: f32, size: f32) -> Update {
+        Update { ts, seq: 0, is_trade: false, is_bid, price, size }
+    }
+
+    #[test]
+    fn should_stream_top_of_book_changes() {
+        let ups = vec![
+            up(1, true, 1., 1.),
+            up(2, false, 3., 1.),
+            // behind the best ask, no change
+            up(3, false, 4., 1.),
+            up(4, true, 1., 2.),
+            up(5, false, 3., 0.),
+        ];
+        let bbos: Vec<Bbo> = BboIter::new(ups.into_iter(), 2).collect();
+        assert_eq!(bbos.iter().map(|b| b.ts).collect::<Vec<_>>(), vec![1, 2, 4, 5]);
+        assert_eq!(bbos[0].ask, None);
+        assert_eq!(bbos[3], Bbo { ts: 5, bid: Some((1., 2.)), ask: Some((4., 1.)) });
+        assert_eq!(bbos[0].to_csv(), "1,1,1,,");
+    }
+}
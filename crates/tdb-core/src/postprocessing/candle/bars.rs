@@ -0,0 +1,179 @@
+use std::io;
+
+use super::{Candle, Sampler};
+use crate::dtf::file_format::scan_files_for_range_for_each;
+use crate::dtf::update::Update;
+use crate::postprocessing::classify::TradeClassifier;
+
+/// sample with a closure, for one-off samplers without a type of their own
+pub struct FnSampler<F: FnMut(&Update) -> bool>(pub F);
+
+impl<F: FnMut(&Update) -> bool> Sampler for FnSampler<F> {
+    fn is_sample(&mut self, trade: &Update) -> bool {
+        (self.0)(trade)
+    }
+    fn reset(&mut self) {}
+}
+
+/// Accumulates trades into candles, a candle closes when the sampler fires
+///
+/// The sampler sees every trade, including the one opening the first candle,
+/// and non-trade updates too unless it is `trades_only`. The trade it fires on
+/// opens the next candle.
+pub struct BarBuilder<S: Sampler> {
+    sampler: S,
+    classifier: TradeClassifier,
+    current_candle: Option<Candle>,
+}
+
+impl<S: Sampler> BarBuilder<S> {
+    /// Create a new builder
+    pub fn new(sampler: S) -> Self {
+        Self {
+            sampler,
+            classifier: TradeClassifier::default(),
+            current_candle: None,
+        }
+    }
+
+    /// Classify trade sides with `classifier` instead of the exchange flag
+    pub fn with_classifier(mut self, classifier: TradeClassifier) -> Self {
+        self.classifier = classifier;
+        self
+    }
+
+    /// feed an update, returns the candle it closed
+    pub fn push(&mut self, up: &Update) -> Option<Candle> {
+        let side = self.classifier.classify(up);
+        if !up.is_trade {
+            if !self.sampler.trades_only() {
+                self.sampler.is_sample(up);
+            }
+            return None;
+        }
+        let is_sample = self.sampler.is_sample(up);
+        match self.current_candle {
+            Some(c) if is_sample => {
+                self.current_candle = Some(Candle::from_trade(up, side));
+                Some(c)
+            }
+            Some(c) => {
+                self.current_candle = Some(c.with_trade(up, side));
+                None
+            }
+            None => {
+                self.current_candle = Some(Candle::from_trade(up, side));
+                None
+            }
+        }
+    }
+
+    /// take the candle that is still open
+    pub fn finish(&mut self) -> Option<Candle> {
+        self.current_candle.take()
+    }
+}
+
+/// Iterator for Bars sampled by any `Sampler`
+pub struct BarsIter<S: Sampler, I: Iterator<Item=Update>> {
+    it: I,
+    builder: BarBuilder<S>,
+    open_bar: bool,
+}
+
+impl<S: Sampler, I: Iterator<Item=Update>> BarsIter<S, I> {
+    /// Create a new iterator closing candles when `sampler` fires
+    pub fn with_sampler(it: I, sampler: S) -> Self {
+        Self {
+            it,
+            builder: BarBuilder::new(sampler),
+            open_bar: true,
+        }
+    }
+
+    /// Classify trade sides with `classifier` instead of the exchange flag
+    pub fn with_classifier(mut self, classifier: TradeClassifier) -> Self {
+        self.builder = self.builder.with_classifier(classifier);
+        self
+    }
+
+    /// Drop the candle still open when the updates run out
+    pub fn closed_only(mut self) -> Self {
+        self.open_bar = false;
+        self
+    }
+}
+
+impl<S: Sampler, I: Iterator<Item=Update>> Iterator for BarsIter<S, I> {
+    type Item = Candle;
+    fn next(&mut self) -> Option<Self::Item> {
+        for up in self.it.by_ref() {
+            if let Some(c) = self.builder.push(&up) {
+                return Some(c);
+            }
+        }
+        if self.open_bar {
+            self.builder.finish()
+        } else {
+            None
+        }
+    }
+}
+
+/// build candles from every matching dtf file under folder in range,
+/// updates are streamed from disk so memory use does not grow with the range
+pub fn scan_bars_for_range<S: Sampler, F: FnMut(Candle)>(
+    folder: &str,
+    symbol: &str,
+    min_ts: u64,
+    max_ts: u64,
+    mut builder: BarBuilder<S>,
+    f: &mut F,
+) -> Result<(), io::Error> {
+    scan_files_for_range_for_each(folder, symbol, min_ts, max_ts, &mut |up| {
+        if let Some(c) = builder.push(up) {
+            f(c);
+        }
+    })?;
+    if let Some(c) = builder.finish() {
+        f(c);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtf::file_format::encode;
+    use crate::postprocessing::candle::tick_bars::TickSampler;
+
+    fn trade(ts: u64, price: f32) -> Update {
+        Update { ts, seq: 0, is_trade: true, is_bid: true, price, size: 1. }
+    }
+
+    #[test]
+    fn should_sample_with_any_sampler() {
+        // one batch per update when written to disk
+        let ups: Vec<Update> = (0..10).map(|i| trade(i * 100_000, i as f32)).collect();
+
+        // new candle whenever the price reaches a multiple of 4
+        let bars: Vec<Candle> = BarsIter::with_sampler(ups.iter().copied(), FnSampler(|up: &Update| up.price as u32 % 4 == 0)).collect();
+        assert_eq!(bars.iter().map(|c| (c.start, c.end)).collect::<Vec<_>>(), vec![(0, 300_000), (400_000, 700_000), (800_000, 900_000)]);
+
+        let boxed: Box<dyn Sampler> = Box::new(TickSampler::new(4));
+        let bars: Vec<Candle> = BarsIter::with_sampler(ups.iter().copied(), boxed).closed_only().collect();
+        assert_eq!(bars.iter().map(|c| c.volume).collect::<Vec<_>>(), vec![4., 4.]);
+
+        let dir = std::env::temp_dir().join("tdb-bars-test");
+        let _ = std::fs::create_dir_all(&dir);
+        let folder = dir.to_str().unwrap();
+        encode(&format!("{}/a.dtf", folder), "bars_test", &ups[..5]).unwrap();
+        encode(&format!("{}/b.dtf", folder), "bars_test", &ups[5..]).unwrap();
+        let mut streamed = vec![];
+        scan_bars_for_range(folder, "bars_test", 0, 1_000_000, BarBuilder::new(TickSampler::new(4)), &mut |c| streamed.push(c)).unwrap();
+        std::fs::remove_dir_all(folder).unwrap();
+        // every update written is in a candle, including the last batch of each file
+        assert_eq!(streamed.iter().map(|c| c.volume).sum::<f32>(), 10.);
+        assert_eq!(streamed, BarsIter::with_sampler(ups.iter().copied(), TickSampler::new(4)).collect::<Vec<_>>());
+    }
+}
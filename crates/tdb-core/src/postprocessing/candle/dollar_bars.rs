@@ -1,4 +1,5 @@
 use super::{Candle, Sampler};
+use super::bars::BarsIter;
 use crate::dtf::update::Update;
 
 /// sample by dollar traded
@@ -35,70 +36,12 @@ impl Sampler for DollarSampler {
 }
 
 /// Iterator for Bars sampled by dollars traded
-pub struct DollarBarsIter<I:Iterator<Item=Update>> {
-    it: I,
-    current_candle: Option<Candle>,
-    sampler: DollarSampler,
-}
+pub type DollarBarsIter<I> = BarsIter<DollarSampler, I>;
 
 impl<I:Iterator<Item=Update>> DollarBarsIter<I> {
-    /// Create a new iterator for time bars
+    /// Create a new iterator for bars sampled by dollars traded
     pub fn new(it: I, dollar_interval: f32) -> Self {
-        Self {
-            it,
-            current_candle: None,
-            sampler: DollarSampler::new(dollar_interval),
-        }
-    }
-}
-
-fn new_candle(trade: Update) -> Candle {
-    Candle {
-        start: trade.ts,
-        end: trade.ts,
-        volume: trade.size,
-        high: trade.price,
-        low: trade.price,
-        close: trade.price,
-        open: trade.price,
-    }
-}
-
-impl<I:Iterator<Item=Update>> Iterator for DollarBarsIter<I> {
-    type Item = Candle;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(trade) = self.it.next() {
-            if !trade.is_trade {
-                continue;
-            }
-
-            if let Some(c) = self.current_candle {
-                if self.sampler.is_sample(&trade) {
-                    self.current_candle = Some(new_candle(trade));
-                    return Some(c);
-                };
-            }
-
-            self.current_candle = if let Some(c) = self.current_candle {
-                Some(Candle {
-                    start: c.start,
-                    end: trade.ts,
-                    volume: c.volume + trade.size,
-                    high: trade.price.max(c.high),
-                    low: trade.price.min(c.low),
-                    close: trade.price,
-                    open: c.open,
-                })
-            } else {
-                Some(new_candle(trade))
-            };
-        }
-        if let Some(x) = self.current_candle {
-            self.current_candle = None;
-            return Some(x)
-        } else {
-            None
-        }
+        BarsIter::with_sampler(it, DollarSampler::new(dollar_interval))
     }
 }
 
@@ -116,11 +59,21 @@ impl DollarBars {
         let v = DollarBarsIter::new(ups.iter().copied(), dollar_interval).collect();
         DollarBars { v }
     }
+
+    /// convert to csv, see `Candle::to_csv` and `Candle::to_csv_extended`
+    pub fn to_csv_with(&self, extended: bool) -> String {
+        let rows: Vec<String> = self.v
+            .iter()
+            .map(|c| if extended { c.to_csv_extended() } else { c.to_csv() })
+            .collect();
+        rows.join("\n")
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postprocessing::candle::TradeFlow;
     use std::f32;
     #[test]
     fn test_dollar_bar() {
@@ -144,6 +97,7 @@ mod tests {
                 low: 0.0,
                 close: 6.0,
                 volume: 21.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 21., trades: 7, notional: 91. },
             }, Candle {
                 start: 7,
                 end: 8,
@@ -152,6 +106,7 @@ mod tests {
                 low: 7.0,
                 close: 8.0,
                 volume: 15.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 15., trades: 2, notional: 113. },
             }, Candle {
                 start: 9,
                 end: 9,
@@ -160,6 +115,7 @@ mod tests {
                 low: 9.0,
                 close: 9.0,
                 volume: 9.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 9., trades: 1, notional: 81. },
             }]}, ret);
     }
 }
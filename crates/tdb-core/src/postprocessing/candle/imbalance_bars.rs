@@ -0,0 +1,142 @@
+use super::Sampler;
+use super::bars::BarsIter;
+use crate::dtf::update::Update;
+use crate::postprocessing::classify::TickRule;
+
+/// what each trade contributes to a bar
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BarKind {
+    /// one per trade
+    Tick,
+    /// trade size
+    Volume,
+    /// price * size
+    Dollar,
+}
+
+impl BarKind {
+    /// contribution of a trade
+    pub fn value(self, trade: &Update) -> f32 {
+        match self {
+            BarKind::Tick => 1.,
+            BarKind::Volume => trade.size,
+            BarKind::Dollar => trade.price * trade.size,
+        }
+    }
+}
+
+/// exponentially weighted moving average, the first value seeds it
+pub(crate) fn ewma(prev: Option<f32>, value: f32, alpha: f32) -> f32 {
+    match prev {
+        Some(prev) => alpha * value + (1. - alpha) * prev,
+        None => value,
+    }
+}
+
+/// sample when the signed flow of the bar exceeds its expected imbalance
+///
+/// A bar closes when |sum(b * v)| >= E[T] * |E[b * v]|, where b is the tick
+/// rule sign and v the contribution of the trade. Both expectations are
+/// exponentially weighted over past bars.
+pub struct ImbalanceSampler {
+    kind: BarKind,
+    alpha: f32,
+    tick_rule: TickRule,
+    /// expected number of trades per bar
+    expected_ticks: f32,
+    /// expected signed value per trade, unknown until the first bar closes
+    expected_imbalance: Option<f32>,
+    ticks: u32,
+    theta: f32,
+    ready: bool,
+    initial_ticks: f32,
+}
+
+impl ImbalanceSampler {
+    /// create a new imbalance sampler
+    ///
+    /// `expected_ticks` is the length of the first bar, `alpha` the weight of
+    /// the latest bar in the moving averages
+    pub fn new(kind: BarKind, expected_ticks: f32, alpha: f32) -> Self {
+        Self {
+            kind,
+            alpha,
+            tick_rule: TickRule::new(),
+            expected_ticks,
+            expected_imbalance: None,
+            ticks: 0,
+            theta: 0.,
+            ready: false,
+            initial_ticks: expected_ticks,
+        }
+    }
+
+    /// current threshold of |sum(b * v)|
+    pub fn threshold(&self) -> f32 {
+        let per_tick = self.expected_imbalance
+            .unwrap_or_else(|| if self.ticks == 0 { 0. } else { self.theta / self.ticks as f32 });
+        self.expected_ticks * per_tick.abs()
+    }
+
+    fn close_bar(&mut self) {
+        let ticks = self.ticks as f32;
+        self.expected_imbalance = Some(ewma(self.expected_imbalance, self.theta / ticks, self.alpha));
+        self.expected_ticks = ewma(Some(self.expected_ticks), ticks, self.alpha);
+        self.ticks = 0;
+        self.theta = 0.;
+    }
+}
+
+impl Sampler for ImbalanceSampler {
+    fn reset(&mut self) {
+        *self = Self::new(self.kind, self.initial_ticks, self.alpha);
+    }
+    fn is_sample(&mut self, trade: &Update) -> bool {
+        let is_sample = self.ready;
+        if is_sample {
+            self.close_bar();
+            self.ready = false;
+        }
+
+        self.ticks += 1;
+        self.theta += self.tick_rule.sign(trade.price) * self.kind.value(trade);
+        self.ready = self.theta.abs() >= self.threshold();
+
+        is_sample
+    }
+}
+
+/// Iterator for tick, volume or dollar imbalance bars
+pub type ImbalanceBarsIter<I> = BarsIter<ImbalanceSampler, I>;
+
+impl<I:Iterator<Item=Update>> ImbalanceBarsIter<I> {
+    /// Create a new iterator for tick, volume or dollar imbalance bars
+    pub fn new(it: I, kind: BarKind, expected_ticks: f32, alpha: f32) -> Self {
+        BarsIter::with_sampler(it, ImbalanceSampler::new(kind, expected_ticks, alpha))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postprocessing::candle::Candle;
+
+    fn trade(ts: u64, price: f32, size: f32) -> Update {
+        Update { ts, seq: 0, is_trade: true, is_bid: true, price, size }
+    }
+
+    #[test]
+    fn test_imbalance_bar() {
+        // one-sided flow closes a bar every `expected_ticks` trades
+        let trades = (0..10).map(|i| trade(i, i as f32, 1.));
+        let bars: Vec<Candle> = ImbalanceBarsIter::new(trades, BarKind::Tick, 3., 0.5).collect();
+        assert_eq!(bars.iter().map(|c| (c.start, c.end)).collect::<Vec<_>>(),
+            vec![(0, 2), (3, 5), (6, 8), (9, 9)]);
+
+        // alternating ticks cancel out, the bar stays open
+        let trades = (0..10).map(|i| trade(i, (i % 2) as f32, 1.));
+        let bars: Vec<Candle> = ImbalanceBarsIter::new(trades, BarKind::Volume, 2., 0.5).collect();
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[1].volume, 8.);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::dtf;
 
+/// candles sampled by any `Sampler`, streamed from memory or disk
+pub mod bars;
 /// candles sampled by time
 pub mod time_bars;
 /// candles sampled by volume
@@ -8,9 +10,14 @@ pub mod volume_bars;
 pub mod tick_bars;
 /// candles sampled by dollar traded
 pub mod dollar_bars;
+/// candles sampled by tick, volume or dollar imbalance
+pub mod imbalance_bars;
+/// candles sampled by tick, volume or dollar runs
+pub mod run_bars;
 /// plot candlesticks in terminal
 pub mod candlestick_graph;
 use self::dtf::update::Update;
+use crate::postprocessing::classify::Side;
 
 type Time = u64;
 type Price = f32;
@@ -34,11 +41,88 @@ pub struct Candle {
     pub close: Price,
     /// volume
     pub volume: Volume,
+    /// signed volume, trade count and notional
+    pub flow: TradeFlow,
 }
 
 impl Eq for Candle {}
 
+#[derive(PartialOrd, PartialEq, Clone, Copy, Debug, Default)]
+/// trades of a candle by side
+pub struct TradeFlow {
+    /// volume of buyer initiated trades
+    pub buy_volume: Volume,
+    /// volume of seller initiated trades
+    pub sell_volume: Volume,
+    /// number of trades
+    pub trades: u32,
+    /// sum of price * size
+    pub notional: f32,
+}
+
+impl TradeFlow {
+    /// flow with one more trade, trades of unknown side only count in `trades` and `notional`
+    pub fn with_trade(self, trade: &Update, side: Option<Side>) -> TradeFlow {
+        let mut flow = self;
+        match side {
+            Some(Side::Buy) => flow.buy_volume += trade.size,
+            Some(Side::Sell) => flow.sell_volume += trade.size,
+            None => (),
+        }
+        flow.trades += 1;
+        flow.notional += trade.price * trade.size;
+        flow
+    }
+
+    /// combined flow of two candles
+    pub fn merge(self, other: &TradeFlow) -> TradeFlow {
+        TradeFlow {
+            buy_volume: self.buy_volume + other.buy_volume,
+            sell_volume: self.sell_volume + other.sell_volume,
+            trades: self.trades + other.trades,
+            notional: self.notional + other.notional,
+        }
+    }
+}
+
 impl Candle {
+    /// candle of a single trade
+    pub fn from_trade(trade: &Update, side: Option<Side>) -> Candle {
+        Candle {
+            start: trade.ts,
+            end: trade.ts,
+            volume: trade.size,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            open: trade.price,
+            flow: TradeFlow::default().with_trade(trade, side),
+        }
+    }
+
+    /// candle extended by a trade
+    pub fn with_trade(&self, trade: &Update, side: Option<Side>) -> Candle {
+        Candle {
+            start: self.start,
+            end: trade.ts,
+            volume: self.volume + trade.size,
+            high: trade.price.max(self.high),
+            low: trade.price.min(self.low),
+            close: trade.price,
+            open: self.open,
+            flow: self.flow.with_trade(trade, side),
+        }
+    }
+
+    /// volume weighted average price, the close price without volume
+    pub fn vwap(&self) -> Price {
+        if self.volume > 0. {
+            self.flow.notional / self.volume
+        } else {
+            self.close
+        }
+    }
+
     /// convert to csv
     /// Format:
     ///     S,E,O,H,L,C,V
@@ -54,6 +138,34 @@ impl Candle {
             self.volume
         )
     }
+
+    /// convert to csv with trade flow
+    /// Format:
+    ///     S,E,O,H,L,C,V,BV,SV,N,VWAP
+    pub fn to_csv_extended(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.to_csv(),
+            self.flow.buy_volume,
+            self.flow.sell_volume,
+            self.flow.trades,
+            self.vwap()
+        )
+    }
+
+    /// convert to json object
+    pub fn as_json(&self) -> String {
+        format!(
+            r#"{{"start":{},"end":{},"open":{},"high":{},"low":{},"close":{},"volume":{}}}"#,
+            self.start,
+            self.end,
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.volume
+        )
+    }
 }
 
 /// draw a list of updates in the terminal
@@ -69,6 +181,10 @@ pub trait Sampler {
     fn is_sample(&mut self, update: &Update) -> bool;
     /// reset sampler state
     fn reset(&mut self);
+    /// whether only trades are fed to `is_sample`, otherwise it sees every update
+    fn trades_only(&self) -> bool {
+        true
+    }
 }
 
 use std::ops::DerefMut;
@@ -79,4 +195,7 @@ impl<T: DerefMut<Target=dyn Sampler>> Sampler for T {
     fn reset(&mut self) {
         self.deref_mut().reset()
     }
+    fn trades_only(&self) -> bool {
+        self.deref().trades_only()
+    }
 }
@@ -0,0 +1,125 @@
+use super::Sampler;
+use super::bars::BarsIter;
+use super::imbalance_bars::{BarKind, ewma};
+use crate::dtf::update::Update;
+use crate::postprocessing::classify::TickRule;
+
+/// sample when the longer side of the bar exceeds its expected run
+///
+/// A bar closes when max(sum of buys, sum of sells) >= E[T] * max(E[buy], E[sell]),
+/// where trades are signed by the tick rule and E[buy], E[sell] are the
+/// expected values per trade. Expectations are exponentially weighted over
+/// past bars.
+pub struct RunSampler {
+    kind: BarKind,
+    alpha: f32,
+    tick_rule: TickRule,
+    /// expected number of trades per bar
+    expected_ticks: f32,
+    /// expected (buy, sell) value per trade, unknown until the first bar closes
+    expected_run: Option<(f32, f32)>,
+    ticks: u32,
+    buys: f32,
+    sells: f32,
+    ready: bool,
+    initial_ticks: f32,
+}
+
+impl RunSampler {
+    /// create a new run sampler
+    ///
+    /// `expected_ticks` is the length of the first bar, `alpha` the weight of
+    /// the latest bar in the moving averages
+    pub fn new(kind: BarKind, expected_ticks: f32, alpha: f32) -> Self {
+        Self {
+            kind,
+            alpha,
+            tick_rule: TickRule::new(),
+            expected_ticks,
+            expected_run: None,
+            ticks: 0,
+            buys: 0.,
+            sells: 0.,
+            ready: false,
+            initial_ticks: expected_ticks,
+        }
+    }
+
+    /// current threshold of the longer run
+    pub fn threshold(&self) -> f32 {
+        let (buy, sell) = self.expected_run.unwrap_or_else(|| {
+            let ticks = self.ticks.max(1) as f32;
+            (self.buys / ticks, self.sells / ticks)
+        });
+        self.expected_ticks * buy.max(sell)
+    }
+
+    fn close_bar(&mut self) {
+        let ticks = self.ticks as f32;
+        let (buy, sell) = (self.buys / ticks, self.sells / ticks);
+        self.expected_run = Some(match self.expected_run {
+            Some((b, s)) => (ewma(Some(b), buy, self.alpha), ewma(Some(s), sell, self.alpha)),
+            None => (buy, sell),
+        });
+        self.expected_ticks = ewma(Some(self.expected_ticks), ticks, self.alpha);
+        self.ticks = 0;
+        self.buys = 0.;
+        self.sells = 0.;
+    }
+}
+
+impl Sampler for RunSampler {
+    fn reset(&mut self) {
+        *self = Self::new(self.kind, self.initial_ticks, self.alpha);
+    }
+    fn is_sample(&mut self, trade: &Update) -> bool {
+        let is_sample = self.ready;
+        if is_sample {
+            self.close_bar();
+            self.ready = false;
+        }
+
+        self.ticks += 1;
+        let value = self.kind.value(trade);
+        if self.tick_rule.sign(trade.price) > 0. {
+            self.buys += value;
+        } else {
+            self.sells += value;
+        }
+        self.ready = self.buys.max(self.sells) >= self.threshold();
+
+        is_sample
+    }
+}
+
+/// Iterator for tick, volume or dollar run bars
+pub type RunBarsIter<I> = BarsIter<RunSampler, I>;
+
+impl<I:Iterator<Item=Update>> RunBarsIter<I> {
+    /// Create a new iterator for tick, volume or dollar run bars
+    pub fn new(it: I, kind: BarKind, expected_ticks: f32, alpha: f32) -> Self {
+        BarsIter::with_sampler(it, RunSampler::new(kind, expected_ticks, alpha))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postprocessing::candle::Candle;
+
+    #[test]
+    fn test_run_bar() {
+        // unlike imbalance bars, alternating flow still builds runs on both sides
+        let trades = (0..10).map(|i| Update {
+            ts: i,
+            seq: 0,
+            is_trade: true,
+            is_bid: true,
+            price: (i % 2) as f32,
+            size: 1.,
+        });
+        let bars: Vec<Candle> = RunBarsIter::new(trades, BarKind::Tick, 2., 0.5).collect();
+        assert_eq!(bars.iter().map(|c| (c.start, c.end)).collect::<Vec<_>>(),
+            vec![(0, 1), (2, 4), (5, 7), (8, 9)]);
+    }
+}
@@ -1,10 +1,13 @@
 use super::{Candle, Sampler};
+use super::bars::BarsIter;
 use crate::dtf::update::Update;
 
-/// sample by fixed number of ticks
+/// sample by fixed number of ticks, every update counts as a tick unless
+/// created with `TickSampler::trades`
 pub struct TickSampler {
     interval: u32,
     elapsed: u32,
+    trades_only: bool,
 }
 
 impl TickSampler {
@@ -13,6 +16,15 @@ impl TickSampler {
         Self {
             elapsed: 0,
             interval,
+            trades_only: false,
+        }
+    }
+
+    /// create a tick sampler counting trades only
+    pub fn trades(interval: u32) -> Self {
+        Self {
+            trades_only: true,
+            ..Self::new(interval)
         }
     }
 }
@@ -31,75 +43,18 @@ impl Sampler for TickSampler {
             false
         }
     }
+    fn trades_only(&self) -> bool {
+        self.trades_only
+    }
 }
 
 /// Iterator for Bars sampled by fixed number of tick
-pub struct TickBarsIter<I:Iterator<Item=Update>> {
-    it: I,
-    current_candle: Option<Candle>,
-    sampler: TickSampler,
-}
+pub type TickBarsIter<I> = BarsIter<TickSampler, I>;
 
 impl<I:Iterator<Item=Update>> TickBarsIter<I> {
-    /// Create a new iterator for time bars
+    /// Create a new iterator for tick bars
     pub fn new(it: I, tick_interval: u32) -> Self {
-        Self {
-            it,
-            current_candle: None,
-            sampler: TickSampler::new(tick_interval),
-        }
-    }
-}
-
-fn new_candle(trade: Update) -> Candle {
-    Candle {
-        start: trade.ts,
-        end: trade.ts,
-        volume: trade.size,
-        high: trade.price,
-        low: trade.price,
-        close: trade.price,
-        open: trade.price,
-    }
-}
-
-impl<I:Iterator<Item=Update>> Iterator for TickBarsIter<I> {
-    type Item = Candle;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(trade) = self.it.next() {
-            let is_sample = self.sampler.is_sample(&trade);
-            if !trade.is_trade {
-                continue;
-            }
-
-            if let Some(c) = self.current_candle {
-                if is_sample {
-                    self.current_candle = Some(new_candle(trade));
-                    return Some(c);
-                };
-            }
-
-            self.current_candle = if let Some(c) = self.current_candle {
-                Some(Candle {
-                    start: c.start,
-                    end: trade.ts,
-                    volume: c.volume + trade.size,
-                    high: trade.price.max(c.high),
-                    low: trade.price.min(c.low),
-                    close: trade.price,
-                    open: c.open,
-                })
-            } else {
-                Some(new_candle(trade))
-            };
-
-        }
-        if let Some(x) = self.current_candle {
-            self.current_candle = None;
-            return Some(x)
-        } else {
-            None
-        }
+        BarsIter::with_sampler(it, TickSampler::new(tick_interval))
     }
 }
 
@@ -116,11 +71,21 @@ impl TickBars {
         let v = TickBarsIter::new(ups.iter().copied(), tick_interval).collect();
         TickBars { v }
     }
+
+    /// convert to csv, see `Candle::to_csv` and `Candle::to_csv_extended`
+    pub fn to_csv_with(&self, extended: bool) -> String {
+        let rows: Vec<String> = self.v
+            .iter()
+            .map(|c| if extended { c.to_csv_extended() } else { c.to_csv() })
+            .collect();
+        rows.join("\n")
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postprocessing::candle::TradeFlow;
     use std::f32;
     #[test]
     fn test_tick_bar() {
@@ -144,6 +109,7 @@ mod tests {
                 low: 0.0,
                 close: 2.0,
                 volume: 3.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 3., trades: 3, notional: 5. },
             }, Candle {
                 start: 3,
                 end: 5,
@@ -152,6 +118,7 @@ mod tests {
                 low: 3.0,
                 close: 5.0,
                 volume: 12.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 12., trades: 3, notional: 50. },
             }, Candle {
                 start: 6,
                 end: 8,
@@ -160,6 +127,7 @@ mod tests {
                 low: 6.0,
                 close: 8.0,
                 volume: 21.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 21., trades: 3, notional: 149. },
             }, Candle {
                 start: 9,
                 end: 9,
@@ -168,6 +136,24 @@ mod tests {
                 low: 9.0,
                 close: 9.0,
                 volume: 9.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 9., trades: 1, notional: 81. },
             }]}, ret);
     }
+
+    #[test]
+    fn should_count_trades_only() {
+        let ups = (0..10).map(|i| Update {
+            is_trade: i % 2 == 0,
+            is_bid: true,
+            price: i as f32,
+            size: 1.,
+            ts: i,
+            seq: 0,
+        });
+        // every update counts, each trade ends up alone
+        let every: Vec<(u64, f32)> = TickBarsIter::new(ups.clone(), 2).map(|c| (c.start, c.volume)).collect();
+        assert_eq!(every, vec![(0, 1.), (2, 1.), (4, 1.), (6, 1.), (8, 1.)]);
+        let trades: Vec<(u64, f32)> = BarsIter::with_sampler(ups, TickSampler::trades(2)).map(|c| (c.start, c.volume)).collect();
+        assert_eq!(trades, vec![(0, 2.), (4, 2.), (8, 1.)]);
+    }
 }
@@ -1,6 +1,8 @@
 use std::collections::HashSet;
-use super::{Candle, Time, Scale, Sampler};
+use super::{Candle, Time, Scale, Sampler, TradeFlow};
+use super::bars::BarsIter;
 use crate::dtf::update::Update;
+use crate::postprocessing::classify::TradeClassifier;
 use crate::utils::fill_digits;
 use indexmap::IndexMap;
 
@@ -43,10 +45,11 @@ impl Sampler for TimeSampler {
 }
 
 /// Iterator for Bars sampled by time, default is 1 minute bar
+///
+/// Candles start and end at the floored ts in seconds, the last candle is
+/// only emitted once a trade of the next interval arrives.
 pub struct TimeBarsIter<I:Iterator<Item=Update>> {
-    it: I,
-    current_candle: Option<Candle>,
-    sampler: TimeSampler,
+    bars: BarsIter<TimeSampler, I>,
     seconds: u64,
 }
 
@@ -54,57 +57,28 @@ impl<I:Iterator<Item=Update>> TimeBarsIter<I> {
     /// Create a new iterator for time bars
     pub fn new(it: I, seconds: u64) -> Self {
         Self {
-            it,
-            current_candle: None,
-            sampler: TimeSampler::new(seconds),
+            bars: BarsIter::with_sampler(it, TimeSampler::new(seconds)).closed_only(),
             seconds,
         }
     }
-}
 
-fn new_candle(t: Time, trade: Update) -> Candle {
-    Candle {
-        start: t,
-        end: t,
-        volume: trade.size,
-        high: trade.price,
-        low: trade.price,
-        close: trade.price,
-        open: trade.price,
+    /// Classify trade sides with `classifier` instead of the exchange flag
+    pub fn with_classifier(mut self, classifier: TradeClassifier) -> Self {
+        self.bars = self.bars.with_classifier(classifier);
+        self
     }
 }
 
 impl<I:Iterator<Item=Update>> Iterator for TimeBarsIter<I> {
     type Item = Candle;
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(trade) = self.it.next() {
-            if !trade.is_trade {
-                continue;
-            }
-
-            let ts = (fill_digits(trade.ts) / 1000 / self.seconds * self.seconds) as Time; // floor(ts)
-
-            self.current_candle = if let Some(c) = &self.current_candle {
-                if self.sampler.is_sample(&trade) {
-                    let c = *c;
-                    self.current_candle = Some(new_candle(ts, trade));
-                    return Some(c);
-                } else {
-                    Some(Candle {
-                        start: ts,
-                        end: ts,
-                        volume: c.volume + trade.size,
-                        high: trade.price.max(c.high),
-                        low: trade.price.min(c.low),
-                        close: trade.price,
-                        open: c.open,
-                    })
-                }
-            } else {
-                Some(new_candle(ts, trade))
-            };
-        }
-        None
+        let c = self.bars.next()?;
+        let ts = (fill_digits(c.start) / 1000 / self.seconds * self.seconds) as Time; // floor(ts)
+        Some(Candle {
+            start: ts,
+            end: ts,
+            ..c
+        })
     }
 }
 
@@ -118,13 +92,21 @@ pub struct TimeBars {
 impl<'a> From<&'a [Update]> for TimeBars {
     /// Generate a vector of 1-min candles from Updates
     fn from(ups: &[Update]) -> TimeBars {
-        let candles = TimeBarsIter::new(ups.iter().copied(), 60).map(|c| (c.start, c)).collect();
-        return TimeBars::new(candles, 1);
+        TimeBars::from_updates_with(ups, TradeClassifier::default())
     }
 }
 
 impl TimeBars {
 
+    /// Generate a vector of 1-min candles from Updates, trade sides given by `classifier`
+    pub fn from_updates_with(ups: &[Update], classifier: TradeClassifier) -> TimeBars {
+        let candles = TimeBarsIter::new(ups.iter().copied(), 60)
+            .with_classifier(classifier)
+            .map(|c| (c.start, c))
+            .collect();
+        TimeBars::new(candles, 1)
+    }
+
     /// Get total length of candles
     pub fn get_size(&self) -> usize {
         self.v.len()
@@ -144,9 +126,18 @@ impl TimeBars {
     /// format is
     ///     T,O,H,L,C,V
     pub fn to_csv(&self) -> String {
+        self.to_csv_with(false)
+    }
+
+    /// convert TimeBars vector to csv, with buy/sell volume, trade count
+    /// and vwap of `Candle::to_csv_extended` when `extended`
+    pub fn to_csv_with(&self, extended: bool) -> String {
         let csvs: Vec<String> = self.v
             .iter()
-            .map(|(key, candle)| format!("{},{}", key, candle.to_csv()))
+            .map(|(key, candle)| {
+                let csv = if extended { candle.to_csv_extended() } else { candle.to_csv() };
+                format!("{},{}", key, csv)
+            })
             .collect();
 
         csvs.join("\n")
@@ -210,6 +201,7 @@ impl TimeBars {
                             low: last_close,
                             open: last_close,
                             close: last_close,
+                            flow: TradeFlow::default(),
                         },
                     );
                     cur += 60;
@@ -263,6 +255,7 @@ impl TimeBars {
         let mut highacc = 0.;
         let mut lowacc = 0.;
         let mut volumeacc = 0.;
+        let mut flowacc = TradeFlow::default();
 
         let mut aligned = false;
         let mut i = 0;
@@ -292,6 +285,7 @@ impl TimeBars {
                 highacc = row.high;
                 lowacc = row.low;
                 volumeacc = row.volume;
+                flowacc = row.flow;
                 i += 1;
                 continue;
             }
@@ -300,6 +294,7 @@ impl TimeBars {
             highacc = highacc.max(row.high);
             lowacc = lowacc.min(row.low);
             volumeacc += row.volume;
+            flowacc = flowacc.merge(&row.flow);
 
             // if it's the last minute, insert
             if (i % (new_scale as usize)) == ((new_scale as usize) - 1) {
@@ -311,6 +306,7 @@ impl TimeBars {
                     low: lowacc,
                     close: row.close,
                     volume: volumeacc,
+                    flow: flowacc,
                 };
 
                 res.insert(startacc, candle);
@@ -409,6 +405,7 @@ mod tests {
             high: 0.,
             low: 0.,
             volume: 0.,
+            flow: TradeFlow::default(),
         };
         let target = "0,0,0,0,0,0,0";
         assert_eq!(inp.to_csv(), target);
@@ -429,6 +426,7 @@ mod tests {
                     high: 2.,
                     low: 0.,
                     volume: 1.,
+                    flow: TradeFlow::default(),
                 },
             );
         }
@@ -445,6 +443,7 @@ mod tests {
                 low: 0.,
                 close: 1.,
                 volume: 60.,
+                flow: TradeFlow::default(),
             },
         );
 
@@ -516,6 +515,7 @@ mod tests {
                     high: 2.,
                     low: 0.,
                     volume: 1.,
+                    flow: TradeFlow::default(),
                 },
             );
         }
@@ -572,6 +572,7 @@ mod tests {
                     high: 0.,
                     low: 0.,
                     volume: 0.,
+                    flow: TradeFlow::default(),
                 },
             );
         }
@@ -592,6 +593,7 @@ mod tests {
                 high: 0.,
                 low: 0.,
                 volume: 0.,
+                flow: TradeFlow::default(),
             },
         );
         let g = TimeBars {
@@ -618,6 +620,7 @@ mod tests {
                     high: 0.,
                     low: 0.,
                     volume: 0.,
+                    flow: TradeFlow::default(),
                 },
             );
         }
@@ -652,6 +655,7 @@ mod tests {
                     high: i as Price,
                     low: i as Price,
                     volume: i as Price,
+                    flow: TradeFlow::default(),
                 },
             );
         }
@@ -1,4 +1,5 @@
 use super::{Candle, Sampler};
+use super::bars::BarsIter;
 use crate::dtf::update::Update;
 
 /// sample by volume traded
@@ -34,71 +35,12 @@ impl Sampler for VolumeSampler {
 }
 
 /// Iterator for Bars sampled by volume
-pub struct VolumeBarsIter<I:Iterator<Item=Update>> {
-    it: I,
-    current_candle: Option<Candle>,
-    sampler: VolumeSampler,
-}
+pub type VolumeBarsIter<I> = BarsIter<VolumeSampler, I>;
 
 impl<I:Iterator<Item=Update>> VolumeBarsIter<I> {
-    /// Create a new iterator for time bars
+    /// Create a new iterator for bars sampled by volume
     pub fn new(it: I, vol_interval: f32) -> Self {
-        Self {
-            it,
-            current_candle: None,
-            sampler: VolumeSampler::new(vol_interval),
-        }
-    }
-}
-
-fn new_candle(trade: Update) -> Candle {
-    Candle {
-        start: trade.ts,
-        end: trade.ts,
-        volume: trade.size,
-        high: trade.price,
-        low: trade.price,
-        close: trade.price,
-        open: trade.price,
-    }
-}
-
-impl<I:Iterator<Item=Update>> Iterator for VolumeBarsIter<I> {
-    type Item = Candle;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(trade) = self.it.next() {
-            if !trade.is_trade {
-                continue;
-            }
-
-            if let Some(c) = self.current_candle {
-                if self.sampler.is_sample(&trade) {
-                    self.current_candle = Some(new_candle(trade));
-                    return Some(c);
-                };
-            }
-
-            self.current_candle = if let Some(c) = self.current_candle {
-                Some(Candle {
-                    start: c.start,
-                    end: trade.ts,
-                    volume: c.volume + trade.size,
-                    high: trade.price.max(c.high),
-                    low: trade.price.min(c.low),
-                    close: trade.price,
-                    open: c.open,
-                })
-            } else {
-                Some(new_candle(trade))
-            };
-
-        }
-        if let Some(x) = self.current_candle {
-            self.current_candle = None;
-            return Some(x)
-        } else {
-            None
-        }
+        BarsIter::with_sampler(it, VolumeSampler::new(vol_interval))
     }
 }
 
@@ -119,11 +61,21 @@ impl VolumeBars {
         let v = VolumeBarsIter::new(ups.iter().copied(), vol_interval).collect();
         VolumeBars { v }
     }
+
+    /// convert to csv, see `Candle::to_csv` and `Candle::to_csv_extended`
+    pub fn to_csv_with(&self, extended: bool) -> String {
+        let rows: Vec<String> = self.v
+            .iter()
+            .map(|c| if extended { c.to_csv_extended() } else { c.to_csv() })
+            .collect();
+        rows.join("\n")
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::postprocessing::candle::TradeFlow;
     use std::f32;
     #[test]
     fn test_vol_bar() {
@@ -147,6 +99,7 @@ mod tests {
                 low: 0.0,
                 close: 8.0,
                 volume: 36.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 36., trades: 9, notional: 204. },
             }, Candle {
                 start: 9,
                 end: 9,
@@ -155,6 +108,7 @@ mod tests {
                 low: 9.0,
                 close: 9.0,
                 volume: 9.0,
+                flow: TradeFlow { buy_volume: 0., sell_volume: 9., trades: 1, notional: 81. },
             }]}, ret);
     }
 }
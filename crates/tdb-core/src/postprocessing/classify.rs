@@ -0,0 +1,163 @@
+//! Trade side classification
+//!
+//! Whether a trade's `is_bid` marks the buyer or the resting order depends on
+//! the exchange, so the side can instead be inferred from prices: the tick
+//! rule compares with the previous trade, the quote rule with the midprice of
+//! the book reconstructed from the same update stream.
+
+use crate::dtf::update::Update;
+use crate::postprocessing::orderbook::Orderbook;
+
+/// Aggressor side of a trade
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// buyer initiated
+    Buy,
+    /// seller initiated
+    Sell,
+}
+
+/// How trade sides are determined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Classification {
+    /// `is_bid` trades hit the bid, so they are sells
+    Exchange,
+    /// `is_bid` trades are buys
+    ExchangeInverted,
+    /// upticks are buys, downticks sells, unchanged prices keep the last side
+    TickRule,
+    /// above the midprice are buys, below sells, unknown at the midprice
+    QuoteRule,
+    /// quote rule, tick rule at the midprice (Lee & Ready)
+    LeeReady,
+}
+
+/// Classify trades by the tick rule
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TickRule {
+    last_price: Option<f32>,
+    last_side: Option<Side>,
+}
+
+impl TickRule {
+    /// Create a new tick rule
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// side of a trade at `price`, unknown until the price first changes
+    pub fn side(&mut self, price: f32) -> Option<Side> {
+        if let Some(last) = self.last_price {
+            if price > last {
+                self.last_side = Some(Side::Buy);
+            } else if price < last {
+                self.last_side = Some(Side::Sell);
+            }
+        }
+        self.last_price = Some(price);
+        self.last_side
+    }
+
+    /// +1 for buys, -1 for sells, trades before the first price change count as buys
+    pub fn sign(&mut self, price: f32) -> f32 {
+        match self.side(price) {
+            Some(Side::Sell) => -1.,
+            _ => 1.,
+        }
+    }
+}
+
+/// Classify trades of an update stream
+#[derive(Clone, Debug)]
+pub struct TradeClassifier {
+    method: Classification,
+    tick_rule: TickRule,
+    book: Orderbook,
+}
+
+impl Default for TradeClassifier {
+    fn default() -> Self {
+        TradeClassifier::new(Classification::Exchange, 8)
+    }
+}
+
+impl TradeClassifier {
+    /// Create a new classifier, `price_decimals` is the precision of the book used by the quote rule
+    pub fn new(method: Classification, price_decimals: u8) -> Self {
+        Self {
+            method,
+            tick_rule: TickRule::new(),
+            book: Orderbook::with_precision(price_decimals),
+        }
+    }
+
+    /// feed an update, returns the side of trades
+    pub fn classify(&mut self, up: &Update) -> Option<Side> {
+        let uses_book = self.method == Classification::QuoteRule || self.method == Classification::LeeReady;
+        if !up.is_trade {
+            if uses_book {
+                self.book.process_update(up);
+            }
+            return None;
+        }
+
+        let tick = self.tick_rule.side(up.price);
+        let quote = if uses_book {
+            let side = self.book.midprice().and_then(|mid| {
+                if up.price > mid {
+                    Some(Side::Buy)
+                } else if up.price < mid {
+                    Some(Side::Sell)
+                } else {
+                    None
+                }
+            });
+            self.book.process_update(up);
+            side
+        } else {
+            None
+        };
+
+        match self.method {
+            Classification::Exchange => Some(if up.is_bid { Side::Sell } else { Side::Buy }),
+            Classification::ExchangeInverted => Some(if up.is_bid { Side::Buy } else { Side::Sell }),
+            Classification::TickRule => tick,
+            Classification::QuoteRule => quote,
+            Classification::LeeReady => quote.or(tick),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(is_trade: bool, is_bid: bool, price: f32) -> Update {
+        Update { ts: 0, seq: 0, is_trade, is_bid, price, size: 1. }
+    }
+
+    #[test]
+    fn should_classify_trades() {
+        let ups = [
+            up(false, true, 9.),
+            up(false, false, 11.),
+            up(true, true, 10.),
+            up(true, false, 10.5),
+            up(true, true, 9.5),
+            up(true, false, 10.),
+        ];
+        let sides = |method| {
+            let mut classifier = TradeClassifier::new(method, 2);
+            ups.iter()
+                .map(|up| (up.is_trade, classifier.classify(up)))
+                .filter(|&(is_trade, _)| is_trade)
+                .map(|(_, side)| side)
+                .collect::<Vec<_>>()
+        };
+        use self::Side::*;
+        assert_eq!(sides(Classification::Exchange), vec![Some(Sell), Some(Buy), Some(Sell), Some(Buy)]);
+        assert_eq!(sides(Classification::TickRule), vec![None, Some(Buy), Some(Sell), Some(Buy)]);
+        assert_eq!(sides(Classification::QuoteRule), vec![None, Some(Buy), Some(Sell), None]);
+        assert_eq!(sides(Classification::LeeReady), vec![None, Some(Buy), Some(Sell), Some(Buy)]);
+    }
+}
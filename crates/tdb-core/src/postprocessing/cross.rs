@@ -0,0 +1,295 @@
+//! Analytics across several symbols
+//!
+//! Update streams of several symbols are merged by timestamp and replayed
+//! into one book per symbol. The books are sampled on a common time grid,
+//! steps without updates repeat the last quotes, and synthetic instruments
+//! (cross-exchange spreads, triangular ratios) and midprice return statistics
+//! (rolling correlation, lead-lag) are computed from the samples.
+
+use std::io;
+use std::iter::Peekable;
+
+use crate::dtf::file_format::scan_files_for_range_for_each;
+use crate::dtf::update::Update;
+use crate::postprocessing::orderbook::Orderbook;
+
+/// Iterator merging update streams by (ts, seq), yields the index of the stream with each update
+pub struct MergeIter<I: Iterator<Item=Update>> {
+    streams: Vec<Peekable<I>>,
+}
+
+impl<I: Iterator<Item=Update>> MergeIter<I> {
+    /// Create a new iterator, each stream must be sorted
+    pub fn new<S: IntoIterator<Item=I>>(streams: S) -> Self {
+        Self { streams: streams.into_iter().map(Iterator::peekable).collect() }
+    }
+}
+
+impl<I: Iterator<Item=Update>> Iterator for MergeIter<I> {
+    type Item = (usize, Update);
+    fn next(&mut self) -> Option<Self::Item> {
+        // ties go to the stream listed first
+        let mut best: Option<(usize, Update)> = None;
+        for (i, stream) in self.streams.iter_mut().enumerate() {
+            if let Some(&up) = stream.peek() {
+                if best.map(|(_, b)| up < b).unwrap_or(true) {
+                    best = Some((i, up));
+                }
+            }
+        }
+        let (i, _) = best?;
+        self.streams[i].next().map(|up| (i, up))
+    }
+}
+
+/// read the updates of every symbol under folder in range, in the order of `symbols`
+pub fn scan_symbols(folder: &str, symbols: &[&str], min_ts: u64, max_ts: u64) -> Result<Vec<Vec<Update>>, io::Error> {
+    symbols.iter()
+        .map(|symbol| {
+            let mut ups = vec![];
+            scan_files_for_range_for_each(folder, symbol, min_ts, max_ts, &mut |up| ups.push(*up))?;
+            Ok(ups)
+        })
+        .collect()
+}
+
+/// Best bid and ask of a symbol
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quote {
+    /// best bid price
+    pub bid: f64,
+    /// best ask price
+    pub ask: f64,
+}
+
+impl Quote {
+    /// (bid + ask) / 2
+    pub fn mid(&self) -> f64 {
+        (self.bid + self.ask) / 2.
+    }
+}
+
+/// Quotes of every symbol at the end of a step
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// start of the step
+    pub ts: u64,
+    /// quote per symbol, None until both sides of its book are known
+    pub quotes: Vec<Option<Quote>>,
+}
+
+impl Sample {
+    /// midprice of symbol `i`
+    pub fn mid(&self, i: usize) -> Option<f64> {
+        self.quotes.get(i).cloned().flatten().map(|q| q.mid())
+    }
+}
+
+/// replay merged updates of `n` symbols and sample their quotes every `step` ms
+pub fn sample_quotes<I: Iterator<Item=(usize, Update)>>(it: I, n: usize, price_decimals: u8, step: u64) -> Vec<Sample> {
+    let step = step.max(1);
+    let mut books = vec![Orderbook::with_precision(price_decimals); n];
+    let quotes = |books: &[Orderbook]| books.iter()
+        .map(|book| book.top().map(|((bid, _), (ask, _))| Quote { bid: f64::from(bid), ask: f64::from(ask) }))
+        .collect::<Vec<_>>();
+
+    let mut ret = vec![];
+    let mut current: Option<u64> = None;
+    for (i, up) in it {
+        let t = up.ts / step * step;
+        if let Some(mut c) = current {
+            while c < t {
+                ret.push(Sample { ts: c, quotes: quotes(&books) });
+                c += step;
+            }
+        }
+        current = Some(current.map_or(t, |c| c.max(t)));
+        if let Some(book) = books.get_mut(i) {
+            book.process_update(&up);
+        }
+    }
+    if let Some(c) = current {
+        ret.push(Sample { ts: c, quotes: quotes(&books) });
+    }
+    ret
+}
+
+/// Spread between the same instrument on two venues
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrossSpread {
+    /// start of the step
+    pub ts: u64,
+    /// midprice of a minus midprice of b
+    pub mid: f64,
+    /// bid of a minus ask of b, positive when selling on a and buying on b is profitable
+    pub a_over_b: f64,
+    /// bid of b minus ask of a
+    pub b_over_a: f64,
+}
+
+/// spreads between symbols `a` and `b`, steps where either is unknown are skipped
+pub fn cross_spreads(samples: &[Sample], a: usize, b: usize) -> Vec<CrossSpread> {
+    samples.iter()
+        .filter_map(|s| {
+            let (qa, qb) = (s.quotes.get(a).cloned().flatten()?, s.quotes.get(b).cloned().flatten()?);
+            Some(CrossSpread {
+                ts: s.ts,
+                mid: qa.mid() - qb.mid(),
+                a_over_b: qa.bid - qb.ask,
+                b_over_a: qb.bid - qa.ask,
+            })
+        })
+        .collect()
+}
+
+/// mid(x/y) * mid(y/z) / mid(x/z) for symbols `xy`, `yz` and `xz`, 1 without arbitrage
+pub fn triangular_ratios(samples: &[Sample], xy: usize, yz: usize, xz: usize) -> Vec<(u64, Option<f64>)> {
+    samples.iter()
+        .map(|s| {
+            let ratio = match (s.mid(xy), s.mid(yz), s.mid(xz)) {
+                (Some(a), Some(b), Some(c)) if c != 0. => Some(a * b / c),
+                _ => None,
+            };
+            (s.ts, ratio)
+        })
+        .collect()
+}
+
+/// midprices of symbol `i`
+pub fn midprices(samples: &[Sample], i: usize) -> Vec<Option<f64>> {
+    samples.iter().map(|s| s.mid(i)).collect()
+}
+
+/// log returns between consecutive values, the first is always None
+pub fn log_returns(series: &[Option<f64>]) -> Vec<Option<f64>> {
+    let mut ret = vec![None; series.len().min(1)];
+    ret.extend(series.windows(2).map(|w| match (w[0], w[1]) {
+        (Some(a), Some(b)) if a > 0. && b > 0. => Some((b / a).ln()),
+        _ => None,
+    }));
+    ret
+}
+
+/// pearson correlation of the pairs where both values are known
+pub fn correlation(xs: &[Option<f64>], ys: &[Option<f64>]) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = xs.iter().zip(ys)
+        .filter_map(|(x, y)| Some(((*x)?, (*y)?)))
+        .collect();
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let (mx, my) = (pairs.iter().map(|p| p.0).sum::<f64>() / n, pairs.iter().map(|p| p.1).sum::<f64>() / n);
+    let cov: f64 = pairs.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+    let vx: f64 = pairs.iter().map(|(x, _)| (x - mx).powi(2)).sum();
+    let vy: f64 = pairs.iter().map(|(_, y)| (y - my).powi(2)).sum();
+    if vx == 0. || vy == 0. {
+        None
+    } else {
+        Some(cov / (vx * vy).sqrt())
+    }
+}
+
+/// correlation over the `window` values ending at each position, None before the window is full
+pub fn rolling_correlation(xs: &[Option<f64>], ys: &[Option<f64>], window: usize) -> Vec<Option<f64>> {
+    let len = xs.len().min(ys.len());
+    (0..len)
+        .map(|i| if window == 0 || i + 1 < window {
+            None
+        } else {
+            correlation(&xs[i + 1 - window..=i], &ys[i + 1 - window..=i])
+        })
+        .collect()
+}
+
+/// correlation of x_t with y_(t + lag) for lags in -max_lag..=max_lag,
+/// a peak at a positive lag means x leads y
+pub fn lead_lag(xs: &[Option<f64>], ys: &[Option<f64>], max_lag: usize) -> Vec<(i64, Option<f64>)> {
+    let len = xs.len().min(ys.len());
+    let max_lag = max_lag.min(len.saturating_sub(1));
+    (-(max_lag as i64)..=max_lag as i64)
+        .map(|lag| {
+            let shift = lag.unsigned_abs() as usize;
+            let corr = if lag >= 0 {
+                correlation(&xs[..len - shift], &ys[shift..len])
+            } else {
+                correlation(&xs[shift..len], &ys[..len - shift])
+            };
+            (lag, corr)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(ts: u64, bid: f32, ask: f32) -> Vec<Update> {
+        vec![
+            Update { ts, seq: 0, is_trade: false, is_bid: true, price: bid, size: 1. },
+            Update { ts, seq: 1, is_trade: false, is_bid: false, price: ask, size: 1. },
+        ]
+    }
+
+    #[test]
+    fn should_merge_and_sample_symbols() {
+        let a: Vec<Update> = quote(0, 9., 11.).into_iter()
+            .chain(quote(2500, 19., 21.))
+            .chain(quote(2500, 9., 11.).into_iter().map(|up| Update { seq: up.seq + 2, size: 0., ..up }))
+            .collect();
+        let b: Vec<Update> = quote(1200, 11.5, 12.).into_iter().collect();
+        let merged: Vec<(usize, u64)> = MergeIter::new(vec![a.clone().into_iter(), b.clone().into_iter()])
+            .map(|(i, up)| (i, up.ts))
+            .collect();
+        assert_eq!(merged, vec![(0, 0), (0, 0), (1, 1200), (1, 1200), (0, 2500), (0, 2500), (0, 2500), (0, 2500)]);
+
+        let samples = sample_quotes(MergeIter::new(vec![a.into_iter(), b.into_iter()]), 2, 2, 1000);
+        assert_eq!(samples.iter().map(|s| s.ts).collect::<Vec<_>>(), vec![0, 1000, 2000]);
+        assert_eq!(midprices(&samples, 0), vec![Some(10.), Some(10.), Some(20.)]);
+        assert_eq!(samples[0].quotes[1], None);
+
+        let spreads = cross_spreads(&samples, 0, 1);
+        assert_eq!(spreads[0], CrossSpread { ts: 1000, mid: -1.75, a_over_b: -3., b_over_a: 0.5 });
+        assert_eq!(triangular_ratios(&samples, 0, 1, 0)[2], (2000, Some(11.75)));
+    }
+
+    #[test]
+    fn should_scan_symbols_from_disk() {
+        use crate::dtf::file_format::encode;
+        let a: Vec<Update> = quote(0, 9., 11.).into_iter().chain(quote(2500, 19., 21.)).collect();
+        // several batches, the last one holds the final quote
+        let b: Vec<Update> = (0..4).flat_map(|i| quote(i * 100_000, 11. + i as f32, 12. + i as f32)).collect();
+
+        let dir = std::env::temp_dir().join(format!("tdb-cross-test-{}", std::process::id()));
+        let _ = std::fs::create_dir_all(&dir);
+        let folder = dir.to_str().unwrap();
+        encode(&format!("{}/a.dtf", folder), "cross_a", &a).unwrap();
+        encode(&format!("{}/b.dtf", folder), "cross_b", &b).unwrap();
+        let scanned = scan_symbols(folder, &["cross_a", "cross_b"], 0, 1_000_000);
+        std::fs::remove_dir_all(folder).unwrap();
+        let scanned = scanned.unwrap();
+        assert_eq!(scanned, vec![a.clone(), b.clone()]);
+
+        let from_disk = sample_quotes(MergeIter::new(scanned.into_iter().map(Vec::into_iter)), 2, 2, 1000);
+        assert_eq!(from_disk, sample_quotes(MergeIter::new(vec![a.into_iter(), b.into_iter()]), 2, 2, 1000));
+        // sampled up to the final quote
+        assert_eq!(from_disk.last().unwrap().ts, 300_000);
+    }
+
+    #[test]
+    fn should_find_lead_lag() {
+        let xs: Vec<Option<f64>> = [1., -2., 3., 0.5, -1., 2., -0.5, 1.5].iter().map(|&x| Some(x)).collect();
+        // y follows x two steps later
+        let ys: Vec<Option<f64>> = vec![Some(0.), Some(0.)].into_iter().chain(xs[..6].iter().cloned()).collect();
+        let best = lead_lag(&xs, &ys, 3).into_iter()
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap();
+        assert_eq!(best.0, 2);
+        assert!((best.1.unwrap() - 1.).abs() < 1e-9);
+
+        let corr = rolling_correlation(&xs, &xs, 3);
+        assert_eq!(corr[..2], [None, None]);
+        assert!((corr[5].unwrap() - 1.).abs() < 1e-9);
+        assert_eq!(log_returns(&[Some(1.), None, Some(2.), Some(4.)]), vec![None, None, None, Some(2f64.ln())]);
+    }
+}
@@ -15,7 +15,7 @@ pub enum EventType {
 
 /// Data structure for storing events.
 /// Each is a map from time to list of updates happened at or near that discrete time
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Events {
     /// cancelled orders
     pub cancelled: BTreeMap<Time, Vec<Update>>,
@@ -27,58 +27,59 @@ pub struct Events {
 
 impl<'a> From<&'a [Update]> for Events {
     fn from(ups: &[Update]) -> Events {
-
-        let mut cancelled = BTreeMap::new();
-        let mut trades = BTreeMap::new();
-        let mut created = BTreeMap::new();
-
+        let mut events = Events::new();
         let mut current_level = BTreeMap::new();
 
         for row in ups {
-
-            let ts = row.ts;
             let price = row.price.to_bits();
-
-            if row.is_trade {
-                let v = trades.entry(ts).or_insert(Vec::new());
-                (*v).push(row.clone());
-            } else {
-                let prev = if current_level.contains_key(&price) {
-                    *current_level.get(&price).unwrap()
-                } else {
-                    0.
-                };
-                if row.size == 0. || row.size <= prev {
-                    let v = cancelled.entry(ts).or_insert(Vec::new());
-                    (*v).push(row.clone());
-                } else if row.size > prev {
-                    let v = created.entry(ts).or_insert(Vec::new());
-                    (*v).push(row.clone());
-                } else {
-                    // size == prev
-                    unreachable!();
-                }
-            }
-
+            let prev = current_level.get(&price).cloned().unwrap_or(0.);
+            events.record(row, prev);
             current_level.insert(price, row.size);
         }
 
-        Events {
-            cancelled,
-            trades,
-            created,
-        }
+        events
     }
 }
 
 impl Events {
-    /// Filter order events based on size
-    pub fn filter_size(&self, event_type: EventType, from_size: f32, to_size: f32) -> Vec<Update> {
-        let obj = match event_type {
+    /// Create empty events
+    pub fn new() -> Events {
+        Events::default()
+    }
+
+    /// Classify an update given the size previously resting at its price level
+    pub fn record(&mut self, row: &Update, prev: f32) {
+        let map = if row.is_trade {
+            &mut self.trades
+        } else if row.size == 0. || row.size <= prev {
+            &mut self.cancelled
+        } else {
+            &mut self.created
+        };
+        map.entry(row.ts).or_insert_with(Vec::new).push(*row);
+    }
+
+    /// Number of events of a type
+    pub fn count(&self, event_type: EventType) -> usize {
+        self.get(event_type).values().map(Vec::len).sum()
+    }
+
+    /// Total size of events of a type
+    pub fn volume(&self, event_type: EventType) -> f32 {
+        self.get(event_type).values().flatten().map(|up| up.size).sum()
+    }
+
+    fn get(&self, event_type: EventType) -> &BTreeMap<Time, Vec<Update>> {
+        match event_type {
             EventType::CancelEvent => &self.cancelled,
             EventType::CreateEvent => &self.created,
             EventType::TradeEvent => &self.trades,
-        };
+        }
+    }
+
+    /// Filter order events based on size
+    pub fn filter_size(&self, event_type: EventType, from_size: f32, to_size: f32) -> Vec<Update> {
+        let obj = self.get(event_type);
 
         let mut ret = Vec::new();
         for v in obj.values() {
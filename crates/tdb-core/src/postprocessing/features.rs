@@ -0,0 +1,274 @@
+//! Microstructure features sampled over fixed time intervals
+//!
+//! Updates drive an `Orderbook`; when an update falls into a new interval the
+//! features of the previous one are emitted. Book based features (spread,
+//! microprice, depth imbalance) describe the book at the end of the interval,
+//! flow based features (order flow imbalance, events) accumulate over it.
+
+use crate::dtf::update::Update;
+use crate::postprocessing::event::{Events, EventType};
+use crate::postprocessing::orderbook::Orderbook;
+use crate::utils::fill_digits;
+
+type Time = u64;
+type Level = Option<(u64, f64)>;
+
+/// Features of one interval
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Features {
+    /// start of the interval in ms
+    pub start: Time,
+    /// ts of the last update in the interval
+    pub end: Time,
+    /// order flow imbalance: net size added at the best bid minus the best ask
+    pub ofi: f64,
+    /// (bid size - ask size) / (bid size + ask size) over the top N levels
+    pub depth_imbalance: Option<f64>,
+    /// best ask - best bid
+    pub spread: Option<f32>,
+    /// mid price weighted by the size on the opposite side
+    pub microprice: Option<f64>,
+    /// size change of the best bid level the interval started with
+    pub bid_queue_change: f64,
+    /// size change of the best ask level the interval started with
+    pub ask_queue_change: f64,
+    /// number of orders created
+    pub created: usize,
+    /// number of orders cancelled
+    pub cancelled: usize,
+    /// number of trades
+    pub trades: usize,
+    /// cancelled / created orders
+    pub cancel_create_ratio: Option<f64>,
+}
+
+impl Features {
+    /// column names of `to_csv`
+    pub fn csv_header() -> &'static str {
+        "start,end,ofi,depth_imbalance,spread,microprice,bid_queue_change,ask_queue_change,created,cancelled,trades,cancel_create_ratio"
+    }
+
+    /// convert to csv, missing values are left empty
+    pub fn to_csv(&self) -> String {
+        fn opt<T: ToString>(v: Option<T>) -> String {
+            v.map(|v| v.to_string()).unwrap_or_default()
+        }
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.start,
+            self.end,
+            self.ofi,
+            opt(self.depth_imbalance),
+            opt(self.spread),
+            opt(self.microprice),
+            self.bid_queue_change,
+            self.ask_queue_change,
+            self.created,
+            self.cancelled,
+            self.trades,
+            opt(self.cancel_create_ratio),
+        )
+    }
+}
+
+/// state of the interval being sampled
+struct Interval {
+    start: Time,
+    end: Time,
+    ofi: f64,
+    events: Events,
+    best_bid: Level,
+    best_ask: Level,
+}
+
+/// Iterator of features sampled every `interval` ms
+pub struct FeaturesIter<I: Iterator<Item=Update>> {
+    it: I,
+    book: Orderbook,
+    interval: u64,
+    depth: usize,
+    current: Option<Interval>,
+}
+
+impl<I: Iterator<Item=Update>> FeaturesIter<I> {
+    /// Create a new iterator starting from an empty book
+    pub fn new(it: I, price_decimals: u8, interval: u64, depth: usize) -> Self {
+        Self::with_book(it, Orderbook::with_precision(price_decimals), interval, depth)
+    }
+
+    /// Create a new iterator continuing from `book`
+    pub fn with_book(it: I, book: Orderbook, interval: u64, depth: usize) -> Self {
+        Self {
+            it,
+            book,
+            interval: interval.max(1),
+            depth,
+            current: None,
+        }
+    }
+
+    /// the book after the last update read
+    pub fn book(&self) -> &Orderbook {
+        &self.book
+    }
+
+    fn best(&self) -> (Level, Level) {
+        let bid = self.book.bids.iter().next_back().map(|(&p, &s)| (p, s));
+        let ask = self.book.asks.iter().next().map(|(&p, &s)| (p, s));
+        (bid, ask)
+    }
+
+    fn apply(&mut self, up: &Update) {
+        let prev = if up.is_trade {
+            0.
+        } else {
+            let side = if up.is_bid { &self.book.bids } else { &self.book.asks };
+            side.get(&self.book.discretize(up.price)).cloned().unwrap_or(0.)
+        };
+        let (bid0, ask0) = self.best();
+        self.book.process_update(up);
+        let (bid1, ask1) = self.best();
+
+        let interval = self.current.as_mut().unwrap();
+        interval.ofi += ofi(bid0, bid1, ask0, ask1);
+        interval.end = up.ts;
+        interval.events.record(up, prev as f32);
+    }
+
+    fn finish(&self, interval: Interval) -> Features {
+        let top = self.book.top_levels(self.depth);
+        let bid_depth: f64 = top.bids.values().sum();
+        let ask_depth: f64 = top.asks.values().sum();
+        let depth_imbalance = if bid_depth + ask_depth > 0. {
+            Some((bid_depth - ask_depth) / (bid_depth + ask_depth))
+        } else {
+            None
+        };
+
+        let (spread, microprice) = match self.book.top() {
+            Some(((bid_p, bid_s), (ask_p, ask_s))) => {
+                let microprice = if bid_s + ask_s > 0. {
+                    Some((f64::from(bid_p) * ask_s + f64::from(ask_p) * bid_s) / (bid_s + ask_s))
+                } else {
+                    None
+                };
+                (Some(ask_p - bid_p), microprice)
+            }
+            None => (None, None),
+        };
+
+        let queue_change = |level: Level, side: &std::collections::BTreeMap<u64, f64>| {
+            level.map(|(p, s)| side.get(&p).cloned().unwrap_or(0.) - s).unwrap_or(0.)
+        };
+
+        let events = &interval.events;
+        let created = events.count(EventType::CreateEvent);
+        let cancelled = events.count(EventType::CancelEvent);
+        let cancel_create_ratio = if created > 0 {
+            Some(cancelled as f64 / created as f64)
+        } else {
+            None
+        };
+
+        Features {
+            start: interval.start,
+            end: interval.end,
+            ofi: interval.ofi,
+            depth_imbalance,
+            spread,
+            microprice,
+            bid_queue_change: queue_change(interval.best_bid, &self.book.bids),
+            ask_queue_change: queue_change(interval.best_ask, &self.book.asks),
+            created,
+            cancelled,
+            trades: events.count(EventType::TradeEvent),
+            cancel_create_ratio,
+        }
+    }
+}
+
+/// contribution of one book change to the order flow imbalance (Cont, Kukanov & Stoikov)
+fn ofi(bid0: Level, bid1: Level, ask0: Level, ask1: Level) -> f64 {
+    let mut e = 0.;
+    if let (Some((p0, q0)), Some((p1, q1))) = (bid0, bid1) {
+        if p1 >= p0 { e += q1; }
+        if p1 <= p0 { e -= q0; }
+    }
+    if let (Some((p0, q0)), Some((p1, q1))) = (ask0, ask1) {
+        if p1 <= p0 { e -= q1; }
+        if p1 >= p0 { e += q0; }
+    }
+    e
+}
+
+impl<I: Iterator<Item=Update>> Iterator for FeaturesIter<I> {
+    type Item = Features;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(up) = self.it.next() {
+            let start = fill_digits(up.ts) / self.interval * self.interval;
+            let done = match &self.current {
+                Some(interval) if interval.start == start => None,
+                _ => {
+                    let (best_bid, best_ask) = self.best();
+                    self.current.replace(Interval {
+                        start,
+                        end: up.ts,
+                        ofi: 0.,
+                        events: Events::new(),
+                        best_bid,
+                        best_ask,
+                    })
+                }
+            };
+            // the previous interval ends with the book before this update
+            let features = done.map(|interval| self.finish(interval));
+            self.apply(&up);
+            if features.is_some() {
+                return features;
+            }
+        }
+        let interval = self.current.take()?;
+        Some(self.finish(interval))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(ts: u64, is_trade: bool, is_bid: bool, price: f32, size: f32) -> Update {
+        Update { ts, seq: 0, is_trade, is_bid, price, size }
+    }
+
+    #[test]
+    fn should_sample_features_per_interval() {
+        let ups = vec![
+            up(1_000_000_000_000, false, true, 10., 2.),
+            up(1_000_000_000_100, false, false, 11., 1.),
+            up(1_000_000_000_200, false, true, 9., 4.),
+            // second interval: bid queue grows, ask is partly cancelled
+            up(1_000_000_001_000, false, true, 10., 5.),
+            up(1_000_000_001_100, false, false, 11., 0.5),
+            up(1_000_000_001_200, true, false, 11., 0.25),
+        ];
+        let features: Vec<Features> = FeaturesIter::new(ups.into_iter(), 2, 1000, 2).collect();
+        assert_eq!(features.len(), 2);
+
+        let f = features[1];
+        assert_eq!(f.start, 1_000_000_001_000);
+        assert_eq!(f.end, 1_000_000_001_200);
+        // +3 from the bid, +0.5 from the cancelled ask and +0.25 from the trade
+        assert_eq!(f.ofi, 3.75);
+        assert_eq!(f.bid_queue_change, 3.);
+        assert_eq!(f.ask_queue_change, -0.75);
+        assert_eq!((f.created, f.cancelled, f.trades), (1, 1, 1));
+        assert_eq!(f.cancel_create_ratio, Some(1.));
+        assert_eq!(f.microprice, Some((10. * 0.25 + 11. * 5.) / 5.25));
+        assert_eq!(f.depth_imbalance, Some((9. - 0.25) / 9.25));
+
+        let f = features[0];
+        assert_eq!(f.spread, Some(1.));
+        assert_eq!(f.microprice, Some((10. * 1. + 11. * 2.) / 3.));
+        assert_eq!(f.depth_imbalance, Some((6. - 1.) / 7.));
+    }
+}
@@ -0,0 +1,254 @@
+//! Order-by-order (L3) book
+//!
+//! Orders queue at their price level in arrival order. A modification that
+//! moves the price or raises the size sends the order to the back of its
+//! queue, a size reduction keeps its priority.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::dtf::l3::{L3Action, L3Update};
+use crate::postprocessing::bbo::Bbo;
+use crate::postprocessing::orderbook::Orderbook;
+
+type Price = u64;
+
+/// A resting order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Order {
+    /// exchange order id
+    pub order_id: u64,
+    /// side of the order
+    pub is_bid: bool,
+    /// discretized price
+    pub price: Price,
+    /// remaining size
+    pub size: f64,
+    /// ts of the event that gave the order its queue priority
+    pub ts: u64,
+}
+
+/// Where an order stands in its price level
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueuePosition {
+    /// side of the order
+    pub is_bid: bool,
+    /// price of the level
+    pub price: f32,
+    /// number of orders ahead in the queue
+    pub orders_ahead: usize,
+    /// size of the orders ahead in the queue
+    pub size_ahead: f64,
+    /// total size of the level, including the order
+    pub level_size: f64,
+}
+
+/// Order-by-order book
+#[derive(Clone, Debug)]
+pub struct L3Book {
+    /// empty book used for price conversion and as template of `to_orderbook`
+    template: Orderbook,
+    orders: HashMap<u64, Order>,
+    bids: BTreeMap<Price, VecDeque<u64>>,
+    asks: BTreeMap<Price, VecDeque<u64>>,
+    /// modify, cancel or execute events of orders not in the book
+    pub unknown_orders: u64,
+}
+
+impl L3Book {
+    /// Create empty book
+    pub fn with_precision(price_decimals: u8) -> L3Book {
+        L3Book::with_template(Orderbook::with_precision(price_decimals))
+    }
+
+    /// Create empty book aggregating into books with the precision, tick size and options of `template`
+    pub fn with_template(mut template: Orderbook) -> L3Book {
+        template.clear();
+        L3Book {
+            template,
+            orders: HashMap::new(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            unknown_orders: 0,
+        }
+    }
+
+    /// number of resting orders
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// whether the book has no orders
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// resting order by id
+    pub fn order(&self, order_id: u64) -> Option<&Order> {
+        self.orders.get(&order_id)
+    }
+
+    /// apply an order event
+    pub fn process_update(&mut self, up: &L3Update) {
+        match up.action {
+            L3Action::Add => {
+                self.remove(up.order_id);
+                self.insert(up);
+            }
+            L3Action::Modify => match self.orders.get_mut(&up.order_id) {
+                Some(order) => {
+                    let price = self.template.discretize(up.price);
+                    let size = f64::from(up.size);
+                    if price == order.price && size <= order.size {
+                        order.size = size;
+                        if size <= 0. {
+                            self.remove(up.order_id);
+                        }
+                    } else {
+                        self.remove(up.order_id);
+                        self.insert(up);
+                    }
+                }
+                None => {
+                    self.unknown_orders += 1;
+                    self.insert(up);
+                }
+            },
+            L3Action::Cancel => {
+                if self.remove(up.order_id).is_none() {
+                    self.unknown_orders += 1;
+                }
+            }
+            L3Action::Execute => match self.orders.get_mut(&up.order_id) {
+                Some(order) => {
+                    order.size -= f64::from(up.size);
+                    if order.size <= 0. {
+                        self.remove(up.order_id);
+                    }
+                }
+                None => self.unknown_orders += 1,
+            },
+        }
+    }
+
+    fn insert(&mut self, up: &L3Update) {
+        if up.size <= 0. {
+            return;
+        }
+        let order = Order {
+            order_id: up.order_id,
+            is_bid: up.is_bid,
+            price: self.template.discretize(up.price),
+            size: f64::from(up.size),
+            ts: up.ts,
+        };
+        let side = if up.is_bid { &mut self.bids } else { &mut self.asks };
+        side.entry(order.price).or_insert_with(VecDeque::new).push_back(order.order_id);
+        self.orders.insert(order.order_id, order);
+    }
+
+    fn remove(&mut self, order_id: u64) -> Option<Order> {
+        let order = self.orders.remove(&order_id)?;
+        let side = if order.is_bid { &mut self.bids } else { &mut self.asks };
+        if let Some(queue) = side.get_mut(&order.price) {
+            queue.retain(|&id| id != order_id);
+            if queue.is_empty() {
+                side.remove(&order.price);
+            }
+        }
+        Some(order)
+    }
+
+    /// orders at a level in queue order
+    pub fn queue(&self, is_bid: bool, price: f32) -> Vec<&Order> {
+        let side = if is_bid { &self.bids } else { &self.asks };
+        side.get(&self.template.discretize(price))
+            .map(|queue| queue.iter().filter_map(|id| self.orders.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    /// position of a resting order in its level
+    pub fn queue_position(&self, order_id: u64) -> Option<QueuePosition> {
+        let order = self.orders.get(&order_id)?;
+        let side = if order.is_bid { &self.bids } else { &self.asks };
+        let queue = side.get(&order.price)?;
+        let mut ret = QueuePosition {
+            is_bid: order.is_bid,
+            price: self.template.undiscretize(order.price),
+            orders_ahead: 0,
+            size_ahead: 0.,
+            level_size: 0.,
+        };
+        let mut ahead = true;
+        for other in queue.iter().filter_map(|id| self.orders.get(id)) {
+            if other.order_id == order_id {
+                ahead = false;
+            } else if ahead {
+                ret.orders_ahead += 1;
+                ret.size_ahead += other.size;
+            }
+            ret.level_size += other.size;
+        }
+        Some(ret)
+    }
+
+    fn level_size(&self, queue: &VecDeque<u64>) -> f64 {
+        queue.iter().filter_map(|id| self.orders.get(id)).map(|o| o.size).sum()
+    }
+
+    /// aggregate orders into price levels
+    pub fn to_orderbook(&self) -> Orderbook {
+        let mut ob = self.template.clone();
+        ob.bids = self.bids.iter().map(|(&p, queue)| (p, self.level_size(queue))).collect();
+        ob.asks = self.asks.iter().map(|(&p, queue)| (p, self.level_size(queue))).collect();
+        ob
+    }
+
+    /// best bid and ask without aggregating the whole book
+    pub fn bbo(&self, ts: u64) -> Bbo {
+        let level = |(&p, queue): (&Price, &VecDeque<u64>)| (self.template.undiscretize(p), self.level_size(queue));
+        Bbo {
+            ts,
+            bid: self.bids.iter().next_back().map(level),
+            ask: self.asks.iter().next().map(level),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(order_id: u64, action: L3Action, price: f32, size: f32) -> L3Update {
+        L3Update { ts: order_id, seq: 0, order_id, action, is_bid: true, price, size }
+    }
+
+    #[test]
+    fn should_track_queue_position() {
+        let mut book = L3Book::with_precision(2);
+        for (id, size) in &[(1, 1.), (2, 2.), (3, 3.)] {
+            book.process_update(&up(*id, L3Action::Add, 10., *size));
+        }
+        book.process_update(&up(4, L3Action::Add, 9., 1.));
+        let pos = book.queue_position(3).unwrap();
+        assert_eq!((pos.orders_ahead, pos.size_ahead, pos.level_size), (2, 3., 6.));
+
+        // partial fill of the head, the reduced order 2 keeps its place
+        book.process_update(&up(1, L3Action::Execute, 10., 0.5));
+        book.process_update(&up(2, L3Action::Modify, 10., 1.));
+        assert_eq!(book.queue_position(3).unwrap().size_ahead, 1.5);
+        // increasing the size loses priority
+        book.process_update(&up(1, L3Action::Modify, 10., 5.));
+        assert_eq!(book.queue(true, 10.).iter().map(|o| o.order_id).collect::<Vec<_>>(), vec![2, 3, 1]);
+
+        book.process_update(&up(2, L3Action::Cancel, 10., 0.));
+        book.process_update(&up(4, L3Action::Execute, 9., 1.));
+        book.process_update(&up(7, L3Action::Cancel, 9., 0.));
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.unknown_orders, 1);
+
+        let ob = book.to_orderbook();
+        assert_eq!(ob.bids.iter().collect::<Vec<_>>(), vec![(&1000, &8.)]);
+        assert!(ob.asks.is_empty());
+        assert_eq!(book.bbo(0), Bbo::of(0, &ob));
+    }
+}
@@ -4,7 +4,23 @@ pub mod candle;
 pub mod orderbook;
 /// orderbook data structure: [price -> time -> size]
 pub mod level;
+/// order-by-order book and queue positions
+pub mod l3book;
+/// top of book change stream
+pub mod bbo;
+/// trade side classification
+pub mod classify;
 /// events stream from slice of updates
 pub mod event;
 ///
 pub mod histogram;
+/// microstructure features sampled over time intervals
+pub mod features;
+/// vwap, twap, realized volatility, spread and trade size statistics
+pub mod stats;
+/// spreads, correlations and lead-lag across symbols
+pub mod cross;
+/// fixed-shape orderbook tensors
+pub mod tensor;
+/// svg and html charts of candles and depth heatmaps
+pub mod plot;
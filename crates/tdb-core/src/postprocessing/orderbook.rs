@@ -4,61 +4,330 @@ use indexmap::IndexMap;
 use crate::postprocessing::histogram::{Histogram, BinCount};
 use crate::dtf::update::Update;
 use std::collections::BTreeMap;
+use serde::ser::{Serialize, Serializer, SerializeMap, SerializeStruct};
+use std::convert::TryFrom;
 use std::fmt;
+use std::mem;
 use std::f64;
 
 type Price = u64;
 type Size = f64;
 type Time = u64;
 
+/// change of a single price level between two orderbooks
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LevelDiff {
+    /// side of the level
+    pub is_bid: bool,
+    /// discretized price
+    pub price: Price,
+    /// size in the old book, 0 if absent
+    pub before: Size,
+    /// size in the new book, 0 if removed
+    pub after: Size,
+}
+
+/// how trades change the book
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TradeHandling {
+    /// trades reduce the size of their level, emptied levels are removed
+    Subtract,
+    /// trades leave the book unchanged, for feeds that send a level update after every trade
+    Ignore,
+}
+
+/// what happens when a depth update crosses the book
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrossHandling {
+    /// keep the crossed levels, only count them
+    Keep,
+    /// the update is the latest information, remove the opposite levels it crosses
+    RemoveStale,
+}
+
+/// how updates are applied to an orderbook
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BookOptions {
+    /// trade application
+    pub trades: TradeHandling,
+    /// crossed book resolution
+    pub crossed: CrossHandling,
+}
+
+impl Default for BookOptions {
+    fn default() -> Self {
+        BookOptions {
+            trades: TradeHandling::Subtract,
+            crossed: CrossHandling::Keep,
+        }
+    }
+}
+
+/// anomalies seen while processing updates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct ValidationReport {
+    /// processed updates
+    pub updates: u64,
+    /// processed trades
+    pub trades: u64,
+    /// trades at a price without a level
+    pub trades_without_level: u64,
+    /// trades larger than their level
+    pub trades_exceeding_level: u64,
+    /// depth updates with a negative size, applied as removals
+    pub negative_sizes: u64,
+    /// updates after which the best bid was at or above the best ask
+    pub crossed: u64,
+    /// levels removed to uncross the book
+    pub uncrossed_levels: u64,
+    /// updates skipped because their price does not fit in a `Price`
+    pub unrepresentable_prices: u64,
+}
+
+impl ValidationReport {
+    /// total number of anomalies
+    pub fn anomalies(&self) -> u64 {
+        self.trades_without_level + self.trades_exceeding_level + self.negative_sizes + self.crossed
+            + self.unrepresentable_prices
+    }
+}
+
 /// data structure for orderbook
-#[derive(Clone, Serialize, Deserialize, PartialEq)]
+///
+/// Prices are integers in units of `10^-price_decimals`, rounded to the
+/// nearest multiple of `tick_size`. Serialized books key their levels by
+/// exact decimal strings, e.g. `{"bids": {"0.07036290": 1.5}}`.
+///
+/// Books compare equal when their levels are equal, options and reports are ignored.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "SerializedOrderbook")]
 pub struct Orderbook {
     /// precision of price
     pub price_decimals: u8,
+    /// smallest price increment in units of `10^-price_decimals`
+    pub tick_size: u64,
     /// bids side of the orderbook
     pub bids: BTreeMap<Price, Size>,
     /// asks side of the orderbook
     pub asks: BTreeMap<Price, Size>,
+    /// how updates are applied
+    pub options: BookOptions,
+    /// anomalies seen so far
+    pub report: ValidationReport,
+}
+
+impl PartialEq for Orderbook {
+    fn eq(&self, other: &Orderbook) -> bool {
+        self.price_decimals == other.price_decimals
+            && self.tick_size == other.tick_size
+            && self.bids == other.bids
+            && self.asks == other.asks
+    }
+}
+
+/// levels of one side keyed by decimal string
+struct DecimalLevels<'a>(&'a Orderbook, &'a BTreeMap<Price, Size>);
+
+impl<'a> Serialize for DecimalLevels<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.1.len()))?;
+        for (&price, size) in self.1 {
+            map.serialize_entry(&self.0.price_string(price), size)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Orderbook {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut st = serializer.serialize_struct("Orderbook", 4)?;
+        st.serialize_field("price_decimals", &self.price_decimals)?;
+        st.serialize_field("tick_size", &self.tick_size)?;
+        st.serialize_field("bids", &DecimalLevels(self, &self.bids))?;
+        st.serialize_field("asks", &DecimalLevels(self, &self.asks))?;
+        st.end()
+    }
+}
+
+fn default_tick_size() -> u64 {
+    1
+}
+
+#[derive(Deserialize)]
+struct SerializedOrderbook {
+    price_decimals: u8,
+    #[serde(default = "default_tick_size")]
+    tick_size: u64,
+    bids: BTreeMap<String, Size>,
+    asks: BTreeMap<String, Size>,
+}
+
+impl TryFrom<SerializedOrderbook> for Orderbook {
+    type Error = String;
+    fn try_from(ser: SerializedOrderbook) -> Result<Orderbook, String> {
+        let mut ob = Orderbook::with_precision(ser.price_decimals).with_tick_size(ser.tick_size);
+        let side = |levels: BTreeMap<String, Size>| levels.into_iter()
+            .map(|(p, s)| ob.parse_price(&p).map(|p| (p, s)).ok_or_else(|| format!("invalid price `{}`", p)))
+            .collect::<Result<BTreeMap<Price, Size>, String>>();
+        let (bids, asks) = (side(ser.bids)?, side(ser.asks)?);
+        ob.bids = bids;
+        ob.asks = asks;
+        Ok(ob)
+    }
 }
 
 impl Orderbook {
-    /// convert price from f32 to u64
+    /// convert price from f32 to u64, rounded to the nearest tick, saturating when out of range
     pub fn discretize(&self, p: f32) -> Price {
-        (f64::from(p) * 10f64.powf(self.price_decimals as f64)) as Price
+        let tick = self.tick_size.max(1);
+        (self.ticks(p) as Price).saturating_mul(tick)
+    }
+
+    /// convert price from f32 to u64, or `None` if it is negative, not finite or too large
+    pub fn checked_discretize(&self, p: f32) -> Option<Price> {
+        let ticks = self.ticks(p);
+        if !(0. ..Price::MAX as f64).contains(&ticks) {
+            return None;
+        }
+        (ticks as Price).checked_mul(self.tick_size.max(1))
+    }
+
+    fn ticks(&self, p: f32) -> f64 {
+        let tick = self.tick_size.max(1);
+        (f64::from(p) * 10f64.powi(i32::from(self.price_decimals)) / tick as f64).round()
     }
 
     /// convert price from u64 to f32
     pub fn undiscretize(&self, p: u64) -> f32 {
-        p as f32 / 10f32.powf(self.price_decimals as f32)
+        (p as f64 / 10f64.powi(i32::from(self.price_decimals))) as f32
+    }
+
+    /// exact decimal representation of a discretized price, with `price_decimals` digits
+    pub fn price_string(&self, p: Price) -> String {
+        let decimals = usize::from(self.price_decimals);
+        if decimals == 0 {
+            return p.to_string();
+        }
+        let digits = format!("{:0>width$}", p, width = decimals + 1);
+        let (int, frac) = digits.split_at(digits.len() - decimals);
+        format!("{}.{}", int, frac)
+    }
+
+    /// discretize a decimal string without going through floats, rounded to the nearest tick
+    pub fn parse_price(&self, s: &str) -> Option<Price> {
+        let decimals = usize::from(self.price_decimals);
+        let (int, frac) = match s.find('.') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        if int.is_empty() && frac.is_empty() || !(int.bytes().chain(frac.bytes())).all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let kept = &frac[..frac.len().min(decimals)];
+        let units = format!("{}{:0<width$}", int, kept, width = decimals);
+        let mut units = match units.trim_start_matches('0') {
+            "" => 0,
+            units => units.parse::<Price>().ok()?,
+        };
+        // round half up on the first dropped digit
+        if frac.len() > decimals && frac.as_bytes()[decimals] >= b'5' {
+            units += 1;
+        }
+        let tick = self.tick_size.max(1);
+        Some((units + tick / 2) / tick * tick)
     }
 
     /// Create empty orderbook
     pub fn with_precision(price_decimals: u8) -> Orderbook {
+        Orderbook::with_options(price_decimals, BookOptions::default())
+    }
+
+    /// Create empty orderbook applying updates according to `options`
+    pub fn with_options(price_decimals: u8, options: BookOptions) -> Orderbook {
         Orderbook {
             price_decimals,
+            tick_size: 1,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            options,
+            report: ValidationReport::default(),
         }
     }
 
-    /// process depth update and clear empty price levels
+    /// round prices to multiples of `tick_size` units of `10^-price_decimals`
+    pub fn with_tick_size(mut self, tick_size: u64) -> Orderbook {
+        self.tick_size = tick_size.max(1);
+        self
+    }
+
+    /// process depth update or trade, levels never hold a non-positive size
     pub fn process_update(&mut self, up: &Update) {
+        self.report.updates += 1;
+        let price = match self.checked_discretize(up.price) {
+            Some(price) => price,
+            None => {
+                self.report.unrepresentable_prices += 1;
+                return;
+            }
+        };
+        let book = if up.is_bid {&mut self.bids} else {&mut self.asks};
         if up.is_trade {
-            let p = self.discretize(up.price);
-            let book = if up.is_bid {&mut self.bids} else {&mut self.asks};
-            book.entry(p)
-                .and_modify(|size| {
-                    // assert!(*size >= up.size);
-                    *size -= up.size as Size
-                });
-        } else {
-            let price = self.discretize(up.price);
-            let book = if up.is_bid {&mut self.bids} else {&mut self.asks};
+            self.report.trades += 1;
+            match book.get_mut(&price) {
+                None => self.report.trades_without_level += 1,
+                Some(size) => {
+                    if up.size as Size > *size {
+                        self.report.trades_exceeding_level += 1;
+                    }
+                    if self.options.trades == TradeHandling::Subtract {
+                        *size -= up.size as Size;
+                        if *size <= 0. {
+                            book.remove(&price);
+                        }
+                    }
+                }
+            }
+        } else if up.size > 0. {
             book.insert(price, up.size as Size);
-            if book[&price] == 0. {
-                book.remove(&price);
+            if self.options.crossed == CrossHandling::RemoveStale {
+                let removed = self.remove_crossed_by(up.is_bid, price);
+                self.report.uncrossed_levels += removed as u64;
             }
+        } else {
+            if up.size < 0. {
+                self.report.negative_sizes += 1;
+            }
+            book.remove(&price);
+        }
+        if self.is_crossed() {
+            self.report.crossed += 1;
+        }
+    }
+
+    /// whether the best bid is at or above the best ask
+    pub fn is_crossed(&self) -> bool {
+        match (self.best_bid_raw(), self.best_ask_raw()) {
+            (Some(bid), Some(ask)) => bid >= ask,
+            _ => false,
+        }
+    }
+
+    /// remove the levels crossing the best level of the kept side, returns the number of removed levels
+    pub fn uncross(&mut self, keep_bids: bool) -> usize {
+        let best = if keep_bids { self.best_bid_raw() } else { self.best_ask_raw() };
+        match best {
+            Some(price) => self.remove_crossed_by(keep_bids, price),
+            None => 0,
+        }
+    }
+
+    /// remove opposite levels at or through `price`
+    fn remove_crossed_by(&mut self, is_bid: bool, price: Price) -> usize {
+        if is_bid {
+            let rest = self.asks.split_off(&(price + 1));
+            mem::replace(&mut self.asks, rest).len()
+        } else {
+            self.bids.split_off(&price).len()
         }
     }
 
@@ -68,16 +337,22 @@ impl Orderbook {
         self.asks.clear();
     }
 
-    /// Remove zero levels from books
+    /// Remove zero and negative levels from books
     pub fn clean(&mut self) {
-        self.bids = self.bids.iter()
-                .map(|(&a,&b)| (a,b))
-                .filter(|&(_p,s)|s != 0.)
-                .collect::<BTreeMap<Price, Size>>();
-        self.asks = self.asks.iter()
-                .map(|(&a,&b)| (a,b))
-                .filter(|&(_p,s)|s != 0.)
-                .collect::<BTreeMap<Price, Size>>();
+        self.bids.retain(|_p, s| *s > 0.);
+        self.asks.retain(|_p, s| *s > 0.);
+    }
+
+    /// copy of the book with only the best `depth` levels on each side
+    pub fn top_levels(&self, depth: usize) -> Orderbook {
+        Orderbook {
+            price_decimals: self.price_decimals,
+            tick_size: self.tick_size,
+            bids: self.bids.iter().rev().take(depth).map(|(&p, &s)| (p, s)).collect(),
+            asks: self.asks.iter().take(depth).map(|(&p, &s)| (p, s)).collect(),
+            options: self.options,
+            report: self.report,
+        }
     }
 
     /// get top of the book, max bid, min ask
@@ -129,6 +404,27 @@ impl Orderbook {
         let ba = self.best_ask()?;
         Some((bb + ba) / 2.)
     }
+
+    /// levels that differ from `self` in `other`, bids then asks in ascending price
+    pub fn diff(&self, other: &Orderbook) -> Vec<LevelDiff> {
+        let side = |is_bid: bool, old: &BTreeMap<Price, Size>, new: &BTreeMap<Price, Size>| {
+            let mut prices: Vec<Price> = old.keys().chain(new.keys()).cloned().collect();
+            prices.sort();
+            prices.dedup();
+            prices.into_iter()
+                .map(|price| LevelDiff {
+                    is_bid,
+                    price,
+                    before: old.get(&price).cloned().unwrap_or(0.),
+                    after: new.get(&price).cloned().unwrap_or(0.),
+                })
+                .filter(|d| d.before != d.after)
+                .collect::<Vec<_>>()
+        };
+        let mut ret = side(true, &self.bids, &other.bids);
+        ret.extend(side(false, &self.asks, &other.asks));
+        ret
+    }
 }
 
 impl fmt::Debug for Orderbook {
@@ -314,6 +610,124 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_top_levels() {
+        let mut ob = Orderbook::with_precision(2);
+        for (i, &price) in [1., 2., 3.].iter().enumerate() {
+            ob.process_update(&Update { ts: i as u64, seq: 0, is_trade: false, is_bid: true, price, size: 1. });
+            ob.process_update(&Update { ts: i as u64, seq: 0, is_trade: false, is_bid: false, price: price + 3., size: 1. });
+        }
+        let top = ob.top_levels(2);
+        assert_eq!(top.bids.keys().collect::<Vec<_>>(), vec![&200, &300]);
+        assert_eq!(top.asks.keys().collect::<Vec<_>>(), vec![&400, &500]);
+        assert_eq!(top.top(), ob.top());
+        assert_eq!(ob.top_levels(10), ob);
+    }
+
+    #[test]
+    fn test_diff() {
+        let up = |is_bid, price, size| Update { ts: 0, seq: 0, is_trade: false, is_bid, price, size };
+        let mut old = Orderbook::with_precision(2);
+        old.process_update(&up(true, 1., 1.));
+        old.process_update(&up(true, 2., 1.));
+        old.process_update(&up(false, 3., 1.));
+        let mut new = old.clone();
+        new.process_update(&up(true, 2., 0.));
+        new.process_update(&up(false, 3., 2.));
+        new.process_update(&up(false, 4., 1.));
+
+        assert_eq!(new.diff(&new), vec![]);
+        assert_eq!(old.diff(&new), vec![
+            LevelDiff { is_bid: true, price: 200, before: 1., after: 0. },
+            LevelDiff { is_bid: false, price: 300, before: 1., after: 2. },
+            LevelDiff { is_bid: false, price: 400, before: 0., after: 1. },
+        ]);
+    }
+
+    #[test]
+    fn test_trade_handling() {
+        let up = |is_trade, is_bid, price, size| Update { ts: 0, seq: 0, is_trade, is_bid, price, size };
+        let mut ob = Orderbook::with_precision(2);
+        ob.process_update(&up(false, true, 1., 2.));
+        ob.process_update(&up(false, true, 2., 1.));
+        ob.process_update(&up(true, true, 1., 1.));
+        assert_eq!(ob.bids[&100], 1.);
+        // levels are removed once traded through
+        ob.process_update(&up(true, true, 2., 1.5));
+        ob.process_update(&up(true, true, 3., 1.));
+        ob.process_update(&up(false, true, 1., -1.));
+        assert!(ob.bids.is_empty());
+        assert_eq!(ob.report, ValidationReport {
+            updates: 6,
+            trades: 3,
+            trades_without_level: 1,
+            trades_exceeding_level: 1,
+            negative_sizes: 1,
+            ..Default::default()
+        });
+
+        let mut ob = Orderbook::with_options(2, BookOptions { trades: TradeHandling::Ignore, ..Default::default() });
+        ob.process_update(&up(false, true, 1., 2.));
+        ob.process_update(&up(true, true, 1., 1.));
+        assert_eq!(ob.bids[&100], 2.);
+    }
+
+    #[test]
+    fn test_crossed_book() {
+        let up = |is_bid, price, size| Update { ts: 0, seq: 0, is_trade: false, is_bid, price, size };
+        let ups = [up(true, 1., 1.), up(false, 3., 1.), up(false, 4., 1.), up(true, 3.5, 1.)];
+
+        let mut ob = Orderbook::with_precision(2);
+        ups.iter().for_each(|up| ob.process_update(up));
+        assert!(ob.is_crossed());
+        assert_eq!(ob.report.crossed, 1);
+        assert_eq!(ob.uncross(false), 1);
+        assert_eq!(ob.best_bid_raw(), Some(100));
+
+        let mut ob = Orderbook::with_options(2, BookOptions { crossed: CrossHandling::RemoveStale, ..Default::default() });
+        ups.iter().for_each(|up| ob.process_update(up));
+        assert!(!ob.is_crossed());
+        assert_eq!(ob.asks.keys().collect::<Vec<_>>(), vec![&400]);
+        assert_eq!((ob.report.crossed, ob.report.uncrossed_levels), (0, 1));
+    }
+
+    #[test]
+    fn test_exact_prices() {
+        let ob = Orderbook::with_precision(2);
+        // truncation would put 0.29f32 = 0.28999999 on 0.28
+        assert_eq!(ob.discretize(0.29), 29);
+        assert_eq!(ob.price_string(29), "0.29");
+        assert_eq!(ob.parse_price("0.29"), Some(29));
+        assert_eq!(ob.parse_price("12.345"), Some(1235));
+        assert_eq!(ob.parse_price("12"), Some(1200));
+        assert_eq!(ob.parse_price("1e3"), None);
+
+        let mut ob = Orderbook::with_precision(8).with_tick_size(5);
+        assert_eq!(ob.discretize(0.0703629), 7036290);
+        assert_eq!(ob.discretize(0.07036293), 7036295);
+        assert_eq!(ob.parse_price("0.07036292"), Some(7036290));
+        assert_eq!(ob.checked_discretize(0.07036293), Some(7036295));
+        ob.process_update(&Update { ts: 0, seq: 0, is_trade: false, is_bid: true, price: 0.0703629, size: 1.5 });
+
+        let json = serde_json::to_string(&ob).unwrap();
+        assert_eq!(json, r#"{"price_decimals":8,"tick_size":5,"bids":{"0.07036290":1.5},"asks":{}}"#);
+        assert_eq!(serde_json::from_str::<Orderbook>(&json).unwrap(), ob);
+        assert!(serde_json::from_str::<Orderbook>(r#"{"price_decimals":8,"bids":{"x":1.0},"asks":{}}"#).is_err());
+    }
+
+    #[test]
+    fn test_unrepresentable_prices() {
+        let mut ob = Orderbook::with_precision(18).with_tick_size(10);
+        assert_eq!(ob.checked_discretize(1.), Some(1_000_000_000_000_000_000));
+        assert_eq!(ob.checked_discretize(20.), None);
+        assert_eq!(ob.checked_discretize(-1.), None);
+        assert_eq!(ob.checked_discretize(f32::NAN), None);
+        assert_eq!(ob.discretize(20.), Price::MAX);
+        ob.process_update(&Update { ts: 0, seq: 0, is_trade: false, is_bid: true, price: 20., size: 1. });
+        assert!(ob.bids.is_empty());
+        assert_eq!((ob.report.unrepresentable_prices, ob.report.anomalies()), (1, 1));
+    }
+
     #[test]
     fn test_orderbook_real() {
         let ups = dtf::file_format::decode(ZRX, Some(1000)).unwrap();
@@ -0,0 +1,232 @@
+//! Standalone SVG charts
+//!
+//! Candles are drawn in equal width slots in the order given, with a volume
+//! panel below the prices. Depth heatmaps have one column per time bin and one
+//! row per price bin of a `RebinnedOrderbook`, cells are shaded by the log of
+//! the resting size, bids in green and asks in red.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::postprocessing::candle::Candle;
+use crate::postprocessing::orderbook::RebinnedOrderbook;
+use crate::utils::{epoch_to_human, fill_digits};
+
+static UP_COLOR: &str = "#26a69a";
+static DOWN_COLOR: &str = "#ef5350";
+static TEXT_STYLE: &str = r##"font-family="monospace" font-size="11" fill="#444""##;
+const MARGIN: f64 = 60.;
+
+/// Size and title of a chart
+#[derive(Clone, Debug)]
+pub struct ChartOptions {
+    /// width in pixels
+    pub width: u32,
+    /// height in pixels
+    pub height: u32,
+    /// title drawn above the chart
+    pub title: Option<String>,
+}
+
+impl Default for ChartOptions {
+    fn default() -> Self {
+        ChartOptions { width: 1200, height: 600, title: None }
+    }
+}
+
+/// escape text for use in svg and html
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn human(ts: u64) -> String {
+    epoch_to_human(fill_digits(ts) / 1000)
+}
+
+fn open_svg(opts: &ChartOptions) -> String {
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = opts.width,
+        h = opts.height,
+    );
+    svg.push_str(r#"<rect width="100%" height="100%" fill="white"/>"#);
+    if let Some(title) = &opts.title {
+        let _ = write!(svg, r#"<text x="{}" y="20" {}>{}</text>"#, MARGIN, TEXT_STYLE, escape(title));
+    }
+    svg
+}
+
+/// draw the first and last ts under the x axis and min/max values left of the y axis
+fn axis_labels(svg: &mut String, opts: &ChartOptions, bottom: f64, times: Option<(u64, u64)>, values: &[(f64, f64)]) {
+    let right = f64::from(opts.width) - MARGIN;
+    if let Some((first, last)) = times {
+        let _ = write!(svg, r#"<text x="{}" y="{}" {}>{}</text>"#, MARGIN, bottom + 15., TEXT_STYLE, human(first));
+        let _ = write!(svg, r#"<text x="{}" y="{}" text-anchor="end" {}>{}</text>"#, right, bottom + 15., TEXT_STYLE, human(last));
+    }
+    for &(y, value) in values {
+        let _ = write!(svg, r#"<text x="{}" y="{:.1}" text-anchor="end" {}>{}</text>"#, MARGIN - 5., y + 4., TEXT_STYLE, value);
+    }
+}
+
+/// candlestick chart with a volume panel, candles are drawn in the order given
+pub fn candles_svg<'a, I: IntoIterator<Item=&'a Candle>>(candles: I, opts: &ChartOptions) -> String {
+    let candles: Vec<&Candle> = candles.into_iter().collect();
+    let mut svg = open_svg(opts);
+
+    let (width, height) = (f64::from(opts.width) - 2. * MARGIN, f64::from(opts.height) - 2. * MARGIN);
+    // prices take the upper 3/4, volume the rest
+    let price_height = height * 0.75;
+    let volume_top = MARGIN + price_height + 10.;
+    let volume_height = height - price_height - 10.;
+
+    if !candles.is_empty() {
+        let low = candles.iter().map(|c| c.low).fold(f32::INFINITY, f32::min);
+        let high = candles.iter().map(|c| c.high).fold(f32::NEG_INFINITY, f32::max);
+        let max_volume = candles.iter().map(|c| c.volume).fold(0., f32::max);
+        let range = if high > low { f64::from(high - low) } else { 1. };
+        let y = |price: f32| MARGIN + (f64::from(high) - f64::from(price)) / range * price_height;
+
+        let slot = width / candles.len() as f64;
+        let body_width = (slot * 0.7).max(1.);
+        for (i, c) in candles.iter().enumerate() {
+            let color = if c.close >= c.open { UP_COLOR } else { DOWN_COLOR };
+            let center = MARGIN + slot * (i as f64 + 0.5);
+            let (top, bottom) = (y(c.open.max(c.close)), y(c.open.min(c.close)));
+            let _ = write!(
+                svg,
+                r#"<line x1="{x:.2}" y1="{:.2}" x2="{x:.2}" y2="{:.2}" stroke="{}"/>"#,
+                y(c.high), y(c.low), color, x = center,
+            );
+            let _ = write!(
+                svg,
+                r#"<rect class="candle" x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}"/>"#,
+                center - body_width / 2., top, body_width, (bottom - top).max(1.), color,
+            );
+            if max_volume > 0. {
+                let bar = f64::from(c.volume / max_volume) * volume_height;
+                let _ = write!(
+                    svg,
+                    r#"<rect class="volume" x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}" fill-opacity="0.5"/>"#,
+                    center - body_width / 2., volume_top + volume_height - bar, body_width, bar, color,
+                );
+            }
+        }
+
+        let times = Some((candles[0].start, candles[candles.len() - 1].start));
+        let values = [(y(high), f64::from(high)), (y(low), f64::from(low)), (volume_top, f64::from(max_volume))];
+        axis_labels(&mut svg, opts, MARGIN + height, times, &values);
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// depth heatmap of a rebinned orderbook
+pub fn heatmap_svg(ob: &RebinnedOrderbook, opts: &ChartOptions) -> String {
+    let mut svg = open_svg(opts);
+
+    // rows are every coarse price present at any time, highest on top
+    let prices: Vec<u64> = ob.book.values()
+        .flat_map(|book| book.bids.keys().chain(book.asks.keys()).cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    let max_size = ob.book.values()
+        .flat_map(|book| book.bids.values().chain(book.asks.values()))
+        .fold(0., |acc: f64, &size| acc.max(size));
+
+    if !prices.is_empty() && max_size > 0. {
+        let (width, height) = (f64::from(opts.width) - 2. * MARGIN, f64::from(opts.height) - 2. * MARGIN);
+        let (cell_width, cell_height) = (width / ob.book.len() as f64, height / prices.len() as f64);
+        let row = |price: &u64| prices.iter().position(|p| p == price).unwrap();
+        let scale = max_size.ln_1p();
+
+        for (col, book) in ob.book.values().enumerate() {
+            let x = MARGIN + cell_width * col as f64;
+            let sides = book.bids.iter().map(|l| (l, UP_COLOR)).chain(book.asks.iter().map(|l| (l, DOWN_COLOR)));
+            for ((price, &size), color) in sides {
+                if size <= 0. {
+                    continue;
+                }
+                let _ = write!(
+                    svg,
+                    r#"<rect class="cell" x="{:.2}" y="{:.2}" width="{:.2}" height="{:.2}" fill="{}" fill-opacity="{:.3}"/>"#,
+                    x, MARGIN + cell_height * row(price) as f64, cell_width, cell_height, color, size.ln_1p() / scale,
+                );
+            }
+        }
+
+        let template = ob.book.values().next().unwrap();
+        let times = Some((
+            f64::from_bits(*ob.book.keys().next().unwrap()) as u64,
+            f64::from_bits(*ob.book.keys().next_back().unwrap()) as u64,
+        ));
+        let values = [
+            (MARGIN, f64::from(template.undiscretize(prices[0]))),
+            (MARGIN + height, f64::from(template.undiscretize(prices[prices.len() - 1]))),
+        ];
+        axis_labels(&mut svg, opts, MARGIN + height, times, &values);
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// standalone html page showing the charts one below the other
+pub fn html_page(title: &str, svgs: &[String]) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h3>{title}</h3>\n{}\n</body>\n</html>\n",
+        svgs.iter().map(|svg| format!("<div>{}</div>", svg)).collect::<Vec<_>>().join("\n"),
+        title = escape(title),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dtf::update::Update;
+    use crate::postprocessing::candle::TradeFlow;
+
+    fn candle(start: u64, open: f32, close: f32) -> Candle {
+        Candle {
+            start,
+            end: start,
+            open,
+            high: open.max(close) + 1.,
+            low: open.min(close) - 1.,
+            close,
+            volume: 2.,
+            flow: TradeFlow::default(),
+        }
+    }
+
+    #[test]
+    fn should_draw_charts() {
+        let candles = vec![candle(60, 10., 12.), candle(120, 12., 11.), candle(180, 11., 11.)];
+        let svg = candles_svg(&candles, &ChartOptions { title: Some("a<b".to_owned()), ..Default::default() });
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>"));
+        assert_eq!(svg.matches(r#"class="candle""#).count(), 3);
+        assert_eq!(svg.matches(r#"class="volume""#).count(), 3);
+        assert_eq!(svg.matches(DOWN_COLOR).count(), 3);
+        assert!(svg.contains("a&lt;b"));
+        assert_eq!(candles_svg(&[], &ChartOptions::default()).matches("<rect").count(), 1);
+
+        let ups: Vec<Update> = (0..20)
+            .map(|i| Update { ts: i * 1000, seq: 0, is_trade: false, is_bid: i % 2 == 0, price: 100. + (i % 4) as f32, size: 1. + i as f32 })
+            .collect();
+        let ob = RebinnedOrderbook::from(2, &ups, 5, 5, 2.);
+        let svg = heatmap_svg(&ob, &ChartOptions::default());
+        let cells: usize = ob.book.values()
+            .map(|book| book.bids.values().chain(book.asks.values()).filter(|&&size| size > 0.).count())
+            .sum();
+        assert!(cells > 0);
+        assert_eq!(svg.matches(r#"class="cell""#).count(), cells);
+
+        let page = html_page("day", &[svg]);
+        assert!(page.contains("<title>day</title>") && page.contains("<div><svg"));
+    }
+}
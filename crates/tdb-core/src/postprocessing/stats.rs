@@ -0,0 +1,216 @@
+//! Aggregate analytics over a range of updates
+//!
+//! Trade based statistics (VWAP, trade sizes) use every trade. Book based
+//! statistics (TWAP, realized variance, spread) use the book sampled at the
+//! end of every `sampling` ms, steps without updates repeat the last book.
+
+use crate::dtf::update::Update;
+use crate::postprocessing::orderbook::Orderbook;
+use crate::utils::fill_digits;
+
+/// Statistics of a range of updates
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stats {
+    /// ts of the first update
+    pub start: u64,
+    /// ts of the last update
+    pub end: u64,
+    /// number of trades
+    pub trades: usize,
+    /// traded size
+    pub volume: f64,
+    /// volume weighted average trade price
+    pub vwap: Option<f64>,
+    /// time weighted average midprice
+    pub twap: Option<f64>,
+    /// sum of squared log returns of the sampled midprice
+    pub realized_variance: Option<f64>,
+    /// square root of the realized variance
+    pub realized_volatility: Option<f64>,
+    /// number of book samples with both sides
+    pub samples: usize,
+    /// spread of the sampled book
+    pub spread: Option<Summary>,
+    /// distribution of trade sizes
+    pub trade_sizes: Option<Summary>,
+}
+
+/// Distribution of a series
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Summary {
+    /// smallest value
+    pub min: f64,
+    /// largest value
+    pub max: f64,
+    /// average
+    pub mean: f64,
+    /// 50th percentile
+    pub median: f64,
+    /// 90th percentile
+    pub p90: f64,
+    /// 99th percentile
+    pub p99: f64,
+}
+
+impl Summary {
+    /// summarize values, None when empty
+    pub fn from_values(mut values: Vec<f64>) -> Option<Summary> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        // nearest rank
+        let percentile = |p: f64| values[((p * values.len() as f64).ceil() as usize).max(1) - 1];
+        Some(Summary {
+            min: values[0],
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        })
+    }
+}
+
+impl Stats {
+    /// compute statistics of `ups` applied to an empty book
+    pub fn from_updates<I: Iterator<Item=Update>>(price_decimals: u8, ups: I, sampling: u64) -> Stats {
+        Stats::with_book(Orderbook::with_precision(price_decimals), ups, sampling)
+    }
+
+    /// compute statistics of `ups` applied to `book`
+    pub fn with_book<I: Iterator<Item=Update>>(book: Orderbook, ups: I, sampling: u64) -> Stats {
+        let mut builder = StatsBuilder::new(book, sampling);
+        ups.for_each(|up| builder.push(&up));
+        builder.finish()
+    }
+}
+
+/// Computes `Stats` from updates fed one at a time, so they can be streamed from disk
+pub struct StatsBuilder {
+    book: Orderbook,
+    sampling: u64,
+    mids: Vec<f64>,
+    spreads: Vec<f64>,
+    range: Option<(u64, u64)>,
+    /// sampling step of the last update
+    current: Option<u64>,
+    sizes: Vec<f64>,
+    notional: f64,
+}
+
+impl StatsBuilder {
+    /// start from `book`, sampling it every `sampling` ms
+    pub fn new(book: Orderbook, sampling: u64) -> Self {
+        StatsBuilder {
+            book,
+            sampling: sampling.max(1),
+            mids: vec![],
+            spreads: vec![],
+            range: None,
+            current: None,
+            sizes: vec![],
+            notional: 0.,
+        }
+    }
+
+    /// apply an update from before the range to the book without counting it
+    pub fn warm_up(&mut self, up: &Update) {
+        self.book.process_update(up);
+    }
+
+    fn sample(&mut self) {
+        if let Some(((bid, _), (ask, _))) = self.book.top() {
+            self.mids.push((f64::from(bid) + f64::from(ask)) / 2.);
+            self.spreads.push(f64::from(ask) - f64::from(bid));
+        }
+    }
+
+    /// feed the next update of the range
+    pub fn push(&mut self, up: &Update) {
+        let step = fill_digits(up.ts) / self.sampling * self.sampling;
+        if let Some(mut t) = self.current {
+            while t < step {
+                self.sample();
+                t += self.sampling;
+            }
+        }
+        self.current = Some(step);
+        self.range = Some((self.range.map(|(start, _)| start).unwrap_or(up.ts), up.ts));
+
+        if up.is_trade {
+            self.sizes.push(f64::from(up.size));
+            self.notional += f64::from(up.price) * f64::from(up.size);
+        }
+        self.book.process_update(up);
+    }
+
+    /// statistics of every update pushed
+    pub fn finish(mut self) -> Stats {
+        if self.current.is_some() {
+            self.sample();
+        }
+
+        let volume: f64 = self.sizes.iter().sum();
+        let realized_variance = if self.mids.len() > 1 {
+            Some(self.mids.windows(2)
+                .filter(|w| w[0] > 0. && w[1] > 0.)
+                .map(|w| (w[1] / w[0]).ln().powi(2))
+                .sum::<f64>())
+        } else {
+            None
+        };
+        let (start, end) = self.range.unwrap_or((0, 0));
+        let mids = &self.mids;
+
+        Stats {
+            start,
+            end,
+            trades: self.sizes.len(),
+            volume,
+            vwap: if volume > 0. { Some(self.notional / volume) } else { None },
+            twap: if mids.is_empty() { None } else { Some(mids.iter().sum::<f64>() / mids.len() as f64) },
+            realized_variance,
+            realized_volatility: realized_variance.map(f64::sqrt),
+            samples: mids.len(),
+            spread: Summary::from_values(self.spreads),
+            trade_sizes: Summary::from_values(self.sizes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(ts: u64, is_trade: bool, is_bid: bool, price: f32, size: f32) -> Update {
+        Update { ts, seq: 0, is_trade, is_bid, price, size }
+    }
+
+    #[test]
+    fn should_compute_stats() {
+        let ups = vec![
+            up(1_000_000_000_000, false, true, 9., 1.),
+            up(1_000_000_000_000, false, false, 11., 1.),
+            up(1_000_000_000_500, true, false, 10.5, 1.),
+            up(1_000_000_000_500, true, false, 12., 3.),
+            // the book is unchanged for a step, then the midprice doubles
+            up(1_000_000_002_000, false, true, 9., 0.),
+            up(1_000_000_002_000, false, false, 11., 0.),
+            up(1_000_000_002_000, false, true, 19., 1.),
+            up(1_000_000_002_000, false, false, 21., 1.),
+        ];
+        let stats = Stats::from_updates(2, ups.into_iter(), 1000);
+        assert_eq!((stats.start, stats.end), (1_000_000_000_000, 1_000_000_002_000));
+        assert_eq!((stats.trades, stats.volume), (2, 4.));
+        assert_eq!(stats.vwap, Some((10.5 + 36.) / 4.));
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.twap, Some(40. / 3.));
+        assert_eq!(stats.realized_variance, Some(2f64.ln().powi(2)));
+
+        let spread = stats.spread.unwrap();
+        assert_eq!((spread.min, spread.max, spread.median), (2., 2., 2.));
+        let sizes = stats.trade_sizes.unwrap();
+        assert_eq!((sizes.min, sizes.max, sizes.median, sizes.p99), (1., 3., 1., 3.));
+    }
+}
@@ -0,0 +1,240 @@
+//! Dense orderbook tensors for machine learning
+//!
+//! The book is sampled at the end of every time step and its sizes are
+//! binned onto a fixed price grid, so every step has the same shape:
+//! `[time_steps × price_levels × {bid, ask}]`. Steps without updates repeat
+//! the previous book.
+
+use std::io::{self, Write};
+
+use crate::dtf::update::Update;
+use crate::postprocessing::orderbook::Orderbook;
+use crate::utils::fill_digits;
+
+/// Price grid the levels are binned on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grid {
+    /// level `i` is `i` ticks away from the midprice, on each side
+    Relative {
+        /// width of a level
+        tick: f32,
+    },
+    /// level `i` starts at `min + i * tick`, shared by both sides
+    Absolute {
+        /// price of the first level
+        min: f32,
+        /// width of a level
+        tick: f32,
+    },
+}
+
+/// How sizes of a time step are scaled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    /// raw sizes
+    None,
+    /// divide by the total size of the step
+    Total,
+    /// divide by the largest level of the step
+    Max,
+    /// ln(1 + size)
+    Log,
+}
+
+/// Shape and scaling of the tensor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TensorConfig {
+    /// length of a time step in ms
+    pub step: u64,
+    /// number of price levels
+    pub levels: usize,
+    /// price grid
+    pub grid: Grid,
+    /// normalization applied to each step
+    pub normalization: Normalization,
+}
+
+/// Dense `[ts.len() × levels × 2]` array in row-major order, bids in channel 0
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderbookTensor {
+    /// number of price levels
+    pub levels: usize,
+    /// start of each time step
+    pub ts: Vec<u64>,
+    /// midprice at the end of each step, NaN when a side is empty
+    pub midprice: Vec<f32>,
+    /// sizes
+    pub data: Vec<f32>,
+}
+
+impl OrderbookTensor {
+    /// sample the book driven by `ups`
+    pub fn from_updates<I: Iterator<Item=Update>>(price_decimals: u8, ups: I, config: &TensorConfig) -> OrderbookTensor {
+        let step = config.step.max(1);
+        let mut tensor = OrderbookTensor {
+            levels: config.levels,
+            ts: vec![],
+            midprice: vec![],
+            data: vec![],
+        };
+        let mut book = Orderbook::with_precision(price_decimals);
+        let mut current: Option<u64> = None;
+        for up in ups {
+            let start = fill_digits(up.ts) / step * step;
+            if let Some(mut t) = current {
+                while t < start {
+                    tensor.push(t, &book, config);
+                    t += step;
+                }
+            }
+            current = Some(start);
+            book.process_update(&up);
+        }
+        if let Some(t) = current {
+            tensor.push(t, &book, config);
+        }
+        tensor
+    }
+
+    /// `[time_steps, levels, 2]`
+    pub fn shape(&self) -> [usize; 3] {
+        [self.ts.len(), self.levels, 2]
+    }
+
+    /// size at a time step, level and side
+    pub fn get(&self, step: usize, level: usize, is_bid: bool) -> f32 {
+        self.data[(step * self.levels + level) * 2 + if is_bid { 0 } else { 1 }]
+    }
+
+    fn push(&mut self, ts: u64, book: &Orderbook, config: &TensorConfig) {
+        let mid = book.midprice();
+        let offset = self.data.len();
+        self.data.resize(offset + self.levels * 2, 0.);
+        self.ts.push(ts);
+        self.midprice.push(mid.unwrap_or(f32::NAN));
+
+        let row = &mut self.data[offset..];
+        for (channel, side) in [&book.bids, &book.asks].iter().enumerate() {
+            for (&price, &size) in side.iter() {
+                let price = book.undiscretize(price);
+                let idx = match config.grid {
+                    Grid::Relative { tick } => {
+                        let mid = match mid { Some(mid) => mid, None => break };
+                        let dist = if channel == 0 { mid - price } else { price - mid };
+                        (dist / tick).floor()
+                    }
+                    Grid::Absolute { min, tick } => ((price - min) / tick).floor(),
+                };
+                if idx >= 0. && (idx as usize) < self.levels {
+                    row[idx as usize * 2 + channel] += size as f32;
+                }
+            }
+        }
+
+        match config.normalization {
+            Normalization::None => (),
+            Normalization::Total => {
+                let total: f32 = row.iter().sum();
+                if total > 0. { row.iter_mut().for_each(|v| *v /= total); }
+            }
+            Normalization::Max => {
+                let max = row.iter().cloned().fold(0., f32::max);
+                if max > 0. { row.iter_mut().for_each(|v| *v /= max); }
+            }
+            Normalization::Log => row.iter_mut().for_each(|v| *v = v.ln_1p()),
+        }
+    }
+
+    /// write the sizes as a `.npy` array
+    pub fn write_npy(&self, wtr: &mut dyn Write) -> Result<(), io::Error> {
+        wtr.write_all(&npy_header("<f4", &self.shape()))?;
+        for v in &self.data {
+            wtr.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// write the start of each step as a `.npy` array
+    pub fn write_ts_npy(&self, wtr: &mut dyn Write) -> Result<(), io::Error> {
+        wtr.write_all(&npy_header("<u8", &[self.ts.len()]))?;
+        for v in &self.ts {
+            wtr.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// write the midprice of each step as a `.npy` array
+    pub fn write_midprice_npy(&self, wtr: &mut dyn Write) -> Result<(), io::Error> {
+        wtr.write_all(&npy_header("<f4", &[self.midprice.len()]))?;
+        for v in &self.midprice {
+            wtr.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// `.npy` version 1.0 header of a C-ordered array, padded to 64 bytes
+pub fn npy_header(descr: &str, shape: &[usize]) -> Vec<u8> {
+    let dims = match shape {
+        [n] => format!("{},", n),
+        _ => shape.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", "),
+    };
+    let mut dict = format!("{{'descr': '{}', 'fortran_order': False, 'shape': ({}), }}", descr, dims);
+    // magic (6) + version (2) + header len (2) + dict + '\n'
+    while (10 + dict.len() + 1) % 64 != 0 {
+        dict.push(' ');
+    }
+    dict.push('\n');
+
+    let mut buf = vec![0x93, b'N', b'U', b'M', b'P', b'Y', 0x01, 0x00];
+    buf.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    buf.extend_from_slice(dict.as_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up(ts: u64, is_bid: bool, price: f32, size: f32) -> Update {
+        Update { ts, seq: 0, is_trade: false, is_bid, price, size }
+    }
+
+    #[test]
+    fn should_sample_fixed_shape_tensor() {
+        let ups = vec![
+            up(1_000_000_000_000, true, 9.5, 2.),
+            up(1_000_000_000_000, true, 9.0, 1.),
+            up(1_000_000_000_000, false, 10.5, 3.),
+            up(1_000_000_003_000, false, 11.5, 4.),
+        ];
+        let config = TensorConfig {
+            step: 1000,
+            levels: 2,
+            grid: Grid::Relative { tick: 1. },
+            normalization: Normalization::None,
+        };
+        let tensor = OrderbookTensor::from_updates(2, ups.clone().into_iter(), &config);
+        assert_eq!(tensor.shape(), [4, 2, 2]);
+        assert_eq!(tensor.midprice[0], 10.);
+        // 9.5 is half a tick below the midprice, 9.0 is one tick
+        assert_eq!((tensor.get(0, 0, true), tensor.get(0, 1, true)), (2., 1.));
+        assert_eq!((tensor.get(2, 0, false), tensor.get(2, 1, false)), (3., 0.));
+        assert_eq!((tensor.get(3, 0, false), tensor.get(3, 1, false)), (3., 4.));
+
+        let config = TensorConfig {
+            grid: Grid::Absolute { min: 9., tick: 1. },
+            normalization: Normalization::Total,
+            ..config
+        };
+        let tensor = OrderbookTensor::from_updates(2, ups.into_iter(), &config);
+        assert_eq!(tensor.get(0, 0, true), 0.5);
+        assert_eq!(tensor.get(0, 1, false), 0.5);
+
+        let mut buf = vec![];
+        tensor.write_npy(&mut buf).unwrap();
+        let header = npy_header("<f4", &tensor.shape());
+        assert_eq!(header.len() % 64, 0);
+        assert_eq!(buf.len(), header.len() + 4 * tensor.data.len());
+    }
+}
@@ -0,0 +1,174 @@
+//! Token based authentication and per-book access control.
+//!
+//! Users are read from a plain text file, one user per line:
+//!
+//! ```text
+//! # name      token           role      books (optional, comma separated patterns)
+//! ingest      s3cr3t-token    writer
+//! research    r34d-only       read      bnc_*,bt_*
+//! ops         4dm1n           admin
+//! ```
+//!
+//! Roles are ordered: `read` < `writer` < `admin`. A user may only touch books
+//! matching one of its patterns; no patterns means every book. `*` matches
+//! any sequence of characters.
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+/// Access level granted to a user
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// may query books but never modify them
+    ReadOnly,
+    /// may insert into, create, flush and clear individual books
+    Writer,
+    /// unrestricted, including `FLUSH ALL` and `CLEAR ALL`
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "read" | "readonly" | "read-only" => Ok(Role::ReadOnly),
+            "write" | "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Role::ReadOnly => write!(f, "read"),
+            Role::Writer => write!(f, "writer"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// An authenticated principal
+#[derive(Clone, Debug, PartialEq)]
+pub struct User {
+    /// name of the user, only used for logging
+    pub name: String,
+    /// secret sent with `AUTH [token]`
+    pub token: String,
+    /// access level
+    pub role: Role,
+    /// book name patterns this user may access, empty means all
+    pub books: Vec<String>,
+}
+
+impl User {
+    /// check if user is allowed to access `book_name`
+    pub fn can_access(&self, book_name: &str) -> bool {
+        self.books.is_empty() || self.books.iter().any(|p| matches_pattern(p, book_name))
+    }
+}
+
+/// Users loaded from the auth file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthConfig {
+    /// every known user
+    pub users: Vec<User>,
+}
+
+impl AuthConfig {
+    /// read users from file
+    pub fn from_file(fname: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(fname)
+            .map_err(|e| format!("Unable to read auth file {}: {}", fname, e))?;
+        Ok(text.parse()?)
+    }
+
+    /// find the user owning `token`
+    pub fn authenticate(&self, token: &str) -> Option<&User> {
+        self.users.iter().find(|u| u.token == token)
+    }
+}
+
+impl FromStr for AuthConfig {
+    type Err = String;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut users = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 3 || cols.len() > 4 {
+                return Err(format!("line {}: expected `name token role [books]`", i + 1));
+            }
+            let role = cols[2].parse().map_err(|e| format!("line {}: {}", i + 1, e))?;
+            let books = cols.get(3)
+                .map(|p| p.split(',').filter(|p| !p.is_empty()).map(String::from).collect())
+                .unwrap_or_default();
+            users.push(User {
+                name: cols[0].to_owned(),
+                token: cols[1].to_owned(),
+                role,
+                books,
+            });
+        }
+        Ok(AuthConfig { users })
+    }
+}
+
+/// glob style matching where `*` matches any (possibly empty) sequence
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == name;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !name.starts_with(first) || !name[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_patterns() {
+        assert!(matches_pattern("bnc_*", "bnc_btc_eth"));
+        assert!(matches_pattern("*_eth", "bnc_btc_eth"));
+        assert!(matches_pattern("bnc_*_eth", "bnc_btc_eth"));
+        assert!(matches_pattern("*", "anything"));
+        assert!(matches_pattern("default", "default"));
+        assert!(!matches_pattern("bnc_*", "bt_btc_eth"));
+        assert!(!matches_pattern("bnc_*_eth", "bnc_btc_neo"));
+        assert!(!matches_pattern("a*a", "a"));
+    }
+
+    #[test]
+    fn should_parse_auth_file() {
+        let conf: AuthConfig = "
+            # comment
+            ingest  tok1  writer
+            research tok2 read bnc_*,bt_*
+        ".parse().unwrap();
+        assert_eq!(conf.users.len(), 2);
+        let research = conf.authenticate("tok2").unwrap();
+        assert_eq!(research.role, Role::ReadOnly);
+        assert!(research.can_access("bnc_btc_eth"));
+        assert!(!research.can_access("default"));
+        assert!(conf.authenticate("tok1").unwrap().can_access("default"));
+        assert!(conf.authenticate("nope").is_none());
+        assert!("a b superuser".parse::<AuthConfig>().is_err());
+    }
+}
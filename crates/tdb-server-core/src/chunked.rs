@@ -0,0 +1,174 @@
+//! Chunked responses for `GET ... STREAM`, and replies that read disk.
+//!
+//! Every chunk is an ok frame with at most `CHUNK_ROWS` updates in the
+//! requested format, an empty ok frame marks the end, or an error frame when a
+//! file could not be read. Chunks are read on their
+//! own thread so the broker keeps serving other clients, and the bounded
+//! channel pauses the reader while the client falls behind. `spawn_reply`
+//! computes a single reply, e.g. `CANDLES`, on its own thread the same way.
+use crate::prelude::*;
+use crate::handler::ResponseStream;
+use crate::state::{into_format, replay_range};
+use std::thread;
+
+/// updates per chunk
+pub const CHUNK_ROWS: usize = 10_000;
+/// chunks buffered ahead of the connection writer
+const CHANNEL_CHUNKS: usize = 4;
+
+/// What a streaming `GET` returns
+#[derive(Debug)]
+pub struct StreamRequest {
+    /// read files of `symbol` in this folder before `mem`, memory only when none
+    pub folder: Option<String>,
+    pub symbol: String,
+    /// inclusive
+    pub range: (u64, u64),
+    /// stop after this many updates
+    pub count: Option<usize>,
+    /// updates in memory within `range`
+    pub mem: Vec<Update>,
+    pub format: GetFormat,
+}
+
+/// start producing chunks, the stream ends when every update was sent, a file could not be read or the client left
+pub fn spawn(req: StreamRequest) -> ResponseStream {
+    let (tx, rx) = mpsc::channel(CHANNEL_CHUNKS);
+    thread::spawn(move || run(req, tx));
+    ResponseStream(rx)
+}
+
+/// compute a reply on its own thread, the connection writer waits for it
+pub fn spawn_reply<F: FnOnce() -> ReturnType + Send + 'static>(f: F) -> ReturnType {
+    let (mut tx, rx) = mpsc::channel(1);
+    thread::spawn(move || {
+        // the client may have left
+        let _ = task::block_on(tx.send(f()));
+    });
+    ReturnType::Deferred(ResponseStream(rx))
+}
+
+fn run(req: StreamRequest, tx: Sender<ReturnType>) {
+    let mut chunker = Chunker {
+        tx,
+        format: req.format,
+        chunk: Vec::with_capacity(CHUNK_ROWS),
+        remaining: req.count.unwrap_or(usize::MAX),
+        closed: false,
+    };
+    let read = replay_range(req.folder.as_deref(), &req.symbol, req.range, &req.mem, &mut |up| chunker.push(up));
+    chunker.flush();
+    if let Err(e) = read {
+        if !chunker.closed {
+            let _ = task::block_on(chunker.tx.send(ReturnType::error(e)));
+        }
+    }
+}
+
+struct Chunker {
+    tx: Sender<ReturnType>,
+    format: GetFormat,
+    chunk: Vec<Update>,
+    remaining: usize,
+    /// the client disconnected
+    closed: bool,
+}
+
+impl Chunker {
+    /// returns whether more updates are wanted
+    fn push(&mut self, up: &Update) -> bool {
+        if self.closed || self.remaining == 0 {
+            return false;
+        }
+        self.chunk.push(*up);
+        self.remaining -= 1;
+        if self.chunk.len() == CHUNK_ROWS {
+            self.flush();
+        }
+        !self.closed && self.remaining > 0
+    }
+
+    fn flush(&mut self) {
+        if self.chunk.is_empty() || self.closed {
+            return;
+        }
+        if let Some(ret) = into_format(&self.chunk, self.format) {
+            self.closed = task::block_on(self.tx.send(ret)).is_err();
+        }
+        self.chunk.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_stream_in_chunks() {
+        let mem = (0..CHUNK_ROWS as u64 + 5)
+            .map(|i| Update { ts: i, seq: 0, is_trade: false, is_bid: true, price: 1., size: 1. })
+            .collect();
+        let req = StreamRequest {
+            folder: None,
+            symbol: "default".to_owned(),
+            range: (0, u64::MAX),
+            count: Some(CHUNK_ROWS + 2),
+            mem,
+            format: GetFormat::Csv,
+        };
+        let chunks: Vec<ReturnType> = task::block_on(spawn(req).0.collect());
+        assert_eq!(chunks.len(), 2);
+        match &chunks[1] {
+            ReturnType::String(s) => assert_eq!(s.lines().count(), 2),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    fn folder_with_files(name: &str) -> String {
+        let folder = std::env::temp_dir().join(format!("tdb_{}_{}", name, std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        std::fs::create_dir_all(&folder).unwrap();
+        for file in 0..2 {
+            let ups: Vec<Update> = (file * 100..file * 100 + 100)
+                .map(|ts| Update { ts, seq: 0, is_trade: false, is_bid: true, price: 1., size: 1. })
+                .collect();
+            tdb_core::dtf::file_format::encode(&format!("{}/{}.dtf", folder, file), "default", &ups).unwrap();
+        }
+        folder
+    }
+
+    #[test]
+    fn should_stop_reading_once_count_is_reached() {
+        let folder = folder_with_files("stop");
+        let mut seen = 0;
+        replay_range(Some(&folder), "default", (0, u64::MAX), &[], &mut |_| {
+            seen += 1;
+            seen < 3
+        }).unwrap();
+        assert_eq!(seen, 3);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn should_end_stream_with_error_frame() {
+        let folder = folder_with_files("unreadable");
+        // cut the last update of the newer file in half
+        let fname = format!("{}/1.dtf", folder);
+        let len = std::fs::metadata(&fname).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&fname).unwrap().set_len(len - 2).unwrap();
+        let req = StreamRequest {
+            folder: Some(folder.clone()),
+            symbol: "default".to_owned(),
+            range: (0, u64::MAX),
+            count: None,
+            mem: vec![],
+            format: GetFormat::Csv,
+        };
+        let chunks: Vec<ReturnType> = task::block_on(spawn(req).0.collect());
+        match chunks.last() {
+            Some(ReturnType::Error(e)) => assert!(e.starts_with(&format!("Unable to read {}", fname)), "{}", e),
+            r => panic!("{:?}", r),
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! TOML configuration file for tdb-server.
+//!
+//! Every key is optional. Values given on the command line or in `TDB_*`
+//! environment variables take precedence over the file.
+//!
+//! ```toml
+//! [server]
+//! host = "0.0.0.0"
+//! port = 9001
+//! log_file = "tdb.log"
+//! auth_file = "users.txt"
+//! tls_cert = "cert.pem"
+//! tls_key = "key.pem"
+//! metrics_addr = "127.0.0.1:9002"
+//!
+//! [storage]
+//! dtf_folder = "db"
+//! autoflush = true
+//! flush_interval = 1000
+//! granularity = 30
+//! q_capacity = 300
+//! # evict loaded books beyond this, 0 is unlimited
+//! memory_limit_mb = 4096
+//! # orderbook checkpoints, 0 disables
+//! snapshot_interval = 100000
+//! snapshot_secs = 0
+//!
+//! [plugins.influx]
+//! host = "http://localhost:8086"
+//! db = "market_data"
+//! interval = 60
+//!
+//! [plugins.gstorage]
+//! bucket_name = "tick_data"
+//!
+//! # exact book names or patterns, `*` matches anything
+//! [books."bnc_*"]
+//! price_decimals = 8
+//! # prices are rounded to multiples of 5 * 10^-8
+//! tick_size = 5
+//! flush_interval = 5000
+//! ```
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+
+/// Contents of the config file
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub plugins: PluginsConfig,
+    /// keyed by book name or pattern
+    pub books: BTreeMap<String, BookSettings>,
+}
+
+/// `[server]` section
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub log_file: Option<String>,
+    pub verbosity: Option<u8>,
+    pub auth_file: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub metrics_addr: Option<String>,
+}
+
+/// `[storage]` section
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub dtf_folder: Option<String>,
+    pub autoflush: Option<bool>,
+    pub flush_interval: Option<u32>,
+    pub granularity: Option<u64>,
+    pub q_capacity: Option<usize>,
+    pub memory_limit_mb: Option<usize>,
+    pub snapshot_interval: Option<u32>,
+    pub snapshot_secs: Option<u64>,
+}
+
+/// `[plugins.*]` sections
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    pub influx: Option<InfluxConfig>,
+    pub gstorage: Option<GStorageSettings>,
+}
+
+/// `[plugins.influx]` section
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxConfig {
+    pub host: String,
+    pub db: String,
+    #[serde(default = "default_influx_interval")]
+    pub interval: u64,
+}
+
+fn default_influx_interval() -> u64 { 60 }
+
+/// `[plugins.gstorage]` section, `GCLOUD_*` env vars override these
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GStorageSettings {
+    pub oauth_token: Option<String>,
+    pub bucket_name: Option<String>,
+    pub folder: Option<String>,
+    pub remove: Option<bool>,
+    pub dcb_url: Option<String>,
+    pub upload_interval_secs: Option<u64>,
+    pub min_file_size: Option<u64>,
+}
+
+/// `[books.NAME]` section, unset values fall back to `[storage]`
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BookSettings {
+    /// digits kept when discretizing prices in the orderbook
+    pub price_decimals: Option<u8>,
+    /// smallest price increment in units of `10^-price_decimals`
+    pub tick_size: Option<u64>,
+    pub autoflush: Option<bool>,
+    pub flush_interval: Option<u32>,
+}
+
+impl FileConfig {
+    /// read and parse config file
+    pub fn from_file(fname: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(fname)
+            .map_err(|e| format!("Unable to read config file {}: {}", fname, e))?;
+        let conf = toml::from_str(&text)
+            .map_err(|e| format!("Invalid config file {}: {}", fname, e))?;
+        Ok(conf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_config() {
+        let conf: FileConfig = toml::from_str(r#"
+            [server]
+            port = 9005
+            [storage]
+            autoflush = true
+            [plugins.influx]
+            host = "http://localhost:8086"
+            db = "market_data"
+            [books."bnc_*"]
+            price_decimals = 8
+            tick_size = 5
+        "#).unwrap();
+        assert_eq!(conf.server.port, Some(9005));
+        assert_eq!(conf.storage.autoflush, Some(true));
+        assert_eq!(conf.storage.flush_interval, None);
+        assert_eq!(conf.plugins.influx.unwrap().interval, 60);
+        assert_eq!(conf.books["bnc_*"].price_decimals, Some(8));
+        assert_eq!(conf.books["bnc_*"].tick_size, Some(5));
+    }
+
+    #[test]
+    fn should_reject_unknown_keys() {
+        let err = toml::from_str::<FileConfig>("[storage]\nautoflsh = true\n").unwrap_err();
+        assert!(err.to_string().contains("autoflsh"));
+        assert!(toml::from_str::<FileConfig>("[storage]\nflush_interval = \"often\"\n").is_err());
+    }
+}
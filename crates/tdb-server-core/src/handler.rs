@@ -5,14 +5,42 @@ pub enum ReturnType {
     String(Cow<'static, str>),
     Bytes(Vec<u8>),
     Error(Cow<'static, str>),
+    /// chunks written as separate frames, followed by an empty frame
+    Stream(ResponseStream),
+    /// a single reply produced off the broker, the first message of the stream
+    Deferred(ResponseStream),
 }
 
+/// Chunks of a streaming response, see `chunked`
+pub struct ResponseStream(pub Receiver<ReturnType>);
+
+impl std::fmt::Debug for ResponseStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ResponseStream")
+    }
+}
+
+/// streams are only equal to themselves
+impl PartialEq for ResponseStream {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for ResponseStream {}
+
 impl ReturnType {
 
     pub const HELP_STR: &'static str = "
-    PING, INFO, USE [db], CREATE [db],
+    PING, INFO, AUTH [token], USE [db], CREATE [db],
+    CONFIG GET [key], CONFIG SET [key] [value],
+    DROP [db] CONFIRM, RENAME [old] [new],
+    OB [db] AT [epoch] [DEPTH n], SUBSCRIBE [db] [FROM epoch], SUBSCRIBE [db] BBO,
+    CANDLES [db] [1m|TICKS n|VOLUME v|DOLLARS d] FROM [epoch] TO [epoch] [AS CSV|JSON],
+    STATS [db] FROM [epoch] TO [epoch] [EVERY secs],
     ADD [ts],[seq],[is_trade],[is_bid],[price],[size];
-    FLUSH, FLUSH ALL, GET ALL, GET [count], CLEAR";
+    FLUSH, FLUSH ALL, GET ALL, GET [count], CLEAR,
+    GET [count|ALL] [FROM epoch TO epoch] [AS CSV|JSON] STREAM";
 
     pub fn ok() -> ReturnType {
         ReturnType::String("1".into())
@@ -38,13 +66,26 @@ pub enum ReqCount {
     Count(u32),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum GetFormat {
     Json,
     Csv,
     Dtf,
 }
 
+/// how candles are sampled
+#[derive(Debug, PartialEq)]
+pub enum BarType {
+    /// seconds per candle
+    Time(u64),
+    /// trades per candle
+    Tick(u32),
+    /// traded size per candle
+    Volume(f32),
+    /// traded price * size per candle
+    Dollar(f32),
+}
+
 #[derive(Debug)]
 pub enum ReadLocation {
     Mem,
@@ -62,25 +103,86 @@ pub enum Command {
     Info,
     Perf,
     Orderbook(Option<BookName>),
+    /// book, timestamp in ms, depth
+    OrderbookAt(BookName, u64, Option<usize>),
     Get(ReqCount, GetFormat, Option<(u64, u64)>, ReadLocation),
+    /// like `Get` but sent in chunks, disk and memory unless `IN MEM`
+    GetStream(ReqCount, GetFormat, Option<(u64, u64)>, ReadLocation),
+    Candles(BookName, BarType, (u64, u64), GetFormat),
+    /// book, range, sampling interval in ms
+    Stats(BookName, (u64, u64), u64),
     Count(ReqCount, ReadLocation),
     Clear(ReqCount),
     Flush(ReqCount),
     Insert(Option<Update>, Option<BookName>),
     Create(BookName),
-    Subscribe(BookName),
+    /// book, replay the book and updates since this ts first
+    Subscribe(BookName, Option<u64>),
+    /// book, send top of book changes as JSON instead of updates
+    SubscribeBbo(BookName),
     Load(BookName),
     Use(BookName),
     Exists(BookName),
+    /// book, confirmed
+    Drop(BookName, bool),
+    /// old name, new name
+    Rename(BookName, BookName),
+    Auth(String),
+    ConfigGet(Option<String>),
+    ConfigSet(String, String),
     Unknown,
     BadFormat,
 }
 
+impl Command {
+    /// short lowercase name used as metrics label
+    pub fn name(&self) -> &'static str {
+        use self::Command::*;
+        match self {
+            Noop => "noop",
+            Ping => "ping",
+            Help => "help",
+            Info => "info",
+            Perf => "perf",
+            Orderbook(_) | OrderbookAt(..) => "ob",
+            Get(..) => "get",
+            GetStream(..) => "get_stream",
+            Candles(..) => "candles",
+            Stats(..) => "stats",
+            Count(..) => "count",
+            Clear(_) => "clear",
+            Flush(_) => "flush",
+            Insert(..) => "insert",
+            Create(_) => "create",
+            Subscribe(..) => "subscribe",
+            SubscribeBbo(..) => "subscribe_bbo",
+            Load(_) => "load",
+            Use(_) => "use",
+            Exists(_) => "exists",
+            Drop(..) => "drop",
+            Rename(..) => "rename",
+            Auth(_) => "auth",
+            ConfigGet(_) | ConfigSet(..) => "config",
+            Unknown => "unknown",
+            BadFormat => "bad_format",
+        }
+    }
+}
+
+/// write half of a client connection, plain TCP or TLS
+pub struct ClientWriter(pub Box<dyn async_std::io::Write + Send + Unpin>);
+
+impl std::fmt::Debug for ClientWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ClientWriter")
+    }
+}
+
 #[derive(Debug)]
 pub enum Event {
     NewConnection {
         addr: SocketAddr,
-        stream: Arc<TcpStream>,
+        stream: ClientWriter,
         shutdown: Receiver<Void>,
     },
     Command {
@@ -92,6 +194,10 @@ pub enum Event {
         // obname, on disk, in mem
         tx: Sender<Vec<(BookName, u64, u64)>>,
     },
+    /// prometheus text
+    FetchMetrics {
+        tx: Sender<String>,
+    },
 }
 
 /// sometimes returns string, sometimes bytes, error string
@@ -131,13 +237,23 @@ pub fn parse_to_command(mut line: &[u8]) -> Command {
         "GET ALL" => Get(ReqCount::All, GetFormat::Dtf, None, ReadLocation::Mem),
         "FLUSH" => Flush(ReqCount::Count(1)),
         "FLUSH ALL" => Flush(ReqCount::All),
+        "CONFIG GET" => ConfigGet(None),
         _ => {
-            if line.starts_with("SUBSCRIBE ") {
-                let dbname: &str = &line[10..];
-                Subscribe(BookName::from(dbname).unwrap())
+            if line.starts_with("SUBSCRIBE ") && line.ends_with(" BBO") {
+                crate::parser::parse_subscribe_bbo(line)
+                    .map(SubscribeBbo)
+                    .unwrap_or(BadFormat)
+            } else if line.starts_with("SUBSCRIBE ") {
+                crate::parser::parse_subscribe(line)
+                    .map(|(book, from)| Subscribe(book, from))
+                    .unwrap_or(BadFormat)
             } else if line.starts_with("CREATE ") {
                 let dbname: &str = &line[7..];
                 Create(BookName::from(dbname).unwrap())
+            } else if line.starts_with("OB ") && line.contains(" AT ") {
+                crate::parser::parse_orderbook_at(line)
+                    .map(|(book, ts, depth)| OrderbookAt(book, ts, depth))
+                    .unwrap_or(BadFormat)
             } else if line.starts_with("OB ") {
                 let dbname: &str = &line[3..];
                 Orderbook(Some(BookName::from(dbname).unwrap()))
@@ -150,6 +266,40 @@ pub fn parse_to_command(mut line: &[u8]) -> Command {
             } else if line.starts_with("EXISTS ") {
                 let dbname: &str = &line[7..];
                 Exists(BookName::from(dbname).unwrap())
+            } else if let Some(args) = line.strip_prefix("DROP ") {
+                let args: Vec<&str> = args.split_whitespace().collect();
+                match args.as_slice() {
+                    [dbname] => BookName::from(dbname).map(|b| Drop(b, false)).unwrap_or(BadFormat),
+                    [dbname, "CONFIRM"] => BookName::from(dbname).map(|b| Drop(b, true)).unwrap_or(BadFormat),
+                    _ => BadFormat,
+                }
+            } else if let Some(args) = line.strip_prefix("RENAME ") {
+                let args: Vec<&str> = args.split_whitespace().collect();
+                match args.as_slice() {
+                    [old, new] => match (BookName::from(old), BookName::from(new)) {
+                        (Ok(old), Ok(new)) => Rename(old, new),
+                        _ => BadFormat,
+                    },
+                    _ => BadFormat,
+                }
+            } else if line.starts_with("CANDLES ") {
+                crate::parser::parse_candles(line)
+                    .map(|(book, bar, range, format)| Candles(book, bar, range, format))
+                    .unwrap_or(BadFormat)
+            } else if line.starts_with("STATS ") {
+                crate::parser::parse_stats(line)
+                    .map(|(book, range, sampling)| Stats(book, range, sampling))
+                    .unwrap_or(BadFormat)
+            } else if let Some(token) = line.strip_prefix("AUTH ") {
+                Auth(token.trim().to_owned())
+            } else if let Some(key) = line.strip_prefix("CONFIG GET ") {
+                ConfigGet(Some(key.trim().to_owned()))
+            } else if let Some(kv) = line.strip_prefix("CONFIG SET ") {
+                let mut kv = kv.trim().splitn(2, ' ');
+                match (kv.next(), kv.next()) {
+                    (Some(key), Some(value)) => ConfigSet(key.to_owned(), value.trim().to_owned()),
+                    _ => BadFormat,
+                }
             } else if line.starts_with("ADD ") || line.starts_with("INSERT ") {
                 let (up, dbname) = if line.contains(" INTO ") {
                     let (up, dbname) = crate::parser::parse_add_into(&line);
@@ -185,7 +335,11 @@ pub fn parse_to_command(mut line: &[u8]) -> Command {
                 };
                 let loc = if line.contains(" IN MEM") { ReadLocation::Mem } else { ReadLocation::Fs };
 
-                Get(count, format, range, loc)
+                if line.ends_with(" STREAM") {
+                    GetStream(count, format, range, loc)
+                } else {
+                    Get(count, format, range, loc)
+                }
             } else {
                 Unknown
             }
@@ -198,6 +352,8 @@ pub fn parse_to_command(mut line: &[u8]) -> Command {
 mod tests {
     use super::*;
     use crate::settings::Settings;
+    use tdb_core::postprocessing::bbo::Bbo;
+    use tdb_core::postprocessing::orderbook::Orderbook;
     use std::net;
 
     fn gen_state() -> (TectonicServer, Option<SocketAddr>) {
@@ -243,6 +399,19 @@ mod tests {
         assert_eq!(ReturnType::String("".into()), resp);
     }
 
+    #[test]
+    fn should_reject_unrepresentable_price() {
+        let (mut state, addr) = gen_state();
+        task::block_on(state.process_command(parse_to_command(b"CREATE bnc_btc_eth"), addr));
+        let resp = task::block_on(state.process_command(
+            parse_to_command(b"ADD 1513749530.585,0,t,f,4000000000,1; INTO bnc_btc_eth"),
+            addr
+        ));
+        assert_eq!(ReturnType::error("Price 4000000000 cannot be represented in bnc_btc_eth"), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"COUNT ALL IN MEM"), addr));
+        assert_eq!(ReturnType::String("0".into()), resp);
+    }
+
     #[test]
     fn should_raw_insert_ok() {
         let (mut state, addr) = gen_state();
@@ -257,4 +426,420 @@ mod tests {
         let resp = task::block_on(state.process_command(parse_to_command(&cmd), addr));
         assert_eq!(ReturnType::String("".into()), resp);
     }
+
+    /// wait for a reply computed off the broker
+    fn resolved(resp: ReturnType) -> ReturnType {
+        match resp {
+            ReturnType::Deferred(reply) => {
+                let mut reply = reply.0;
+                task::block_on(reply.next()).unwrap()
+            }
+            resp => resp,
+        }
+    }
+
+    fn gen_auth_state() -> (TectonicServer, Option<SocketAddr>) {
+        let settings = Settings {
+            auth: Some("
+                ingest  w-token  writer
+                research r-token read bnc_*
+                ops     a-token  admin
+            ".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut global = TectonicServer::new(Arc::new(settings));
+        let addr = SocketAddr::new(
+            net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)),
+            1);
+        let (client_sender, _client_receiver) = mpsc::channel(CHANNEL_SZ);
+        global.new_connection(client_sender, addr);
+        (global, Some(addr))
+    }
+
+    #[test]
+    fn should_require_auth() {
+        let (mut state, addr) = gen_auth_state();
+        let resp = task::block_on(state.process_command(Command::Ping, addr));
+        assert_eq!(ReturnType::String("PONG".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CREATE bnc_btc_eth"), addr));
+        assert_eq!(ReturnType::Error("Authentication required. Use `AUTH [token]`.".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"AUTH bad-token"), addr));
+        assert_eq!(ReturnType::Error("Invalid token.".into()), resp);
+    }
+
+    #[test]
+    fn should_check_role_and_book() {
+        let (mut state, addr) = gen_auth_state();
+        let resp = task::block_on(state.process_command(parse_to_command(b"AUTH r-token"), addr));
+        assert_eq!(ReturnType::String("Authenticated as `research`.".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CREATE bnc_btc_eth"), addr));
+        assert_eq!(ReturnType::Error("Permission denied: `research` requires writer role.".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"COUNT"), addr));
+        assert_eq!(ReturnType::Error("Permission denied: `research` cannot access `default`.".into()), resp);
+
+        let resp = task::block_on(state.process_command(parse_to_command(b"AUTH w-token"), addr));
+        assert_eq!(ReturnType::String("Authenticated as `ingest`.".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CREATE bnc_btc_eth"), addr));
+        assert_eq!(ReturnType::String("Created orderbook `bnc_btc_eth`.".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"FLUSH ALL"), addr));
+        assert_eq!(ReturnType::Error("Permission denied: `ingest` requires admin role.".into()), resp);
+
+        let resp = task::block_on(state.process_command(parse_to_command(b"AUTH r-token"), addr));
+        assert_eq!(ReturnType::String("Authenticated as `research`.".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"USE bnc_btc_eth"), addr));
+        assert_eq!(ReturnType::String("SWITCHED TO orderbook `bnc_btc_eth`.".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"COUNT"), addr));
+        assert_eq!(ReturnType::String("0".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CONFIG GET"), addr));
+        assert_eq!(ReturnType::Error("Permission denied: `research` requires admin role.".into()), resp);
+    }
+
+    #[test]
+    fn should_hide_other_books_from_info_and_count_all() {
+        let (mut state, addr) = gen_auth_state();
+        let mut run = |cmd: &[u8]| task::block_on(state.process_command(parse_to_command(cmd), addr));
+        assert_eq!(ReturnType::Error("Authentication required. Use `AUTH [token]`.".into()), run(b"INFO"));
+        run(b"AUTH w-token");
+        run(b"CREATE bnc_btc_eth");
+        run(b"CREATE gdax_btc_usd");
+        run(b"ADD 1513749530.585,0,t,t,0.04683200,0.18900000; INTO bnc_btc_eth");
+        run(b"ADD 1513749530.585,0,t,t,0.04683200,0.18900000; INTO gdax_btc_usd");
+        run(b"ADD 1513749531.585,1,t,t,0.04683200,0.18900000; INTO gdax_btc_usd");
+        assert_eq!(ReturnType::String("3".into()), run(b"COUNT ALL IN MEM"));
+
+        run(b"AUTH r-token");
+        assert_eq!(ReturnType::String("1".into()), run(b"COUNT ALL IN MEM"));
+        match run(b"INFO") {
+            ReturnType::String(info) => {
+                assert!(info.contains("\"name\": \"bnc_btc_eth\""));
+                assert!(!info.contains("gdax_btc_usd"));
+                assert!(!info.contains("\"name\": \"default\""));
+                assert!(info.contains("\"total_in_memory_count\": 1"));
+            }
+            resp => panic!("unexpected {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn should_get_and_set_config() {
+        let settings = Settings {
+            dtf_folder: "db".to_owned(),
+            flush_interval: 1000,
+            q_capacity: 300,
+            ..Default::default()
+        };
+        let mut state = TectonicServer::new(Arc::new(settings));
+        let addr = SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), 1);
+        let (client_sender, _client_receiver) = mpsc::channel(CHANNEL_SZ);
+        state.new_connection(client_sender, addr);
+        let addr = Some(addr);
+
+        let resp = task::block_on(state.process_command(parse_to_command(b"CONFIG GET storage.autoflush"), addr));
+        assert_eq!(ReturnType::String("false".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CONFIG SET storage.autoflush true"), addr));
+        assert_eq!(ReturnType::ok(), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CONFIG SET books.default.flush_interval 10"), addr));
+        assert_eq!(ReturnType::ok(), resp);
+        let book = &state.books["default"];
+        assert!(book.config.autoflush);
+        assert_eq!(book.config.flush_interval, 10);
+
+        let resp = task::block_on(state.process_command(parse_to_command(b"CONFIG SET storage.flush_interval 0"), addr));
+        assert_eq!(ReturnType::Error("storage.flush_interval must be positive when autoflush is on".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CONFIG SET storage.dtf_folder /tmp"), addr));
+        assert_eq!(ReturnType::Error("`storage.dtf_folder` cannot be changed at runtime".into()), resp);
+        let resp = task::block_on(state.process_command(parse_to_command(b"CONFIG GET"), addr));
+        match resp {
+            ReturnType::String(s) => assert!(s.contains("books.default.flush_interval = 10\n")),
+            _ => panic!("expected string"),
+        }
+    }
+
+    #[test]
+    fn should_rename_and_drop() {
+        let folder = std::env::temp_dir().join(format!("tdb_rename_drop_{}", std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        let (mut state, addr) = gen_state();
+        state.settings = Arc::new(Settings { dtf_folder: folder.clone(), ..Default::default() });
+        let mut run = |cmd: &[u8]| task::block_on(state.process_command(parse_to_command(cmd), addr));
+
+        run(b"CREATE bnc_btc_eth");
+        run(b"USE bnc_btc_eth");
+        run(b"ADD 1513749530.585,0,t,t,0.04683200,0.18900000;");
+        run(b"FLUSH");
+        let resp = run(b"RENAME bnc_btc_eth bnc_eth_btc");
+        assert_eq!(ReturnType::String("Renamed orderbook `bnc_btc_eth` to `bnc_eth_btc` in 1 file(s).".into()), resp);
+        let fname = format!("{}/bnc_eth_btc.dtf", folder);
+        assert_eq!(dtf::file_format::read_meta(&fname).unwrap().symbol, "bnc_eth_btc");
+        assert_eq!(ReturnType::String("1".into()), run(b"COUNT"));
+
+        let resp = run(b"DROP bnc_eth_btc");
+        assert_eq!(ReturnType::Error("Dropping `bnc_eth_btc` deletes 1 file(s). Use `DROP bnc_eth_btc CONFIRM` to proceed.".into()), resp);
+        let resp = run(b"DROP bnc_eth_btc CONFIRM");
+        assert_eq!(ReturnType::String("Dropped orderbook `bnc_eth_btc` and 1 file(s).".into()), resp);
+        assert!(!Path::new(&fname).exists());
+        assert_eq!(ReturnType::Error("No db named `bnc_eth_btc`".into()), run(b"EXISTS bnc_eth_btc"));
+        assert_eq!(ReturnType::String("0".into()), run(b"COUNT"));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn should_aggregate_candles() {
+        let (mut state, addr) = gen_state();
+        let mut run = |cmd: &[u8]| resolved(task::block_on(state.process_command(parse_to_command(cmd), addr)));
+        run(b"ADD 1513749530.000,0,t,t,1.0,1.0;");
+        run(b"ADD 1513749531.000,1,t,f,3.0,2.0;");
+        run(b"ADD 1513749532.000,2,f,f,9.0,2.0;");
+        run(b"ADD 1513749533.000,3,t,t,2.0,1.0;");
+        // only trades count, the book update in between does not close a candle
+        let resp = run(b"CANDLES default TICKS 3 FROM 1513749500 TO 1513749600");
+        assert_eq!(ReturnType::String("1513749530000,1513749533000,1,3,1,2,4\n".into()), resp);
+        let resp = run(b"CANDLES default TICKS 2 FROM 1513749500 TO 1513749600");
+        assert_eq!(ReturnType::String("1513749530000,1513749531000,1,3,1,3,3\n1513749533000,1513749533000,2,2,2,2,1\n".into()), resp);
+        let resp = run(b"CANDLES default VOLUME 10 FROM 1513749500 TO 1513749600 AS JSON");
+        assert_eq!(ReturnType::String(
+            "[{\"start\":1513749530000,\"end\":1513749533000,\"open\":1,\"high\":3,\"low\":1,\"close\":2,\"volume\":4}]\n".into()), resp);
+        let resp = run(b"CANDLES default TICKS 3 FROM 1513749532 TO 1513749600");
+        assert_eq!(ReturnType::String("1513749533000,1513749533000,2,2,2,2,1\n".into()), resp);
+        // time candles start at the minute in ms, the last one is kept
+        run(b"ADD 1513749545.000,4,t,f,5.0,1.0;");
+        let resp = run(b"CANDLES default 1m FROM 1513749500 TO 1513749600");
+        assert_eq!(ReturnType::String("1513749480000,1513749533000,1,3,1,2,4\n1513749540000,1513749545000,5,5,5,5,1\n".into()), resp);
+        assert_eq!(ReturnType::Error("No db named `nope`".into()), run(b"CANDLES nope 1m FROM 1 TO 2"));
+    }
+
+    #[test]
+    fn should_aggregate_flushed_candles() {
+        let folder = std::env::temp_dir().join(format!("tdb_candles_{}", std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        let (mut state, addr) = gen_state();
+        state.settings = Arc::new(Settings { dtf_folder: folder.clone(), ..Default::default() });
+        let mut run = |cmd: &[u8]| resolved(task::block_on(state.process_command(parse_to_command(cmd), addr)));
+        run(b"CREATE bnc_btc_eth");
+        run(b"USE bnc_btc_eth");
+        run(b"ADD 1513749530.000,0,t,t,1.0,1.0;");
+        run(b"ADD 1513749531.000,1,t,f,3.0,2.0;");
+        run(b"ADD 1513749532.000,2,t,f,9.0,2.0;");
+        run(b"ADD 1513749533.000,3,t,t,2.0,1.0;");
+        // a single batch on disk and nothing in memory
+        run(b"FLUSH");
+        let resp = run(b"CANDLES bnc_btc_eth TICKS 2 FROM 1513749500 TO 1513749600");
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(ReturnType::String("1513749530000,1513749531000,1,3,1,3,3\n1513749532000,1513749533000,9,9,2,2,3\n".into()), resp);
+    }
+
+    #[test]
+    fn should_compute_stats() {
+        let (mut state, addr) = gen_state();
+        let mut run = |cmd: &[u8]| resolved(task::block_on(state.process_command(parse_to_command(cmd), addr)));
+        run(b"ADD 1513749530.000,0,f,t,1.0,4.0;");
+        run(b"ADD 1513749530.000,1,f,f,3.0,2.0;");
+        run(b"ADD 1513749531.000,2,t,f,3.0,1.0;");
+        run(b"ADD 1513749532.000,3,t,t,1.0,3.0;");
+        let stats = match run(b"STATS default FROM 1513749531 TO 1513749600 EVERY 1") {
+            ReturnType::String(s) => serde_json::from_str::<serde_json::Value>(&s).unwrap(),
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(stats["trades"], 2);
+        assert_eq!(stats["vwap"], 1.5);
+        // the book before the range is replayed
+        assert_eq!(stats["twap"], 2.);
+        assert_eq!(stats["trade_sizes"]["max"], 3.);
+        assert_eq!(ReturnType::Error("No db named `nope`".into()), run(b"STATS nope FROM 1 TO 2"));
+        assert_eq!(
+            ReturnType::Error("STATS samples the book over 1000000 times, use a shorter range or a larger EVERY".into()),
+            run(b"STATS default FROM 0 TO 1513749600 EVERY 1")
+        );
+    }
+
+    #[test]
+    fn should_compute_flushed_stats() {
+        let folder = std::env::temp_dir().join(format!("tdb_stats_{}", std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        let (mut state, addr) = gen_state();
+        state.settings = Arc::new(Settings { dtf_folder: folder.clone(), ..Default::default() });
+        let mut run = |cmd: &[u8]| resolved(task::block_on(state.process_command(parse_to_command(cmd), addr)));
+        run(b"CREATE bnc_btc_eth");
+        run(b"USE bnc_btc_eth");
+        run(b"ADD 1513749530.000,0,t,f,3.0,1.0;");
+        run(b"ADD 1513749531.000,1,t,t,1.0,3.0;");
+        run(b"FLUSH");
+        let resp = run(b"STATS bnc_btc_eth FROM 1513749500 TO 1513749600");
+        std::fs::remove_dir_all(&folder).unwrap();
+        let stats = match resp {
+            ReturnType::String(s) => serde_json::from_str::<serde_json::Value>(&s).unwrap(),
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(stats["trades"], 2);
+        assert_eq!(stats["vwap"], 1.5);
+    }
+
+    #[test]
+    fn should_stream_bbo_to_subscribers() {
+        let (mut state, _) = gen_state();
+        let addr = SocketAddr::new(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)), 2);
+        let (client_sender, mut client_receiver) = mpsc::channel(CHANNEL_SZ);
+        state.new_connection(client_sender, addr);
+        let addr = Some(addr);
+        let mut run = |cmd: &[u8]| task::block_on(state.process_command(parse_to_command(cmd), addr));
+
+        run(b"ADD 1513749530.000,0,f,t,1.0,1.0;");
+        assert_eq!(ReturnType::String("Subscribed to default BBO".into()), run(b"SUBSCRIBE default BBO"));
+        run(b"ADD 1513749531.000,1,f,f,3.0,1.0;");
+        // behind the best ask
+        run(b"ADD 1513749532.000,2,f,f,4.0,1.0;");
+        run(b"ADD 1513749533.000,3,f,t,1.0,2.0;");
+
+        let mut bbos = vec![];
+        while let Ok(Some(msg)) = client_receiver.try_next() {
+            match msg {
+                ReturnType::String(s) => bbos.push(serde_json::from_str::<Bbo>(&s).unwrap()),
+                r => panic!("{:?}", r),
+            }
+        }
+        assert_eq!(bbos, vec![
+            Bbo { ts: 1513749531000, bid: Some((1., 1.)), ask: Some((3., 1.)) },
+            Bbo { ts: 1513749533000, bid: Some((1., 2.)), ask: Some((3., 1.)) },
+        ]);
+        assert!(matches!(parse_to_command(b"SUBSCRIBE default FROM 1 BBO"), Command::BadFormat));
+    }
+
+    #[test]
+    fn should_rebuild_orderbook_at() {
+        let folder = std::env::temp_dir().join(format!("tdb_ob_at_{}", std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        let (mut state, addr) = gen_state();
+        state.settings = Arc::new(Settings { dtf_folder: folder.clone(), ..Default::default() });
+        state.create(&BookName::from("bnc_btc_eth").unwrap());
+        let mut run = |cmd: &[u8]| task::block_on(state.process_command(parse_to_command(cmd), addr));
+
+        run(b"USE bnc_btc_eth");
+        run(b"ADD 1513749530.000,0,f,t,1.0,1.0;");
+        run(b"ADD 1513749530.000,1,f,t,2.0,1.0;");
+        run(b"ADD 1513749531.000,2,f,f,3.0,1.0;");
+        run(b"FLUSH");
+        run(b"ADD 1513749532.000,3,f,t,2.0,0.0;");
+        run(b"ADD 1513749533.000,4,f,f,4.0,2.0;");
+
+        let ob = |resp| match resp {
+            ReturnType::String(s) => serde_json::from_str::<Orderbook>(&s).unwrap(),
+            r => panic!("{:?}", r),
+        };
+        let at = ob(run(b"OB bnc_btc_eth AT 1513749531"));
+        assert_eq!(at.top(), Some(((2., 1.), (3., 1.))));
+        let at = ob(run(b"OB bnc_btc_eth AT 1513749533000"));
+        assert_eq!(at.top(), Some(((1., 1.), (3., 1.))));
+        assert_eq!(at.asks.len(), 2);
+        let at = ob(run(b"OB bnc_btc_eth AT 1513749533 DEPTH 1"));
+        assert_eq!(at.asks.len(), 1);
+        assert_eq!(ReturnType::Error("No db named `nope`".into()), run(b"OB nope AT 1"));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn should_checkpoint_and_restore_orderbook() {
+        use tdb_core::dtf::snapshot;
+        let folder = std::env::temp_dir().join(format!("tdb_snapshot_{}", std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        let settings = Arc::new(Settings { dtf_folder: folder.clone(), snapshot_interval: 2, ..Default::default() });
+        let (mut state, addr) = gen_state();
+        state.settings = Arc::clone(&settings);
+        state.create(&BookName::from("bnc_btc_eth").unwrap());
+        let mut run = |cmd: &[u8]| task::block_on(state.process_command(parse_to_command(cmd), addr));
+
+        run(b"USE bnc_btc_eth");
+        run(b"ADD 1513749530.000,0,f,t,1.0,1.0;");
+        run(b"ADD 1513749530.000,1,f,t,2.0,1.0;");
+        // due after 2 updates, taken before the first update with a later ts
+        run(b"ADD 1513749531.000,2,f,f,3.0,1.0;");
+        run(b"FLUSH");
+        run(b"ADD 1513749532.000,3,f,t,2.0,0.0;");
+        run(b"ADD 1513749533.000,4,f,f,4.0,2.0;");
+        run(b"FLUSH");
+
+        let fname = snapshot::snapshot_fname(&folder, "bnc_btc_eth");
+        assert_eq!(snapshot::nearest(&fname, 1513749531000).unwrap().unwrap().ts, 1513749530000);
+        assert_eq!(snapshot::latest(&fname).unwrap().unwrap().ts, 1513749532000);
+        let at = state.orderbook_at("bnc_btc_eth", 1513749531000).unwrap();
+        assert_eq!(at.top(), Some(((2., 1.), (3., 1.))));
+
+        let restored = Book::new("bnc_btc_eth", settings);
+        assert_eq!(restored.orderbook, state.books["bnc_btc_eth"].orderbook);
+
+        let backlog = state.subscription_backlog("bnc_btc_eth", 1513749531000).unwrap();
+        let ts: Vec<u64> = backlog.iter().map(|up| up.ts).collect();
+        assert_eq!(ts, vec![1513749531000, 1513749531000, 1513749531000, 1513749532000, 1513749533000]);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn should_stream_get_from_disk_and_memory() {
+        let folder = std::env::temp_dir().join(format!("tdb_stream_{}", std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        let (mut state, addr) = gen_state();
+        state.settings = Arc::new(Settings { dtf_folder: folder.clone(), ..Default::default() });
+        state.create(&BookName::from("bnc_btc_eth").unwrap());
+        let mut run = |cmd: &[u8]| task::block_on(state.process_command(parse_to_command(cmd), addr));
+
+        run(b"USE bnc_btc_eth");
+        run(b"ADD 1513749530.000,0,f,t,1.0,1.0;");
+        run(b"ADD 1513749531.000,1,f,t,2.0,1.0;");
+        run(b"FLUSH");
+        run(b"ADD 1513749532.000,2,f,f,3.0,1.0;");
+
+        let lines = |resp| match resp {
+            ReturnType::Stream(chunks) => task::block_on(chunks.0.collect::<Vec<_>>())
+                .into_iter()
+                .map(|chunk| match chunk {
+                    ReturnType::String(s) => s.lines().count(),
+                    r => panic!("{:?}", r),
+                })
+                .sum::<usize>(),
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(lines(run(b"GET ALL AS CSV STREAM")), 3);
+        assert_eq!(lines(run(b"GET ALL IN MEM AS CSV STREAM")), 1);
+        assert_eq!(lines(run(b"GET ALL FROM 1513749531 TO 1513749532 AS CSV STREAM")), 2);
+        assert_eq!(lines(run(b"GET 2 AS CSV STREAM")), 2);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_books() {
+        let folder = std::env::temp_dir().join(format!("tdb_evict_{}", std::process::id()));
+        let folder = folder.to_str().unwrap().to_owned();
+        std::fs::create_dir_all(&folder).unwrap();
+        // 20k updates take less than half of the 1 MB budget
+        for (name, n) in &[("mem_a", 20_000), ("mem_b", 20_000), ("mem_c", 20_000), ("mem_big", 50_000)] {
+            let ups: Vec<Update> = (0..*n)
+                .map(|i| Update { ts: 1_000 + i, seq: i as u32, is_trade: false, is_bid: true, price: 1., size: 1. })
+                .collect();
+            dtf::file_format::encode(&format!("{}/{}.dtf", folder, name), name, &ups).unwrap();
+        }
+        let (mut state, addr) = gen_state();
+        state.settings = Arc::new(Settings { dtf_folder: folder.clone(), memory_limit_mb: 1, ..Default::default() });
+        for name in &["mem_a", "mem_b", "mem_c", "mem_big"] {
+            state.create(&BookName::from(name).unwrap());
+        }
+        assert_eq!(state.memory_usage(), 0);
+        let mut run = |cmd: &[u8]| task::block_on(state.process_command(parse_to_command(cmd), addr));
+
+        assert_eq!(ReturnType::String("Loaded orderbook `mem_a`.".into()), run(b"LOAD mem_a"));
+        assert_eq!(ReturnType::String("Loaded orderbook `mem_b`.".into()), run(b"LOAD mem_b"));
+        run(b"USE mem_a");
+        run(b"COUNT IN MEM");
+        assert_eq!(ReturnType::String("Loaded orderbook `mem_c`.".into()), run(b"LOAD mem_c"));
+        assert_eq!(
+            ReturnType::Error("Not enough memory to load `mem_big`: needs 1.1 MB of 1 MB.".into()),
+            run(b"LOAD mem_big")
+        );
+
+        assert_eq!(state.books["mem_a"].loaded_count(), 20_000);
+        assert_eq!(state.books["mem_b"].loaded_count(), 0);
+        assert_eq!(state.books["mem_c"].loaded_count(), 20_000);
+        assert_eq!(state.metrics.evictions, 1);
+        assert!(state.memory_usage() <= 1 << 20);
+        assert!(state.info(None).contains("\"evictions\": 1"));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
 }
@@ -2,9 +2,7 @@
 extern crate tdb_core;
 extern crate clap;
 extern crate chrono;
-#[cfg(feature = "gcs")]
 extern crate serde;
-#[cfg(feature = "gcs")]
 #[macro_use]
 extern crate serde_derive;
 extern crate openssl_probe;
@@ -29,7 +27,12 @@ use alloc_counter::AllocCounterSystem;
 #[cfg_attr(feature = "count_alloc", global_allocator)]
 static A: AllocCounterSystem = AllocCounterSystem;
 
+pub mod auth;
+pub mod chunked;
+pub mod config;
+pub mod metrics;
 pub mod plugins;
+pub mod tls;
 pub mod utils;
 pub mod server;
 pub mod state;
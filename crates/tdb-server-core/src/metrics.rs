@@ -0,0 +1,198 @@
+//! Counters and latency histograms exported in the Prometheus text format.
+//!
+//! Everything except the broker queue depth is owned by the broker loop, so
+//! plain integers suffice. See `plugins::prometheus` for the HTTP endpoint.
+use crate::prelude::*;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// events sent to the broker but not handled yet
+static BROKER_QUEUE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// call right before sending an event to the broker
+pub fn event_queued() {
+    BROKER_QUEUE_DEPTH.fetch_add(1, Ordering::Relaxed);
+}
+
+/// call when the broker picks up an event
+pub fn event_handled() {
+    // never underflow if an event was sent without `event_queued`
+    let _ = BROKER_QUEUE_DEPTH.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |d| d.checked_sub(1));
+}
+
+/// upper bounds in seconds, +Inf is implied
+const BUCKETS: [f64; 11] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1., 5., 10.];
+
+/// Latency histogram with fixed buckets
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    /// observations per bucket, the last one is +Inf
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, dur: Duration) {
+        let secs = dur.as_secs_f64();
+        let idx = BUCKETS.iter().position(|&b| secs <= b).unwrap_or(BUCKETS.len());
+        self.counts[idx] += 1;
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn write(&self, buf: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            let le = BUCKETS.get(i).map(|b| b.to_string()).unwrap_or_else(|| "+Inf".to_owned());
+            let _ = writeln!(buf, "{}_bucket{{{}le=\"{}\"}} {}", name, labels, le, cumulative);
+        }
+        let labels = labels.trim_end_matches(',');
+        let _ = writeln!(buf, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(buf, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// Per book counters, kept on `Book`
+#[derive(Clone, Debug, Default)]
+pub struct BookMetrics {
+    /// updates inserted since startup
+    pub inserts: u64,
+    /// successful flushes to disk
+    pub flushes: u64,
+    /// failed flushes
+    pub flush_errors: u64,
+    /// time spent writing to disk
+    pub flush_latency: Histogram,
+}
+
+/// Server wide counters, kept on `TectonicServer`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// latency of every command by `Command::name`
+    pub commands: HashMap<&'static str, Histogram>,
+    /// connections accepted since startup
+    pub connections_total: u64,
+    /// loaded books evicted to stay within the memory limit
+    pub evictions: u64,
+}
+
+impl Metrics {
+    pub fn observe_command(&mut self, name: &'static str, dur: Duration) {
+        self.commands.entry(name).or_default().observe(dur);
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(buf: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(buf, "# HELP {} {}", name, help);
+    let _ = writeln!(buf, "# TYPE {} {}", name, kind);
+}
+
+/// Render the current state in the Prometheus text exposition format
+pub fn render(state: &TectonicServer) -> String {
+    let mut buf = String::new();
+    let mut books: Vec<_> = state.books.iter().collect();
+    books.sort_by(|a, b| a.0.cmp(b.0));
+
+    header(&mut buf, "tdb_book_updates", "gauge", "Number of updates per book, same as FetchSizes.");
+    for (name, book) in &books {
+        let name = escape(name);
+        let _ = writeln!(buf, "tdb_book_updates{{book=\"{}\",location=\"disk\"}} {}", name, book.nominal_count);
+        let _ = writeln!(buf, "tdb_book_updates{{book=\"{}\",location=\"memory\"}} {}", name, book.vec.len());
+    }
+
+    header(&mut buf, "tdb_inserts_total", "counter", "Updates inserted since startup.");
+    for (name, book) in &books {
+        let _ = writeln!(buf, "tdb_inserts_total{{book=\"{}\"}} {}", escape(name), book.metrics.inserts);
+    }
+
+    header(&mut buf, "tdb_flushes_total", "counter", "Successful flushes to disk.");
+    for (name, book) in &books {
+        let _ = writeln!(buf, "tdb_flushes_total{{book=\"{}\"}} {}", escape(name), book.metrics.flushes);
+    }
+
+    header(&mut buf, "tdb_flush_errors_total", "counter", "Failed flushes to disk.");
+    for (name, book) in &books {
+        let _ = writeln!(buf, "tdb_flush_errors_total{{book=\"{}\"}} {}", escape(name), book.metrics.flush_errors);
+    }
+
+    header(&mut buf, "tdb_flush_duration_seconds", "histogram", "Time spent flushing a book to disk.");
+    for (name, book) in &books {
+        book.metrics.flush_latency.write(&mut buf, "tdb_flush_duration_seconds", &format!("book=\"{}\",", escape(name)));
+    }
+
+    header(&mut buf, "tdb_memory_bytes", "gauge", "Approximate memory held by updates and orderbooks.");
+    for (name, book) in &books {
+        let _ = writeln!(buf, "tdb_memory_bytes{{book=\"{}\"}} {}", escape(name), book.memory_usage());
+    }
+
+    header(&mut buf, "tdb_evictions_total", "counter", "Loaded books evicted to stay within the memory limit.");
+    let _ = writeln!(buf, "tdb_evictions_total {}", state.metrics.evictions);
+
+    header(&mut buf, "tdb_connections", "gauge", "Currently connected clients.");
+    let _ = writeln!(buf, "tdb_connections {}", state.connections.len());
+
+    header(&mut buf, "tdb_connections_total", "counter", "Connections accepted since startup.");
+    let _ = writeln!(buf, "tdb_connections_total {}", state.metrics.connections_total);
+
+    header(&mut buf, "tdb_subscribers", "gauge", "Current subscribers per book.");
+    let mut subs: Vec<_> = state.subscriptions.iter().collect();
+    subs.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, addrs) in subs {
+        let _ = writeln!(buf, "tdb_subscribers{{book=\"{}\"}} {}", escape(name), addrs.len());
+    }
+
+    header(&mut buf, "tdb_broker_queue_depth", "gauge", "Events waiting for the broker.");
+    let _ = writeln!(buf, "tdb_broker_queue_depth {}", BROKER_QUEUE_DEPTH.load(Ordering::Relaxed));
+
+    header(&mut buf, "tdb_command_duration_seconds", "histogram", "Time spent processing a command.");
+    let mut commands: Vec<_> = state.metrics.commands.iter().collect();
+    commands.sort_by_key(|(name, _)| *name);
+    for (name, hist) in commands {
+        hist.write(&mut buf, "tdb_command_duration_seconds", &format!("command=\"{}\",", name));
+    }
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_fill_cumulative_buckets() {
+        let mut hist = Histogram::default();
+        hist.observe(Duration::from_micros(50));
+        hist.observe(Duration::from_millis(3));
+        hist.observe(Duration::from_secs(20));
+        let mut buf = String::new();
+        hist.write(&mut buf, "x", "a=\"b\",");
+        assert!(buf.contains("x_bucket{a=\"b\",le=\"0.0001\"} 1\n"));
+        assert!(buf.contains("x_bucket{a=\"b\",le=\"0.005\"} 2\n"));
+        assert!(buf.contains("x_bucket{a=\"b\",le=\"10\"} 2\n"));
+        assert!(buf.contains("x_bucket{a=\"b\",le=\"+Inf\"} 3\n"));
+        assert!(buf.contains("x_count{a=\"b\"} 3\n"));
+    }
+
+    #[test]
+    fn should_render_books_and_commands() {
+        let mut state = TectonicServer::new(Arc::new(Settings::default()));
+        state.books.get_mut("default").unwrap().metrics.inserts = 3;
+        state.metrics.observe_command("get", Duration::from_millis(1));
+        let text = render(&state);
+        assert!(text.contains("tdb_inserts_total{book=\"default\"} 3\n"));
+        assert!(text.contains("tdb_book_updates{book=\"default\",location=\"memory\"} 0\n"));
+        assert!(text.contains("tdb_command_duration_seconds_count{command=\"get\"} 1\n"));
+        assert!(text.contains("# TYPE tdb_flush_duration_seconds histogram\n"));
+    }
+}
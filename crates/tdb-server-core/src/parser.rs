@@ -122,9 +122,166 @@ pub fn parse_get_range(string: &str) -> Option<(u64, u64)> {
     }
 }
 
+/// Parses a bar spec like `1m`, `30s`, `4h`, `1d`, `TICKS 100`, `VOLUME 5.5` or
+/// `DOLLARS 10000`. Returns the bar type and the number of words consumed.
+pub fn parse_bar_type(words: &[&str]) -> Option<(BarType, usize)> {
+    let first = *words.first()?;
+    let arg = words.get(1);
+    match first {
+        "TICKS" => Some((BarType::Tick(arg?.parse().ok().filter(|&n| n > 0)?), 2)),
+        "VOLUME" => Some((BarType::Volume(arg?.parse().ok().filter(|&v: &f32| v > 0.)?), 2)),
+        "DOLLARS" => Some((BarType::Dollar(arg?.parse().ok().filter(|&d: &f32| d > 0.)?), 2)),
+        _ => {
+            let (n, unit) = first.split_at(first.char_indices().last()?.0);
+            let n = n.parse::<u64>().ok().filter(|&n| n > 0)?;
+            let secs = match unit {
+                "s" => 1,
+                "m" => 60,
+                "h" => 3600,
+                "d" => 86400,
+                _ => return None,
+            };
+            Some((BarType::Time(n * secs), 1))
+        }
+    }
+}
+
+/// Parses `CANDLES [db] [bar spec] FROM [epoch] TO [epoch] [AS CSV|JSON]`,
+/// epochs are in seconds like in `GET`.
+pub fn parse_candles(string: &str) -> Option<(BookName, BarType, (u64, u64), GetFormat)> {
+    let words: Vec<&str> = string.split_whitespace().collect();
+    if words.first() != Some(&"CANDLES") {
+        return None;
+    }
+    let book_name = BookName::from(words.get(1)?).ok()?;
+    let (bar_type, n) = parse_bar_type(&words[2..])?;
+    let rest = &words[2 + n..];
+    let (min_ts, max_ts, rest) = match rest {
+        ["FROM", from, "TO", to, rest @ ..] => (from.parse::<u64>().ok()? * 1000, to.parse::<u64>().ok()? * 1000, rest),
+        _ => return None,
+    };
+    let format = match rest {
+        [] | ["AS", "CSV"] => GetFormat::Csv,
+        ["AS", "JSON"] => GetFormat::Json,
+        _ => return None,
+    };
+    Some((book_name, bar_type, (min_ts, max_ts), format))
+}
+
+/// Parses `STATS [db] FROM [epoch] TO [epoch] [EVERY secs]`, sampling defaults to a minute.
+pub fn parse_stats(string: &str) -> Option<(BookName, (u64, u64), u64)> {
+    let words: Vec<&str> = string.split_whitespace().collect();
+    match words.as_slice() {
+        ["STATS", book_name, "FROM", from, "TO", to, rest @ ..] => {
+            let book_name = BookName::from(book_name).ok()?;
+            let range = (from.parse::<u64>().ok()? * 1000, to.parse::<u64>().ok()? * 1000);
+            let secs = match rest {
+                [] => 60,
+                ["EVERY", secs] => secs.parse::<u64>().ok().filter(|&s| s > 0)?,
+                _ => return None,
+            };
+            Some((book_name, range, secs * 1000))
+        }
+        _ => None,
+    }
+}
+
+/// Parses `OB [db] AT [epoch] [DEPTH n]`, epoch may be in seconds or milliseconds.
+pub fn parse_orderbook_at(string: &str) -> Option<(BookName, u64, Option<usize>)> {
+    let words: Vec<&str> = string.split_whitespace().collect();
+    match words.as_slice() {
+        ["OB", book_name, "AT", ts, rest @ ..] => {
+            let book_name = BookName::from(book_name).ok()?;
+            let ts = utils::fill_digits(ts.parse().ok()?);
+            let depth = match rest {
+                [] => None,
+                ["DEPTH", n] => Some(n.parse().ok()?),
+                _ => return None,
+            };
+            Some((book_name, ts, depth))
+        }
+        _ => None,
+    }
+}
+
+/// Parses `SUBSCRIBE [db] [FROM epoch]`, epoch may be in seconds or milliseconds.
+pub fn parse_subscribe(string: &str) -> Option<(BookName, Option<u64>)> {
+    let words: Vec<&str> = string.split_whitespace().collect();
+    match words.as_slice() {
+        ["SUBSCRIBE", book_name] => Some((BookName::from(book_name).ok()?, None)),
+        ["SUBSCRIBE", book_name, "FROM", ts] =>
+            Some((BookName::from(book_name).ok()?, Some(utils::fill_digits(ts.parse().ok()?)))),
+        _ => None,
+    }
+}
+
+/// Parses `SUBSCRIBE [db] BBO`
+pub fn parse_subscribe_bbo(string: &str) -> Option<BookName> {
+    let words: Vec<&str> = string.split_whitespace().collect();
+    match words.as_slice() {
+        ["SUBSCRIBE", book_name, "BBO"] => BookName::from(book_name).ok(),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[test]
+    fn should_parse_candles() {
+        let (book, bar, range, format) = parse_candles("CANDLES bnc_btc_eth 5m FROM 100 TO 200 AS JSON").unwrap();
+        assert_eq!(book.as_str(), "bnc_btc_eth");
+        assert_eq!(bar, BarType::Time(300));
+        assert_eq!(range, (100_000, 200_000));
+        assert!(matches!(format, GetFormat::Json));
+        let (_, bar, _, format) = parse_candles("CANDLES default TICKS 50 FROM 1 TO 2").unwrap();
+        assert_eq!(bar, BarType::Tick(50));
+        assert!(matches!(format, GetFormat::Csv));
+        assert_eq!(parse_candles("CANDLES default DOLLARS 1e4 FROM 1 TO 2").unwrap().1, BarType::Dollar(10000.));
+        assert!(parse_candles("CANDLES default 5x FROM 1 TO 2").is_none());
+        assert!(parse_candles("CANDLES default TICKS 0 FROM 1 TO 2").is_none());
+        assert!(parse_candles("CANDLES default 1m").is_none());
+        assert!(parse_candles("CANDLES default 1m FROM 1 TO 2 AS DTF").is_none());
+    }
+
+    #[test]
+    fn should_parse_stats() {
+        assert_eq!(
+            parse_stats("STATS bnc_btc_eth FROM 100 TO 200"),
+            Some((BookName::from("bnc_btc_eth").unwrap(), (100_000, 200_000), 60_000))
+        );
+        assert_eq!(parse_stats("STATS default FROM 1 TO 2 EVERY 5").unwrap().2, 5_000);
+        assert!(parse_stats("STATS default FROM 1 TO 2 EVERY 0").is_none());
+        assert!(parse_stats("STATS default").is_none());
+    }
+
+    #[test]
+    fn should_parse_orderbook_at() {
+        assert_eq!(
+            parse_orderbook_at("OB bnc_btc_eth AT 1513749530"),
+            Some((BookName::from("bnc_btc_eth").unwrap(), 1513749530000, None))
+        );
+        assert_eq!(
+            parse_orderbook_at("OB default AT 1513749530585 DEPTH 10"),
+            Some((BookName::from("default").unwrap(), 1513749530585, Some(10)))
+        );
+        assert_eq!(parse_orderbook_at("OB default AT now"), None);
+        assert_eq!(parse_orderbook_at("OB default AT 1 DEPTH"), None);
+    }
+
+    #[test]
+    fn should_parse_subscribe() {
+        assert_eq!(parse_subscribe("SUBSCRIBE bnc_btc_eth"), Some((BookName::from("bnc_btc_eth").unwrap(), None)));
+        assert_eq!(
+            parse_subscribe("SUBSCRIBE bnc_btc_eth FROM 1513749530"),
+            Some((BookName::from("bnc_btc_eth").unwrap(), Some(1513749530000)))
+        );
+        assert_eq!(parse_subscribe("SUBSCRIBE bnc_btc_eth FROM"), None);
+        assert_eq!(parse_subscribe("SUBSCRIBE bnc_btc_eth BBO"), None);
+        assert_eq!(parse_subscribe_bbo("SUBSCRIBE bnc_btc_eth BBO"), Some(BookName::from("bnc_btc_eth").unwrap()));
+        assert_eq!(parse_subscribe_bbo("SUBSCRIBE bnc_btc_eth"), None);
+    }
+
     #[test]
     fn should_parse_string_not_okay() {
         let string = "1505177459.658, 139010,,, f, t, 0.0703629, 7.65064249;";
@@ -1,6 +1,6 @@
 use std::error::Error;
 
-use crate::settings::{key_or_default_parse, key_or_default, key_or_none};
+use crate::settings::{Settings, key_or_default_parse, key_or_default, key_or_none};
 
 #[derive(Debug)]
 pub struct GStorageConfig {
@@ -23,17 +23,18 @@ pub struct GStorageConfig {
 }
 
 impl GStorageConfig {
-    /// Creates a new `GStorageConfig` from environment variables, filling in the remaining values with
-    /// defaults if not available.
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    /// Creates a new `GStorageConfig` from environment variables, falling back to the
+    /// `[plugins.gstorage]` section of the config file and then to defaults.
+    pub fn new(settings: &Settings) -> Result<Self, Box<dyn Error>> {
+        let file = settings.gstorage.clone().unwrap_or_default();
         Ok(GStorageConfig {
-            oauth_token: key_or_none("GCLOUD_OAUTH_TOKEN"),
-            bucket_name: key_or_default("GCLOUD_BUCKET_NAME", "tick_data"),
-            folder: key_or_default("GCLOUD_FOLDER", ""),
-            remove: key_or_default_parse("GCLOUD_REMOVE_ON_UPLOAD", true)?,
-            dcb_url: key_or_none("DCB_URL"),
-            upload_interval_secs: key_or_default_parse("GCLOUD_UPLOAD_INTERVAL_SECS", 30)?,
-            min_file_size: key_or_default_parse("GCLOUD_MIN_FILE_SIZE_BYTES", 1024 * 1024)?,
+            oauth_token: key_or_none("GCLOUD_OAUTH_TOKEN").or(file.oauth_token),
+            bucket_name: key_or_default("GCLOUD_BUCKET_NAME", file.bucket_name.as_deref().unwrap_or("tick_data")),
+            folder: key_or_default("GCLOUD_FOLDER", file.folder.as_deref().unwrap_or("")),
+            remove: key_or_default_parse("GCLOUD_REMOVE_ON_UPLOAD", file.remove.unwrap_or(true))?,
+            dcb_url: key_or_none("DCB_URL").or(file.dcb_url),
+            upload_interval_secs: key_or_default_parse("GCLOUD_UPLOAD_INTERVAL_SECS", file.upload_interval_secs.unwrap_or(30))?,
+            min_file_size: key_or_default_parse("GCLOUD_MIN_FILE_SIZE_BYTES", file.min_file_size.unwrap_or(1024 * 1024))?,
         })
     }
 }
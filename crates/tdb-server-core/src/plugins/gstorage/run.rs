@@ -56,8 +56,7 @@ fn upload_file(path_buf: PathBuf, conf: &GStorageConfig) {
     }
 }
 
-fn upload_all_files(dir_path: &Path) {
-    let conf = GStorageConfig::new().unwrap();
+fn upload_all_files(dir_path: &Path, conf: &GStorageConfig) {
 
     // Upload all files in the directory
     for path_res in fs::read_dir(dir_path).unwrap() {
@@ -67,7 +66,7 @@ fn upload_all_files(dir_path: &Path) {
                 // the DCB
                 let file_path = entry.path();
                 info!("Found file to upload: {:?}", file_path);
-                upload_file(file_path, &conf);
+                upload_file(file_path, conf);
             },
             Err(err) => error!("Error while reading dir entry: {:?}", err),
         }
@@ -81,7 +80,7 @@ lazy_static! {
 
 pub fn run(_broker: Sender<Event>, settings: Arc<Settings>) {
     thread::spawn(move || {
-        let conf = GStorageConfig::new().unwrap();
+        let conf = GStorageConfig::new(&settings).unwrap();
         let min_file_size_bytes = conf.min_file_size;
         info!("Initializing GStorage plugin with config: {:?}", conf);
         let tmp_dir_path = TMP_DIR.path();
@@ -99,6 +98,10 @@ pub fn run(_broker: Sender<Event>, settings: Arc<Settings>) {
                             .unwrap()
                             .to_str()
                             .unwrap();
+                        // orderbook checkpoints are only useful to the server
+                        if dtf_file_name.ends_with(".snap") {
+                            continue;
+                        }
                         let metadata = match entry.metadata() {
                             Ok(metadata) => metadata,
                             Err(err) => {
@@ -125,12 +128,13 @@ pub fn run(_broker: Sender<Event>, settings: Arc<Settings>) {
             }
 
             // Upload all files in the temporary directory
-            upload_all_files(tmp_dir_path);
+            upload_all_files(tmp_dir_path, &conf);
         }
     });
 }
 
 /// Called when the database is being shut down.  Upload all files, regardless of size.
 pub fn run_exit_hook(settings: Arc<Settings>) {
-    upload_all_files(&Path::new(&settings.dtf_folder))
+    let conf = GStorageConfig::new(&settings).unwrap();
+    upload_all_files(&Path::new(&settings.dtf_folder), &conf)
 }
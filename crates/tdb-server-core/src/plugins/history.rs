@@ -10,6 +10,7 @@ pub async fn run(broker: Sender<Event>, settings: Arc<Settings>) {
 pub async fn timer_loop(mut broker: Sender<Event>, settings: Arc<Settings>) {
     let dur = time::Duration::from_secs(settings.granularity);
     loop {
+        crate::metrics::event_queued();
         broker.send(Event::RecordHistory).await.unwrap();
         task::sleep(dur).await;
     }
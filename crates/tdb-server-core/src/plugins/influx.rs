@@ -17,6 +17,7 @@ pub async fn timer_loop(mut broker: Sender<Event>, settings: Arc<Settings>) {
     let mut buf = String::new();
     loop {
         let (tx, mut rx) = mpsc::channel(2048);
+        crate::metrics::event_queued();
         broker.send(Event::FetchSizes { tx }).await.unwrap();
         while let Some(sizes) = rx.next().await {
             buf.clear();
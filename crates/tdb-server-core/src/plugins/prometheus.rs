@@ -0,0 +1,89 @@
+//! `/metrics` endpoint for prometheus scrapers
+//!
+//! The endpoint does not check `AUTH` and reports every book, so it binds to
+//! loopback unless `metrics_addr` names a host.
+use crate::prelude::*;
+
+/// largest request head we are willing to read
+const MAX_REQUEST_SZ: usize = 8192;
+
+pub async fn run(broker: Sender<Event>, settings: Arc<Settings>) {
+    task::spawn(async move {
+        if let Err(e) = accept_loop(broker, settings).await {
+            error!("metrics endpoint stopped: {}", e);
+        }
+    });
+}
+
+async fn accept_loop(broker: Sender<Event>, settings: Arc<Settings>) -> Result<()> {
+    let addr = match &settings.metrics_addr {
+        Some(addr) => bind_addr(addr),
+        None => return Ok(()),
+    };
+    let listener = TcpListener::bind(&addr).await?;
+    info!("Serving prometheus metrics on http://{}/metrics", addr);
+
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let broker = broker.clone();
+        task::spawn(async move {
+            if let Err(e) = respond(broker, stream?).await {
+                error!("metrics request failed: {}", e);
+            }
+            Ok::<(), std::io::Error>(())
+        });
+    }
+    Ok(())
+}
+
+/// `9002` and `:9002` bind to loopback, `host:port` is used as is
+fn bind_addr(addr: &str) -> String {
+    match addr.trim_start_matches(':') {
+        port if !port.contains(':') => format!("127.0.0.1:{}", port),
+        _ => addr.to_owned(),
+    }
+}
+
+async fn respond(mut broker: Sender<Event>, mut stream: TcpStream) -> Result<()> {
+    let mut req = Vec::new();
+    let mut buf = [0; 1024];
+    while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || req.len() + n > MAX_REQUEST_SZ {
+            return Ok(());
+        }
+        req.extend_from_slice(&buf[..n]);
+    }
+
+    let (status, body) = if req.starts_with(b"GET /metrics ") || req.starts_with(b"GET /metrics?") {
+        let (tx, mut rx) = mpsc::channel(1);
+        crate::metrics::event_queued();
+        broker.send(Event::FetchMetrics { tx }).await?;
+        let body = rx.next().await.ok_or("broker dropped metrics request")?;
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", "Not found. Try /metrics\n".to_owned())
+    };
+
+    let resp = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+    stream.write_all(resp.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_default_to_loopback() {
+        assert_eq!(bind_addr("9002"), "127.0.0.1:9002");
+        assert_eq!(bind_addr(":9002"), "127.0.0.1:9002");
+        assert_eq!(bind_addr("0.0.0.0:9002"), "0.0.0.0:9002");
+        assert_eq!(bind_addr("[::1]:9002"), "[::1]:9002");
+    }
+}
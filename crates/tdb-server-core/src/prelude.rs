@@ -1,6 +1,6 @@
 pub use crate::settings::{Settings, key_or_default, key_or_none};
 pub use crate::state::{TectonicServer, Book};
-pub use crate::handler::{ReturnType, Command, Event, Void, ReqCount, GetFormat, ReadLocation};
+pub use crate::handler::{ReturnType, Command, Event, Void, ReqCount, GetFormat, ReadLocation, BarType};
 pub use crate::utils;
 pub use tdb_core::dtf::{
     self,
@@ -1,6 +1,9 @@
 use crate::prelude::*;
 use byteorder::{BigEndian, ReadBytesExt};
 use async_std::future;
+use async_tls::TlsAcceptor;
+use crate::handler::ClientWriter;
+use std::collections::VecDeque;
 
 const IGNORE_TCP_WRITE: bool = true;
 
@@ -9,6 +12,7 @@ const IGNORE_TCP_WRITE: bool = true;
 #[allow(unused)]
 async fn onexit(mut broker: Sender<Event>, settings: Arc<Settings>) {
     info!("`TERM` signal recieved; flushing all stores...");
+    crate::metrics::event_queued();
     broker.send(Event::Command {from: None, command: Command::Flush(ReqCount::All)}).await.unwrap();
     info!("All stores flushed; calling plugin exit hooks...");
     crate::plugins::run_plugin_exit_hooks(broker, settings);
@@ -46,6 +50,13 @@ pub async fn run_server(host: &str, port: &str, settings: Arc<Settings>) -> Resu
         settings.flush_interval
     );
     info!("History granularity: {}.", settings.granularity);
+    let tls = match &settings.tls {
+        Some(tls_settings) => {
+            info!("TLS is on: cert {}, key {}.", tls_settings.cert, tls_settings.key);
+            Some(crate::tls::acceptor(tls_settings)?)
+        }
+        None => None,
+    };
 
     let listener = TcpListener::bind(addr).await?;
 
@@ -62,8 +73,17 @@ pub async fn run_server(host: &str, port: &str, settings: Arc<Settings>) -> Resu
     let mut incoming = listener.incoming();
     while let Some(stream) = incoming.next().await {
         let stream = stream?;
-        info!("Accepting from: {}", stream.peer_addr()?);
-        spawn_and_log_error(connection_loop(broker_sender.clone(), stream));
+        let addr = stream.peer_addr()?;
+        info!("Accepting from: {}", addr);
+        match &tls {
+            Some(acceptor) => {
+                spawn_and_log_error(tls_connection_loop(broker_sender.clone(), acceptor.clone(), stream));
+            }
+            None => {
+                let writer = ClientWriter(Box::new(stream.clone()));
+                spawn_and_log_error(connection_loop(broker_sender.clone(), stream, writer, addr));
+            }
+        }
     }
     drop(broker_sender);
     broker.await;
@@ -72,16 +92,26 @@ pub async fn run_server(host: &str, port: &str, settings: Arc<Settings>) -> Resu
 
 
 
-async fn connection_loop(mut broker: Sender<Event>, stream: TcpStream) -> Result<()> {
-    let stream = Arc::new(stream);
-    let mut reader = BufReader::new(&*stream);
+async fn tls_connection_loop(broker: Sender<Event>, acceptor: TlsAcceptor, stream: TcpStream) -> Result<()> {
     let addr = stream.peer_addr()?;
+    let stream = acceptor.accept(stream).await
+        .map_err(|e| format!("TLS handshake with {} failed: {}", addr, e))?;
+    let (reader, writer) = futures::io::AsyncReadExt::split(stream);
+    connection_loop(broker, reader, ClientWriter(Box::new(writer)), addr).await
+}
+
+async fn connection_loop<R>(mut broker: Sender<Event>, reader: R, writer: ClientWriter, addr: SocketAddr) -> Result<()>
+where
+    R: async_std::io::Read + Unpin,
+{
+    let mut reader = BufReader::new(reader);
 
     let (_shutdown_sender, shutdown_receiver) = mpsc::channel::<Void>(CHANNEL_SZ);
+    crate::metrics::event_queued();
     broker
         .send(Event::NewConnection {
             addr: addr,
-            stream: Arc::clone(&stream),
+            stream: writer,
             shutdown: shutdown_receiver,
         })
         .await
@@ -98,6 +128,7 @@ async fn connection_loop(mut broker: Sender<Event>, stream: TcpStream) -> Result
 
         let command = crate::handler::parse_to_command(&buf[..sz]);
         let from = Some(addr);
+        crate::metrics::event_queued();
         if let Err(_) = broker
             .send(Event::Command{from, command})
             .await
@@ -122,7 +153,10 @@ async fn broker_loop(mut events: Receiver<Event>, settings: Arc<Settings>) {
         let event = select! {
             event = events.next().fuse() => match event {
                 None => break,
-                Some(event) => event,
+                Some(event) => {
+                    crate::metrics::event_handled();
+                    event
+                }
             },
             disconnect = disconnect_receiver.next().fuse() => {
                 let (addr, _pending_messages) = disconnect.unwrap();
@@ -142,6 +176,9 @@ async fn broker_loop(mut events: Receiver<Event>, settings: Arc<Settings>) {
                 ).collect();
                 tx.send(sizes).await.unwrap();
             }
+            Event::FetchMetrics { mut tx } => {
+                let _ = tx.send(crate::metrics::render(&state)).await;
+            }
             Event::RecordHistory => {
                 state.record_history();
             }
@@ -169,58 +206,249 @@ async fn broker_loop(mut events: Receiver<Event>, settings: Arc<Settings>) {
     while let Some((_name, _pending_messages)) = disconnect_receiver.next().await { }
 }
 
+/// `[u8 success][u64 len][payload]`
+fn write_frame(buf: &mut Vec<u8>, msg: ReturnType) {
+    match msg {
+        ReturnType::Bytes(bytes) => {
+            buf.push(0x1);
+            buf.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+            buf.extend_from_slice(&bytes);
+        },
+        ReturnType::String(str_resp) => {
+            buf.push(0x1);
+            buf.extend_from_slice(&(str_resp.len() as u64).to_be_bytes());
+            buf.extend_from_slice(str_resp.as_bytes());
+        },
+        ReturnType::Error(errmsg) => {
+            buf.push(0x0);
+            let ret = format!("ERR: {}\n", errmsg);
+            buf.extend_from_slice(&(ret.len() as u64).to_be_bytes());
+            buf.extend_from_slice(ret.as_bytes());
+        },
+        ReturnType::Stream(_) | ReturnType::Deferred(_) => error!("nested response streams are not supported"),
+    }
+}
+
+/// await `fut` while moving messages for this connection into `pending`,
+/// so the broker never waits on a client that is slow to read a stream.
+/// `open` is cleared once `messages` ended, it must not be polled again.
+async fn draining<T>(
+    fut: impl Future<Output=T>,
+    messages: &mut Receiver<ReturnType>,
+    open: &mut bool,
+    pending: &mut VecDeque<ReturnType>,
+) -> T {
+    let fut = fut.fuse();
+    futures::pin_mut!(fut);
+    while *open {
+        select! {
+            ret = fut => return ret,
+            msg = messages.next() => match msg {
+                Some(msg) => pending.push_back(msg),
+                None => *open = false,
+            },
+        }
+    }
+    fut.await
+}
+
 async fn connection_writer_loop(
     messages: &mut Receiver<ReturnType>,
-    stream: Arc<TcpStream>,
+    stream: ClientWriter,
     mut shutdown: Receiver<Void>,
 ) -> Result<()> {
     let mut buf = Vec::with_capacity(CHANNEL_SZ);
-    let mut stream = &*stream;
+    let mut stream = stream.0;
+    // messages that arrived while a stream was written, sent after it in order
+    let mut pending = VecDeque::new();
+    let mut open = true;
     loop {
-        select! {
-            msg = messages.next().fuse() => {
-                match msg {
-                    Some(ReturnType::Bytes(bytes)) => {
-                        buf.write(&[0x1]).await?;
-                        buf.write(&bytes.len().to_be_bytes()).await?;
-                        buf.write(&bytes).await?;
-                        // buf.flush().await?;
-                    },
-                    Some(ReturnType::String(str_resp)) => {
-                        buf.write(&[0x1]).await?;
-                        buf.write(&str_resp.len().to_be_bytes()).await?;
-                        buf.write(&str_resp.as_bytes()).await?;
-                        // buf.flush().await?;
-                    },
-                    Some(ReturnType::Error(errmsg)) => {
-                        // error!("Req: `{}`", line);
-                        // error!("Err: `{}`", errmsg.clone());
-                        buf.write(&[0x0]).await?;
-                        let ret = format!("ERR: {}\n", errmsg);
-                        buf.write(&ret.len().to_be_bytes()).await?;
-                        buf.write(ret.as_bytes()).await?;
-                        // buf.flush().await?;
-                    },
+        let msg = match pending.pop_front() {
+            Some(msg) => msg,
+            None if !open => break,
+            None => select! {
+                msg = messages.next().fuse() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                void = shutdown.next().fuse() => match void {
+                    Some(void) => match void {},
                     None => break,
-                };
-                if let Err(future::TimeoutError {..}) = future::timeout(
-                    std::time::Duration::from_millis(0),
-                    stream.write_all(&buf)
-                ).await
-                {
-                    if IGNORE_TCP_WRITE {
-
-                    } else {
-                        error!("tcpstream write_all timeout.");
-                    }
                 }
-                buf.clear()
             },
-            void = shutdown.next().fuse() => match void {
-                Some(void) => match void {},
-                None => break,
+        };
+        if let ReturnType::Deferred(reply) = msg {
+            // later messages wait behind the reply like behind a stream
+            let mut reply = reply.0;
+            let msg = draining(reply.next(), messages, &mut open, &mut pending).await
+                .unwrap_or_else(|| ReturnType::error("Unable to compute reply"));
+            write_frame(&mut buf, msg);
+            draining(async {
+                stream.write_all(&buf).await?;
+                stream.flush().await
+            }, messages, &mut open, &mut pending).await?;
+            buf.clear();
+            continue;
+        }
+        if let ReturnType::Stream(chunks) = msg {
+            // wait for the client instead of dropping chunks or the end marker
+            let mut chunks = chunks.0;
+            loop {
+                let chunk = draining(chunks.next(), messages, &mut open, &mut pending).await;
+                // an error frame ends the stream in place of the end marker
+                let end = chunk.as_ref().is_none_or(|chunk| matches!(chunk, ReturnType::Error(_)));
+                write_frame(&mut buf, chunk.unwrap_or_else(|| ReturnType::Bytes(vec![])));
+                draining(async {
+                    stream.write_all(&buf).await?;
+                    stream.flush().await
+                }, messages, &mut open, &mut pending).await?;
+                buf.clear();
+                if end {
+                    break;
+                }
             }
+            continue;
         }
+        write_frame(&mut buf, msg);
+        if let Err(future::TimeoutError {..}) = future::timeout(
+            std::time::Duration::from_millis(0),
+            async {
+                stream.write_all(&buf).await?;
+                // TLS streams hold back records until flushed
+                stream.flush().await
+            }
+        ).await
+        {
+            if IGNORE_TCP_WRITE {
+
+            } else {
+                error!("tcpstream write_all timeout.");
+            }
+        }
+        buf.clear()
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::ResponseStream;
+    use byteorder::ByteOrder;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll};
+
+    /// collects everything written to a connection
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl async_std::io::Write for SharedWriter {
+        fn poll_write(self: Pin<&mut Self>, _: &mut Context, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// payloads of the frames written
+    fn frames(out: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+        let mut rest = out;
+        while !rest.is_empty() {
+            let len = BigEndian::read_u64(&rest[1..9]) as usize;
+            frames.push(rest[9..9 + len].to_vec());
+            rest = &rest[9 + len..];
+        }
+        frames
+    }
+
+    #[test]
+    fn should_end_streams_and_keep_receiving() {
+        let out = Arc::new(Mutex::new(vec![]));
+        let writer = ClientWriter(Box::new(SharedWriter(Arc::clone(&out))));
+        let (mut tx, mut rx) = mpsc::channel(1);
+        let (mut chunk_tx, chunk_rx) = mpsc::channel(1);
+        let (_shutdown_tx, shutdown_rx) = mpsc::channel::<Void>(1);
+        let writer_loop = task::spawn(async move {
+            connection_writer_loop(&mut rx, writer, shutdown_rx).await.unwrap();
+        });
+        let sent = task::block_on(future::timeout(std::time::Duration::from_secs(10), async move {
+            tx.send(ReturnType::Stream(ResponseStream(chunk_rx))).await.unwrap();
+            // far more than the channel holds while the stream is still open
+            for i in 0..100 {
+                tx.send(ReturnType::string(i.to_string())).await.unwrap();
+            }
+            chunk_tx.send(ReturnType::Bytes(vec![7])).await.unwrap();
+            drop(chunk_tx);
+            drop(tx);
+            writer_loop.await;
+        }));
+        assert!(sent.is_ok());
+
+        let frames = frames(&out.lock().unwrap());
+        assert_eq!(frames.len(), 102);
+        assert_eq!(frames[0], vec![7]);
+        // end marker before the replies that queued up behind the stream
+        assert!(frames[1].is_empty());
+        assert_eq!(frames[101], b"99".to_vec());
+    }
+
+    #[test]
+    fn should_end_stream_with_error_instead_of_end_marker() {
+        let out = Arc::new(Mutex::new(vec![]));
+        let writer = ClientWriter(Box::new(SharedWriter(Arc::clone(&out))));
+        let (mut tx, mut rx) = mpsc::channel(1);
+        let (mut chunk_tx, chunk_rx) = mpsc::channel(2);
+        let (_shutdown_tx, shutdown_rx) = mpsc::channel::<Void>(1);
+        let writer_loop = task::spawn(async move {
+            connection_writer_loop(&mut rx, writer, shutdown_rx).await.unwrap();
+        });
+        let sent = task::block_on(future::timeout(std::time::Duration::from_secs(10), async move {
+            tx.send(ReturnType::Stream(ResponseStream(chunk_rx))).await.unwrap();
+            chunk_tx.send(ReturnType::Bytes(vec![7])).await.unwrap();
+            chunk_tx.send(ReturnType::error("Unable to read 1.dtf")).await.unwrap();
+            tx.send(ReturnType::string("next")).await.unwrap();
+            drop(tx);
+            writer_loop.await;
+        }));
+        assert!(sent.is_ok());
+
+        let out = out.lock().unwrap();
+        let frames = frames(&out);
+        assert_eq!(frames, vec![vec![7], b"ERR: Unable to read 1.dtf\n".to_vec(), b"next".to_vec()]);
+        // the error frame is marked as failed
+        assert_eq!(out[10], 0x0);
+    }
+
+    #[test]
+    fn should_write_deferred_reply_in_order() {
+        let out = Arc::new(Mutex::new(vec![]));
+        let writer = ClientWriter(Box::new(SharedWriter(Arc::clone(&out))));
+        let (mut tx, mut rx) = mpsc::channel(1);
+        let (mut reply_tx, reply_rx) = mpsc::channel(1);
+        let (_shutdown_tx, shutdown_rx) = mpsc::channel::<Void>(1);
+        let writer_loop = task::spawn(async move {
+            connection_writer_loop(&mut rx, writer, shutdown_rx).await.unwrap();
+        });
+        let sent = task::block_on(future::timeout(std::time::Duration::from_secs(10), async move {
+            tx.send(ReturnType::Deferred(ResponseStream(reply_rx))).await.unwrap();
+            for i in 0..10 {
+                tx.send(ReturnType::string(i.to_string())).await.unwrap();
+            }
+            reply_tx.send(ReturnType::string("candles")).await.unwrap();
+            drop(tx);
+            writer_loop.await;
+        }));
+        assert!(sent.is_ok());
+
+        let frames = frames(&out.lock().unwrap());
+        assert_eq!(frames.len(), 11);
+        assert_eq!(frames[0], b"candles".to_vec());
+        assert_eq!(frames[10], b"9".to_vec());
+    }
+}
@@ -1,6 +1,15 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::error::Error;
+use std::path::Path;
 use std::str::FromStr;
+use crate::auth::{AuthConfig, matches_pattern};
+use crate::config::{BookSettings, GStorageSettings};
+
+/// used for books without `price_decimals` in the config file
+pub const DEFAULT_PRICE_DECIMALS: u8 = 10;
+/// keeps prices up to about 18 million representable as `u64` units of `10^-price_decimals`
+pub const MAX_PRICE_DECIMALS: u8 = 12;
 
 pub fn key_or_default_parse<
     E: Into<Box<dyn Error>>,
@@ -38,8 +47,168 @@ pub struct Settings {
     pub granularity: u64,
     /// history circular queue capacity
     pub q_capacity: usize,
+    /// evict loaded books beyond this many megabytes, 0 is unlimited
+    pub memory_limit_mb: usize,
+    /// checkpoint the orderbook every n updates, 0 disables
+    pub snapshot_interval: u32,
+    /// checkpoint the orderbook every n seconds of market time, 0 disables
+    pub snapshot_secs: u64,
     /// settings for influxdb
     pub influx: Option<InfluxSettings>,
+    /// users allowed to connect, auth is disabled when none
+    pub auth: Option<AuthConfig>,
+    /// serve clients over TLS, plain TCP when none
+    pub tls: Option<TlsSettings>,
+    /// address of the unauthenticated prometheus `/metrics` endpoint, disabled when none.
+    /// A bare port binds to loopback.
+    pub metrics_addr: Option<String>,
+    /// per book overrides keyed by name or pattern
+    pub books: BTreeMap<String, BookSettings>,
+    /// settings for the gstorage plugin from the config file
+    pub gstorage: Option<GStorageSettings>,
+}
+
+/// Effective settings of a single book
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BookConfig {
+    pub price_decimals: u8,
+    pub tick_size: u64,
+    pub autoflush: bool,
+    pub flush_interval: u32,
+    pub snapshot_interval: u32,
+    pub snapshot_secs: u64,
+}
+
+impl Settings {
+    /// Resolve the settings of `book_name`: the exact entry in `books` wins,
+    /// then the longest matching pattern, then the global storage settings.
+    pub fn book_config(&self, book_name: &str) -> BookConfig {
+        let overrides = self.books.get(book_name).or_else(||
+            self.books.iter()
+                .filter(|(pattern, _)| matches_pattern(pattern, book_name))
+                .max_by_key(|(pattern, _)| pattern.len())
+                .map(|(_, settings)| settings)
+        );
+        let overrides = overrides.cloned().unwrap_or_default();
+        BookConfig {
+            price_decimals: overrides.price_decimals.unwrap_or(DEFAULT_PRICE_DECIMALS),
+            tick_size: overrides.tick_size.unwrap_or(1),
+            autoflush: overrides.autoflush.unwrap_or(self.autoflush),
+            flush_interval: overrides.flush_interval.unwrap_or(self.flush_interval),
+            snapshot_interval: self.snapshot_interval,
+            snapshot_secs: self.snapshot_secs,
+        }
+    }
+
+    /// check settings for values the server can't run with
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dtf_folder.is_empty() {
+            return Err("storage.dtf_folder must not be empty".to_owned());
+        }
+        if self.autoflush && self.flush_interval == 0 {
+            return Err("storage.flush_interval must be positive when autoflush is on".to_owned());
+        }
+        if self.q_capacity == 0 {
+            return Err("storage.q_capacity must be positive".to_owned());
+        }
+        if let Some(tls) = &self.tls {
+            for (key, fname) in &[("server.tls_cert", &tls.cert), ("server.tls_key", &tls.key)] {
+                if !Path::new(fname).is_file() {
+                    return Err(format!("{}: file `{}` not found", key, fname));
+                }
+            }
+        }
+        if let Some(influx) = &self.influx {
+            if influx.interval == 0 {
+                return Err("plugins.influx.interval must be positive".to_owned());
+            }
+        }
+        for (key, book) in &self.books {
+            if !key.contains('*') && key.len() > 64 {
+                return Err(format!("books.{}: book names are at most 64 bytes", key));
+            }
+            if book.price_decimals.map(|d| d > MAX_PRICE_DECIMALS).unwrap_or(false) {
+                return Err(format!("books.{}.price_decimals must be at most {}", key, MAX_PRICE_DECIMALS));
+            }
+            if book.tick_size == Some(0) {
+                return Err(format!("books.{}.tick_size must be positive", key));
+            }
+            let conf = self.book_config(key);
+            if conf.autoflush && conf.flush_interval == 0 {
+                return Err(format!("books.{}.flush_interval must be positive when autoflush is on", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// every setting as `(key, value)`, values formatted as in the config file
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let quote = |s: &str| format!("{:?}", s);
+        let mut ret = vec![
+            ("storage.dtf_folder".to_owned(), quote(&self.dtf_folder)),
+            ("storage.autoflush".to_owned(), self.autoflush.to_string()),
+            ("storage.flush_interval".to_owned(), self.flush_interval.to_string()),
+            ("storage.granularity".to_owned(), self.granularity.to_string()),
+            ("storage.q_capacity".to_owned(), self.q_capacity.to_string()),
+            ("storage.memory_limit_mb".to_owned(), self.memory_limit_mb.to_string()),
+            ("storage.snapshot_interval".to_owned(), self.snapshot_interval.to_string()),
+            ("storage.snapshot_secs".to_owned(), self.snapshot_secs.to_string()),
+            ("server.auth".to_owned(), self.auth.is_some().to_string()),
+            ("server.tls".to_owned(), self.tls.is_some().to_string()),
+        ];
+        if let Some(addr) = &self.metrics_addr {
+            ret.push(("server.metrics_addr".to_owned(), quote(addr)));
+        }
+        if let Some(influx) = &self.influx {
+            ret.push(("plugins.influx.host".to_owned(), quote(&influx.host)));
+            ret.push(("plugins.influx.db".to_owned(), quote(&influx.db)));
+            ret.push(("plugins.influx.interval".to_owned(), influx.interval.to_string()));
+        }
+        for (key, book) in &self.books {
+            if let Some(d) = book.price_decimals {
+                ret.push((format!("books.{}.price_decimals", key), d.to_string()));
+            }
+            if let Some(t) = book.tick_size {
+                ret.push((format!("books.{}.tick_size", key), t.to_string()));
+            }
+            if let Some(a) = book.autoflush {
+                ret.push((format!("books.{}.autoflush", key), a.to_string()));
+            }
+            if let Some(i) = book.flush_interval {
+                ret.push((format!("books.{}.flush_interval", key), i.to_string()));
+            }
+        }
+        ret
+    }
+
+    /// Change one of the runtime tunable settings:
+    /// `storage.autoflush`, `storage.flush_interval`,
+    /// `books.NAME.autoflush` and `books.NAME.flush_interval`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let parse_bool = |v: &str| v.parse::<bool>().map_err(|_| format!("`{}` expects true or false", key));
+        let parse_u32 = |v: &str| v.parse::<u32>().map_err(|_| format!("`{}` expects a positive integer", key));
+        let mut new = self.clone();
+        match key {
+            "storage.autoflush" => new.autoflush = parse_bool(value)?,
+            "storage.flush_interval" => new.flush_interval = parse_u32(value)?,
+            _ => {
+                let book = key.strip_prefix("books.")
+                    .and_then(|k| k.rfind('.').map(|i| (&k[..i], &k[i + 1..])));
+                match book {
+                    Some((name, "autoflush")) if !name.is_empty() =>
+                        new.books.entry(name.to_owned()).or_default().autoflush = Some(parse_bool(value)?),
+                    Some((name, "flush_interval")) if !name.is_empty() =>
+                        new.books.entry(name.to_owned()).or_default().flush_interval = Some(parse_u32(value)?),
+                    _ if self.entries().iter().any(|(k, _)| k == key) =>
+                        return Err(format!("`{}` cannot be changed at runtime", key)),
+                    _ => return Err(format!("Unknown setting `{}`", key)),
+                }
+            }
+        }
+        new.validate()?;
+        *self = new;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -47,4 +216,11 @@ pub struct InfluxSettings {
     pub host: String,
     pub db: String,
     pub interval: u64,
-}
\ No newline at end of file
+}
+#[derive(Clone, Debug, Default)]
+pub struct TlsSettings {
+    /// PEM encoded certificate chain
+    pub cert: String,
+    /// PEM encoded private key
+    pub key: String,
+}
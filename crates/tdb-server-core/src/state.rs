@@ -14,13 +14,33 @@
 #[cfg(feature = "count_alloc")]
 use alloc_counter::{count_alloc, count_alloc_future};
 use crate::prelude::*;
+use crate::auth::{Role, User};
+use crate::metrics::{BookMetrics, Metrics};
+use crate::settings::BookConfig;
 
 use circular_queue::CircularQueue;
-use tdb_core::dtf::file_format::scan_files_for_range;
+use tdb_core::dtf::file_format::{decode_while, find_files_for_symbol, read_meta, scan_files_for_range};
+use tdb_core::dtf::snapshot::{self, Snapshot, snapshot_fname};
+use tdb_core::postprocessing::candle::{
+    Candle,
+    Sampler,
+    bars::BarBuilder,
+    time_bars::TimeSampler,
+    tick_bars::TickSampler,
+    volume_bars::VolumeSampler,
+    dollar_bars::DollarSampler,
+};
+use tdb_core::postprocessing::bbo::Bbo;
 use tdb_core::postprocessing::orderbook::Orderbook;
-use std::time::{SystemTime, UNIX_EPOCH};
+use tdb_core::postprocessing::stats;
+use std::cell::Cell;
+use std::fs;
+use std::mem;
+use std::ops::Range;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-static PRICE_DECIMALS: u8 = 10; // TODO: don't hardcode this
+/// most book samples a single `STATS` may take, each keeps a mid and a spread
+pub const MAX_STATS_SAMPLES: u64 = 1_000_000;
 
 macro_rules! catch {
     ($($code:tt)*) => {
@@ -52,6 +72,86 @@ pub fn into_format(result: &[Update], format: GetFormat) -> Option<ReturnType> {
     })
 }
 
+/// Replay the updates of `symbol` with `after < ts <= until` from every file
+/// in `folder`, oldest file first, until `f` returns false. Returns the ts of
+/// the last replayed update, or why a file could not be read.
+pub(crate) fn replay_from_disk<F: FnMut(&Update) -> bool>(folder: &str, symbol: &str, after: Option<u64>, until: u64,
+    f: &mut F) -> std::result::Result<Option<u64>, String>
+{
+    // files overlapping the range, decoded whole and filtered by ts
+    let fnames = find_files_for_symbol(folder, symbol)
+        .map_err(|e| format!("Unable to scan {} for {}: {}", folder, symbol, e))?;
+    let mut files = Vec::with_capacity(fnames.len());
+    for fname in fnames {
+        let meta = read_meta(&fname).map_err(|e| format!("Unable to read {}: {}", fname, e))?;
+        if meta.min_ts <= until && after.map(|after| meta.max_ts > after).unwrap_or(true) {
+            files.push((meta, fname));
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut last = None;
+    for (_, fname) in files {
+        let done = decode_while(&fname, &mut |up| {
+            if up.ts > until || after.map(|after| up.ts <= after).unwrap_or(false) {
+                return true;
+            }
+            last = Some(last.map_or(up.ts, |last: u64| last.max(up.ts)));
+            f(up)
+        }).map_err(|e| format!("Unable to read {}: {}", fname, e))?;
+        if !done {
+            break;
+        }
+    }
+    Ok(last)
+}
+
+/// The orderbook of `book_name` at its nearest checkpoint at or before `ts`
+/// and the ts of the checkpoint, an empty book when there is none.
+pub(crate) fn checkpoint(folder: &str, book_name: &str, config: BookConfig, ts: u64) -> (Orderbook, Option<u64>) {
+    let fname = snapshot_fname(folder, book_name);
+    let snap = if Path::new(&fname).exists() {
+        snapshot::nearest(&fname, ts).unwrap_or_else(|e| {
+            error!("Unable to read {}: {}", fname, e);
+            None
+        })
+    } else {
+        None
+    };
+    match snap {
+        Some(snap) if snap.orderbook.price_decimals == config.price_decimals =>
+            (snap.orderbook.with_tick_size(config.tick_size), Some(snap.ts)),
+        _ => (Orderbook::with_precision(config.price_decimals).with_tick_size(config.tick_size), None),
+    }
+}
+
+/// Replay the updates of `symbol` in `[min_ts, max_ts]`, from the files in
+/// `folder` first, then those in `mem` after the last update on disk, until
+/// `f` returns false.
+pub(crate) fn replay_range<F: FnMut(&Update) -> bool>(folder: Option<&str>, symbol: &str, (min_ts, max_ts): (u64, u64),
+    mem: &[Update], f: &mut F) -> std::result::Result<(), String>
+{
+    let mut on_disk = None;
+    if let Some(folder) = folder {
+        let mut more = true;
+        on_disk = replay_from_disk(folder, symbol, min_ts.checked_sub(1), max_ts, &mut |up| {
+            more = f(up);
+            more
+        })?;
+        if !more {
+            return Ok(());
+        }
+    }
+    for up in mem.iter()
+        .filter(|up| up.ts >= min_ts && up.ts <= max_ts)
+        .filter(|up| on_disk.map(|last| up.ts > last).unwrap_or(true))
+    {
+        if !f(up) {
+            break;
+        }
+    }
+    Ok(())
+}
+
 pub struct Book {
     pub vec: Vec<Update>,
     /// nominal count of updates from disk
@@ -60,14 +160,29 @@ pub struct Book {
     pub in_memory: bool,
     pub orderbook: Orderbook,
     pub settings: Arc<Settings>,
+    /// settings resolved for this book
+    pub config: BookConfig,
+    pub metrics: BookMetrics,
+    /// ts of the last update applied to `orderbook`
+    last_ts: Option<u64>,
+    /// ts of the last orderbook checkpoint, or of the first update
+    snapshot_ts: Option<u64>,
+    /// updates since the last orderbook checkpoint
+    updates_since_snapshot: u32,
+    /// position of the updates read by `LOAD` in `vec`, they are already on disk
+    loaded: Range<usize>,
+    /// last time a client read or loaded this book, for LRU eviction
+    last_access: Cell<Instant>,
 }
 
 impl Book {
 
-    pub fn new(name: &str, settings: Arc<Settings>, price_decimals: u8) -> Self {
-        let vec = Vec::with_capacity(usize::max(settings.flush_interval as usize * 3, 1024*64));
+    pub fn new(name: &str, settings: Arc<Settings>) -> Self {
+        let config = settings.book_config(name);
+        // allocated on the first insert, most books are only read
+        let vec = Vec::new();
         let nominal_count = 0;
-        let orderbook = Orderbook::with_precision(price_decimals);
+        let orderbook = Orderbook::with_precision(config.price_decimals).with_tick_size(config.tick_size);
         let name = name.to_owned();
         let in_memory = false;
         let mut ret = Self {
@@ -77,11 +192,105 @@ impl Book {
             name,
             in_memory,
             settings,
+            config,
+            metrics: BookMetrics::default(),
+            last_ts: None,
+            snapshot_ts: None,
+            updates_since_snapshot: 0,
+            loaded: 0..0,
+            last_access: Cell::new(Instant::now()),
         };
         ret.load_size_from_file();
+        ret.restore_orderbook();
         ret
     }
 
+    /// Restore the live orderbook from the latest checkpoint and the updates
+    /// flushed after it. The orderbook stays empty without checkpoints.
+    fn restore_orderbook(&mut self) {
+        let fname = snapshot_fname(&self.settings.dtf_folder, &self.name);
+        if !Path::new(&fname).exists() {
+            return;
+        }
+        match snapshot::latest(&fname) {
+            Ok(Some(snap)) if snap.orderbook.price_decimals == self.config.price_decimals => {
+                let ob = &mut self.orderbook;
+                *ob = snap.orderbook.with_tick_size(self.config.tick_size);
+                let last = replay_from_disk(&self.settings.dtf_folder, &self.name, Some(snap.ts), u64::MAX,
+                    &mut |up| { ob.process_update(up); true })
+                    .unwrap_or_else(|e| { error!("{}", e); None });
+                self.last_ts = Some(last.unwrap_or(snap.ts));
+                self.snapshot_ts = Some(snap.ts);
+                info!("Restored orderbook {} from checkpoint at {}", self.name, snap.ts);
+            }
+            Ok(Some(_)) => warn!("Ignoring checkpoints of {} taken with different price_decimals", self.name),
+            Ok(None) => (),
+            Err(e) => error!("Unable to read {}: {}", fname, e),
+        }
+    }
+
+    /// a checkpoint is due before applying an update at `ts`,
+    /// never between updates with the same timestamp
+    fn snapshot_due(&self, ts: u64) -> bool {
+        let last_ts = match self.last_ts {
+            Some(last_ts) if ts > last_ts => last_ts,
+            _ => return false,
+        };
+        let by_count = self.config.snapshot_interval > 0
+            && self.updates_since_snapshot >= self.config.snapshot_interval;
+        let by_time = self.config.snapshot_secs > 0
+            && self.snapshot_ts.map(|s| last_ts >= s + self.config.snapshot_secs * 1000).unwrap_or(false);
+        by_count || by_time
+    }
+
+    /// append the current orderbook to the checkpoint file
+    fn snapshot(&mut self) {
+        let ts = match self.last_ts {
+            Some(ts) => ts,
+            None => return,
+        };
+        utils::create_dir_if_not_exist(&self.settings.dtf_folder);
+        let fname = snapshot_fname(&self.settings.dtf_folder, &self.name);
+        let snap = Snapshot { ts, orderbook: self.orderbook.clone() };
+        match snapshot::append(&fname, &snap) {
+            Ok(()) => debug!("Checkpointed orderbook {} at {}", self.name, ts),
+            Err(e) => error!("Unable to write checkpoint {}: {}", fname, e),
+        }
+        self.snapshot_ts = Some(ts);
+        self.updates_since_snapshot = 0;
+    }
+
+    /// updates to reserve on the first insert
+    fn prealloc(&self) -> usize {
+        usize::max(self.config.flush_interval as usize * 3, 1024*64)
+    }
+
+    /// approximate bytes held by updates and the orderbook
+    pub fn memory_usage(&self) -> usize {
+        let levels = self.orderbook.bids.len() + self.orderbook.asks.len();
+        self.vec.capacity() * mem::size_of::<Update>() + levels * 2 * mem::size_of::<u64>()
+    }
+
+    /// mark as recently used
+    pub fn touch(&self) {
+        self.last_access.set(Instant::now());
+    }
+
+    /// number of updates read by `LOAD` that are still in memory
+    pub fn loaded_count(&self) -> usize {
+        self.loaded.len()
+    }
+
+    /// drop the updates read by `LOAD` and release their memory, returns bytes freed
+    fn evict(&mut self) -> usize {
+        let before = self.memory_usage();
+        self.vec.drain(self.loaded.clone());
+        self.vec.shrink_to_fit();
+        self.loaded = 0..0;
+        self.in_memory = false;
+        before - self.memory_usage()
+    }
+
     /// load items from dtf file
     fn load(&mut self) {
         let fname = format!("{}/{}.dtf", &self.settings.dtf_folder, self.name);
@@ -96,6 +305,7 @@ impl Book {
             match ups {
                 Ok(mut ups) => {
                     // let size = ups.len() as u64;
+                    self.loaded = self.vec.len()..self.vec.len() + ups.len();
                     self.vec.append(&mut ups);
                     // wtr.vec_store.insert(self.name.to_owned(), (ups, size));
                     self.in_memory = true;
@@ -125,12 +335,22 @@ impl Book {
 
     #[cfg_attr(feature = "count_alloc", count_alloc)]
     fn add(&mut self, up: Update) {
+        if self.snapshot_due(up.ts) {
+            self.snapshot();
+        }
+        if self.vec.capacity() == 0 {
+            self.vec.reserve(self.prealloc());
+        }
         self.vec.push(up);
         self.nominal_count += 1;
+        self.metrics.inserts += 1;
         self.orderbook.process_update(&up);
+        self.last_ts = Some(self.last_ts.map_or(up.ts, |ts| ts.max(up.ts)));
+        self.snapshot_ts.get_or_insert(up.ts);
+        self.updates_since_snapshot += 1;
         // Saves current store into disk after n items is inserted.
         let len = self.vec.len() as u32;
-        if self.settings.autoflush && len != 0 && len % self.settings.flush_interval == 0 {
+        if self.config.autoflush && len != 0 && len % self.config.flush_interval == 0 {
             info!(
                 "AUTOFLUSHING {}! Size: {}",
                 self.name,
@@ -151,20 +371,25 @@ impl Book {
         utils::create_dir_if_not_exist(&self.settings.dtf_folder);
 
         let fpath = Path::new(&fname);
+        let start = Instant::now();
         let result = if fpath.exists() {
             info!("File exists. Appending...");
             dtf::file_format::append(&fname, &self.vec)
         } else {
             dtf::file_format::encode(&fname, &self.name, &self.vec)
         };
+        self.metrics.flush_latency.observe(start.elapsed());
         match result {
             Ok(_) => {
+                self.metrics.flushes += 1;
                 info!("Successfully flushed into {}.", fname);
                 self.vec.clear();
+                self.loaded = 0..0;
                 self.in_memory = false;
                 Some(())
             }
             Err(e) => {
+                self.metrics.flush_errors += 1;
                 error!("Error flushing file. {}", e);
                 None
             }
@@ -179,6 +404,9 @@ pub struct Connection {
 
     /// the current Store client is using
     pub book_entry: Arc<BookName>,
+
+    /// user authenticated with `AUTH`
+    pub user: Option<User>,
 }
 
 impl Connection {
@@ -186,10 +414,26 @@ impl Connection {
         Self {
             outbound,
             book_entry: Arc::new(BookName::from("default").unwrap()),
+            user: None,
         }
     }
 }
 
+/// What a subscriber receives
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubscriptionMode {
+    /// every update, DTF encoded
+    Updates,
+    /// top of book changes as JSON lines
+    Bbo,
+}
+
+#[derive(Debug)]
+pub struct Subscriber {
+    pub outbound: Sender<ReturnType>,
+    pub mode: SubscriptionMode,
+}
+
 /// key: { btc_neo => [(t0, c0), (t1, c1), ...]
 ///        ...
 ///      { total => [...]}
@@ -199,7 +443,8 @@ pub struct TectonicServer {
     pub settings: Arc<Settings>,
     pub books: HashMap<BookName, Book>,
     pub history: CountHistory,
-    pub subscriptions: HashMap<BookName, HashMap<SocketAddr, Sender<ReturnType>>>,
+    pub subscriptions: HashMap<BookName, HashMap<SocketAddr, Subscriber>>,
+    pub metrics: Metrics,
 }
 
 impl TectonicServer {
@@ -208,7 +453,7 @@ impl TectonicServer {
         let mut books = HashMap::new();
         books.insert(
             BookName::from("default").unwrap(),
-            Book::new("default", settings.clone(), PRICE_DECIMALS)
+            Book::new("default", settings.clone())
         );
         let subscriptions = HashMap::new();
         let history = HashMap::new();
@@ -218,17 +463,21 @@ impl TectonicServer {
             history,
             subscriptions,
             connections,
+            metrics: Metrics::default(),
         }
     }
 
     pub async fn process_command(&mut self, command: Command, addr: Option<SocketAddr>) -> ReturnType {
         use Command::*;
+        if let Err(e) = self.authorize(&command, addr) {
+            return ReturnType::error(e);
+        }
         match command {
             Noop => ReturnType::string(""),
             Ping => ReturnType::string("PONG"),
             Help => ReturnType::string(ReturnType::HELP_STR),
-            Info => ReturnType::string(self.info()),
-            Perf => ReturnType::string(self.perf()),
+            Info => ReturnType::string(self.info(addr)),
+            Perf => ReturnType::string(self.perf(addr)),
             Orderbook(book_name) => {
                 let book_name = book_name
                     .map(|i| Arc::new(i))
@@ -237,6 +486,17 @@ impl TectonicServer {
                     .map(|c| ReturnType::string(c))
                     .unwrap_or_else(|| ReturnType::error("Unable to get orderbook"))
             },
+            OrderbookAt(book_name, ts, depth) => {
+                match self.orderbook_at(&book_name, ts) {
+                    Some(ob) => {
+                        let ob = depth.map(|n| ob.top_levels(n)).unwrap_or(ob);
+                        serde_json::to_string(&ob)
+                            .map(ReturnType::string)
+                            .unwrap_or_else(|_| ReturnType::error("Unable to get orderbook"))
+                    }
+                    None => ReturnType::error(format!("No db named `{}`", book_name)),
+                }
+            }
             Count(ReqCount::Count(_), ReadLocation::Fs) => {
                 self.count(addr)
                     .map(|c| ReturnType::string(format!("{}", c)))
@@ -247,8 +507,8 @@ impl TectonicServer {
                     .map(|c| ReturnType::string(format!("{}", c)))
                     .unwrap_or_else(|| ReturnType::error("Unable to get count in memory"))
             },
-            Count(ReqCount::All, ReadLocation::Fs) => ReturnType::string(format!("{}", self.countall())),
-            Count(ReqCount::All, ReadLocation::Mem) => ReturnType::string(format!("{}", self.countall_in_mem())),
+            Count(ReqCount::All, ReadLocation::Fs) => ReturnType::string(format!("{}", self.countall(addr))),
+            Count(ReqCount::All, ReadLocation::Mem) => ReturnType::string(format!("{}", self.countall_in_mem(addr))),
             Clear(ReqCount::Count(_)) => {
                 self.clear(addr);
                 ReturnType::ok()
@@ -270,6 +530,11 @@ impl TectonicServer {
                 let book_name = book_name
                     .map(|i| Arc::new(i))
                     .unwrap_or_else(|| Arc::clone(&self.conn(addr).unwrap().book_entry));
+                let representable = self.books.get(&*book_name)
+                    .is_none_or(|book| book.orderbook.checked_discretize(up.price).is_some());
+                if !representable {
+                    return ReturnType::error(format!("Price {} cannot be represented in {}", up.price, &book_name));
+                }
                 match self.insert(up, &book_name).await {
                     Some(()) => ReturnType::string(""),
                     None => ReturnType::Error(Cow::Owned(format!("DB {} not found.", &book_name))),
@@ -280,10 +545,14 @@ impl TectonicServer {
                     Some(()) => ReturnType::string(format!("Created orderbook `{}`.", &dbname)),
                     None => ReturnType::error(format!("Unable to create orderbook `{}`.", &dbname)),
                 },
-            Subscribe(dbname) => {
-                self.sub(&dbname, addr);
+            Subscribe(dbname, _) => {
+                self.sub(&dbname, addr, SubscriptionMode::Updates);
                 ReturnType::string(format!("Subscribed to {}", dbname))
             }
+            SubscribeBbo(dbname) => {
+                self.sub(&dbname, addr, SubscriptionMode::Bbo);
+                ReturnType::string(format!("Subscribed to {} BBO", dbname))
+            }
             // Subscription => {
             //     let message = state.rx.as_ref().unwrap().try_recv();
             //     match message {
@@ -301,9 +570,9 @@ impl TectonicServer {
             //     ReturnType::string(format!("Unsubscribed from {}", old_dbname))
             // }
             Load(dbname) => {
-                match self.load_db(&dbname, addr) {
-                    Some(_) => ReturnType::string(format!("Loaded orderbook `{}`.", &dbname)),
-                    None => ReturnType::error(format!("No db named `{}`", dbname)),
+                match self.load_db(&dbname) {
+                    Ok(()) => ReturnType::string(format!("Loaded orderbook `{}`.", &dbname)),
+                    Err(e) => ReturnType::error(e),
                 }
             }
             Use(dbname) => {
@@ -319,9 +588,54 @@ impl TectonicServer {
                     ReturnType::error(format!("No db named `{}`", dbname))
                 }
             }
+            Drop(dbname, confirmed) => {
+                match self.drop_book(&dbname, confirmed) {
+                    Ok(n) => ReturnType::string(format!("Dropped orderbook `{}` and {} file(s).", dbname, n)),
+                    Err(e) => ReturnType::error(e),
+                }
+            }
+            Rename(old, new) => {
+                match self.rename_book(&old, &new) {
+                    Ok(n) => ReturnType::string(format!("Renamed orderbook `{}` to `{}` in {} file(s).", old, new, n)),
+                    Err(e) => ReturnType::error(e),
+                }
+            }
+            Auth(token) => {
+                match self.auth(&token, addr) {
+                    Some(user) => ReturnType::string(format!("Authenticated as `{}`.", user)),
+                    None => ReturnType::error("Invalid token."),
+                }
+            }
+            ConfigGet(None) => {
+                let entries: Vec<String> = self.settings.entries().into_iter()
+                    .map(|(k, v)| format!("{} = {}", k, v))
+                    .collect();
+                ReturnType::string(entries.join("\n") + "\n")
+            }
+            ConfigGet(Some(key)) => {
+                match self.settings.entries().into_iter().find(|(k, _)| *k == key) {
+                    Some((_, v)) => ReturnType::string(v),
+                    None => ReturnType::error(format!("Unknown setting `{}`", key)),
+                }
+            }
+            ConfigSet(key, value) => {
+                match self.set_config(&key, &value) {
+                    Ok(()) => ReturnType::ok(),
+                    Err(e) => ReturnType::error(e),
+                }
+            }
+            Candles(book_name, bar_type, (min_ts, max_ts), fmt) =>
+                self.candles(&book_name, bar_type, min_ts, max_ts, fmt)
+                    .unwrap_or_else(|| ReturnType::error(format!("No db named `{}`", book_name))),
+            Stats(book_name, (min_ts, max_ts), sampling) =>
+                self.stats(&book_name, min_ts, max_ts, sampling)
+                    .unwrap_or_else(|| ReturnType::error(format!("No db named `{}`", book_name))),
             Get(cnt, fmt, rng, loc) =>
                 self.get(cnt, fmt, rng, loc, addr)
                     .unwrap_or_else(|| ReturnType::error("Not enough items to return")),
+            GetStream(cnt, fmt, rng, loc) =>
+                self.get_stream(cnt, fmt, rng, loc, addr)
+                    .unwrap_or_else(|| ReturnType::error("Unable to stream")),
             Unknown => {
                 error!("Unknown command");
                 ReturnType::error("Unknown command.")
@@ -333,6 +647,86 @@ impl TectonicServer {
         }
     }
 
+    /// Check that the user behind `addr` may run `command`.
+    ///
+    /// Always succeeds when auth is disabled or for internal commands without a connection.
+    pub fn authorize(&self, command: &Command, addr: Option<SocketAddr>) -> std::result::Result<(), Cow<'static, str>> {
+        use Command::*;
+        if self.settings.auth.is_none() || addr.is_none() {
+            return Ok(());
+        }
+        let (role, book_name) = match command {
+            Noop | Ping | Help | Auth(_) | Unknown | BadFormat => return Ok(()),
+            // any user may run these, the output only covers books passing `can_see`
+            Info | Perf | Count(ReqCount::All, _) => {
+                return self.conn(addr)
+                    .and_then(|c| c.user.as_ref())
+                    .map(|_| ())
+                    .ok_or_else(|| "Authentication required. Use `AUTH [token]`.".into());
+            }
+            Orderbook(book_name) => (Role::ReadOnly, book_name.as_ref()),
+            Get(..) | GetStream(..) | Count(..) => (Role::ReadOnly, None),
+            Subscribe(book_name, _) | SubscribeBbo(book_name) | Load(book_name) | Use(book_name) | Exists(book_name) =>
+                (Role::ReadOnly, Some(book_name)),
+            Insert(_, book_name) => (Role::Writer, book_name.as_ref()),
+            Create(book_name) => (Role::Writer, Some(book_name)),
+            Clear(ReqCount::Count(_)) | Flush(ReqCount::Count(_)) => (Role::Writer, None),
+            Clear(ReqCount::All) | Flush(ReqCount::All) | ConfigGet(_) | ConfigSet(..) => (Role::Admin, None),
+            Drop(book_name, _) | Rename(book_name, _) => (Role::Admin, Some(book_name)),
+            Candles(book_name, ..) | Stats(book_name, ..) | OrderbookAt(book_name, ..) =>
+                (Role::ReadOnly, Some(book_name)),
+        };
+
+        let conn = self.conn(addr).ok_or("Connection not found.")?;
+        let user = conn.user.as_ref().ok_or("Authentication required. Use `AUTH [token]`.")?;
+        if user.role < role {
+            return Err(format!("Permission denied: `{}` requires {} role.", user.name, role).into());
+        }
+        let book_name = book_name.map(|b| b.as_str()).unwrap_or_else(|| conn.book_entry.as_str());
+        if !user.can_access(book_name) {
+            return Err(format!("Permission denied: `{}` cannot access `{}`.", user.name, book_name).into());
+        }
+        if let Rename(_, new) = command {
+            if !user.can_access(new) {
+                return Err(format!("Permission denied: `{}` cannot access `{}`.", user.name, new).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the user behind `addr` may see `book_name` in server wide output
+    pub fn can_see(&self, addr: Option<SocketAddr>, book_name: &str) -> bool {
+        self.conn(addr)
+            .and_then(|c| c.user.as_ref())
+            .is_none_or(|user| user.can_access(book_name))
+    }
+
+    /// authenticate connection with token, returns user name
+    pub fn auth(&mut self, token: &str, addr: Option<SocketAddr>) -> Option<String> {
+        let user = self.settings.auth.as_ref()?.authenticate(token)?.clone();
+        let name = user.name.clone();
+        info!("{:?} authenticated as `{}` ({})", addr, user.name, user.role);
+        self.conn_mut(addr)?.user = Some(user);
+        Some(name)
+    }
+
+    /// change a runtime tunable setting and apply it to every book
+    pub fn set_config(&mut self, key: &str, value: &str) -> std::result::Result<(), String> {
+        let mut settings = (*self.settings).clone();
+        settings.set(key, value)?;
+        info!("CONFIG SET {} = {}", key, value);
+        self.settings = Arc::new(settings);
+        for book in self.books.values_mut() {
+            book.settings = Arc::clone(&self.settings);
+            book.config = BookConfig {
+                // the orderbook was built with the old precision
+                price_decimals: book.config.price_decimals,
+                tick_size: book.config.tick_size,
+                ..self.settings.book_config(&book.name)
+            };
+        }
+        Ok(())
+    }
 
     #[cfg_attr(feature = "count_alloc", count_alloc)]
     pub fn record_history(&mut self) {
@@ -372,12 +766,20 @@ impl TectonicServer {
     ///     "stores":
     ///     {
     ///         "name": "something", // name of the store
-    ///         "in_memory": true, // if the file is read into memory
-    ///         "count": 10 // number of rows in this store
+    ///         "in_memory": 10, // number of rows in memory
+    ///         "count": 10, // number of rows in this store
+    ///         "loaded": 0, // rows read by `LOAD`, evicted first
+    ///         "memory_bytes": 1572864
     ///     }
     /// }
-    pub fn info(&self) -> String {
-        let info_vec: Vec<String> = self.books
+    ///
+    /// Only books visible to the user behind `addr` are listed and counted.
+    pub fn info(&self, addr: Option<SocketAddr>) -> String {
+        let books: Vec<_> = self.books
+            .iter()
+            .filter(|(name, _)| self.can_see(addr, name))
+            .collect();
+        let info_vec: Vec<String> = books
             .iter()
             .map(|i| {
                 let (key, book) = i;
@@ -385,11 +787,15 @@ impl TectonicServer {
                     r#"{{
     "name": "{}",
     "in_memory": {},
-    "count": {}
+    "count": {},
+    "loaded": {},
+    "memory_bytes": {}
   }}"#,
                     key,
                     book.vec.len(),
                     book.nominal_count,
+                    book.loaded_count(),
+                    book.memory_usage(),
                 )
             })
             .collect();
@@ -402,7 +808,10 @@ impl TectonicServer {
     "autoflush_interval": {},
     "dtf_folder": "{}",
     "total_in_memory_count": {},
-    "total_count": {}
+    "total_count": {},
+    "memory_bytes": {},
+    "memory_limit_bytes": {},
+    "evictions": {}
   }}"#,
             self.connections.len(),
             self.subscriptions.iter().map(|i| i.1.len()).sum::<usize>(),
@@ -413,14 +822,17 @@ impl TectonicServer {
             self.settings.autoflush,
             self.settings.flush_interval,
             self.settings.dtf_folder,
-            self.books.iter().fold(
+            books.iter().fold(
                 0,
                 |acc, (_name, tup)| acc + tup.vec.len(),
             ),
-            self.books.iter().fold(
+            books.iter().fold(
                 0,
                 |acc, (_name, tup)| acc + tup.nominal_count,
-            )
+            ),
+            self.memory_usage(),
+            self.settings.memory_limit_mb << 20,
+            self.metrics.evictions,
         );
         let mut ret = format!(
             r#"{{
@@ -442,9 +854,12 @@ impl TectonicServer {
 
     /// Returns a JSON object like
     /// [{"total": [1508968738: 0]}, {"default": [1508968738: 0]}]
-    pub fn perf(&self) -> String {
+    ///
+    /// Only books visible to the user behind `addr` are listed, `total` needs access to all books.
+    pub fn perf(&self, addr: Option<SocketAddr>) -> String {
         let objs: Vec<String> = (&self.history)
             .iter()
+            .filter(|(name, _)| self.can_see(addr, name))
             .map(|(name, vec)| {
                 let hists: Vec<String> = vec.iter()
                     .map(|&(t, size)| {
@@ -462,15 +877,29 @@ impl TectonicServer {
     /// Insert a row into store
     pub async fn insert(&mut self, up: Update, book_name: &str) -> Option<()> {
         let book = self.books.get_mut(book_name)?;
+        let capacity = book.vec.capacity();
+        let before = Bbo::of(up.ts, &book.orderbook);
         book.add(up);
-        self.send_subs(up, book_name).await
+        let after = Bbo::of(up.ts, &book.orderbook);
+        let bbo = if before.same_quotes(&after) { None } else { Some(after) };
+        // ingest is never refused, but it may push loaded books out
+        if book.vec.capacity() > capacity && !self.make_room(0, book_name) {
+            warn!("Unflushed updates exceed the memory limit of {} MB", self.settings.memory_limit_mb);
+        }
+        self.send_subs(up, bbo, book_name).await
     }
 
-    async fn send_subs(&mut self, up: Update, book_name: &str) -> Option<()> {
+    /// `bbo` is the new top of the book if `up` changed it
+    async fn send_subs(&mut self, up: Update, bbo: Option<Bbo>, book_name: &str) -> Option<()> {
         if let Some(book_sub) = self.subscriptions.get_mut(book_name) {
-            for sub in book_sub.iter_mut() {
-                let bytes = tdb_core::utils::encode_insert_into(Some(book_name), &up).ok()?;
-                sub.1.send(ReturnType::Bytes(bytes)).await.ok()?;
+            for sub in book_sub.values_mut() {
+                let msg = match (sub.mode, bbo) {
+                    (SubscriptionMode::Updates, _) =>
+                        ReturnType::Bytes(tdb_core::utils::encode_insert_into(Some(book_name), &up).ok()?),
+                    (SubscriptionMode::Bbo, Some(bbo)) => ReturnType::string(bbo.as_json() + "\n"),
+                    (SubscriptionMode::Bbo, None) => continue,
+                };
+                sub.outbound.send(msg).await.ok()?;
             }
         }
         Some(())
@@ -488,20 +917,176 @@ impl TectonicServer {
         } else {
             self.books.insert(
                 book_name.to_owned(),
-                Book::new(book_name, self.settings.clone(), PRICE_DECIMALS),
+                Book::new(book_name, self.settings.clone()),
             );
             Some(())
         }
     }
 
-    /// load a datastore file into memory
-    pub fn load_db(&mut self, book_name: &BookName, addr: Option<SocketAddr>) -> Option<()> {
-        if self.books.contains_key(book_name) {
-            self.book_mut(addr)?.load();
-            Some(())
+    /// Remove a book from memory, subscriptions and disk. Returns the number of deleted files.
+    ///
+    /// Without `confirmed` nothing is removed. `default` is recreated empty.
+    pub fn drop_book(&mut self, book_name: &BookName, confirmed: bool) -> std::result::Result<usize, String> {
+        if !self.books.contains_key(book_name) {
+            return Err(format!("No db named `{}`", book_name));
+        }
+        let folder = &self.settings.dtf_folder;
+        let files = if Path::new(folder).exists() {
+            dtf::file_format::find_files_for_symbol(folder, book_name)
+                .map_err(|e| format!("Unable to read {}: {}", folder, e))?
         } else {
-            None
+            vec![]
+        };
+        if !confirmed {
+            return Err(format!(
+                "Dropping `{}` deletes {} file(s). Use `DROP {} CONFIRM` to proceed.",
+                book_name, files.len(), book_name));
+        }
+
+        self.books.remove(book_name);
+        self.subscriptions.remove(book_name);
+        self.history.remove(book_name);
+        self.reset_book_entries(book_name);
+        for fname in &files {
+            fs::remove_file(fname).map_err(|e| format!("Unable to delete {}: {}", fname, e))?;
+            info!("Deleted {}", fname);
+        }
+        let snap_fname = snapshot_fname(&self.settings.dtf_folder, book_name);
+        if Path::new(&snap_fname).exists() {
+            fs::remove_file(&snap_fname).map_err(|e| format!("Unable to delete {}: {}", snap_fname, e))?;
+        }
+        Ok(files.len())
+    }
+
+    /// Rename a book and rewrite the symbol of its files. Returns the number of rewritten files.
+    ///
+    /// `{old}.dtf` is moved to `{new}.dtf`. `default` is recreated empty.
+    pub fn rename_book(&mut self, old: &BookName, new: &BookName) -> std::result::Result<usize, String> {
+        if !self.books.contains_key(old) {
+            return Err(format!("No db named `{}`", old));
+        }
+        if self.books.contains_key(new) {
+            return Err(format!("Orderbook `{}` already exists.", new));
+        }
+        if new.len() > 20 {
+            return Err(format!("Name `{}` is longer than 20 bytes.", new));
+        }
+        let folder = &self.settings.dtf_folder;
+        let files = if Path::new(folder).exists() {
+            dtf::file_format::find_files_for_symbol(folder, old)
+                .map_err(|e| format!("Unable to read {}: {}", folder, e))?
+        } else {
+            vec![]
+        };
+        let (old_fname, new_fname) = (format!("{}/{}.dtf", folder, old), format!("{}/{}.dtf", folder, new));
+        if Path::new(&new_fname).exists() {
+            return Err(format!("File {} already exists.", new_fname));
+        }
+
+        for fname in &files {
+            dtf::file_format::rewrite_symbol(fname, new)
+                .map_err(|e| format!("Unable to rewrite {}: {}", fname, e))?;
+        }
+        if Path::new(&old_fname).exists() {
+            fs::rename(&old_fname, &new_fname)
+                .map_err(|e| format!("Unable to move {} to {}: {}", old_fname, new_fname, e))?;
+        }
+        let (old_snap, new_snap) = (snapshot_fname(folder, old), snapshot_fname(folder, new));
+        if Path::new(&old_snap).exists() {
+            fs::rename(&old_snap, &new_snap)
+                .map_err(|e| format!("Unable to move {} to {}: {}", old_snap, new_snap, e))?;
+        }
+
+        let mut book = self.books.remove(old).unwrap();
+        book.name = new.to_string();
+        book.config = BookConfig {
+            price_decimals: book.config.price_decimals,
+            tick_size: book.config.tick_size,
+            ..self.settings.book_config(new)
+        };
+        self.books.insert(new.to_owned(), book);
+        if let Some(subs) = self.subscriptions.remove(old) {
+            self.subscriptions.insert(new.to_owned(), subs);
+        }
+        if let Some(history) = self.history.remove(old) {
+            self.history.insert(new.to_owned(), history);
+        }
+        for conn in self.connections.values_mut() {
+            if conn.book_entry.as_str() == old.as_str() {
+                conn.book_entry = Arc::new(new.to_owned());
+            }
+        }
+        self.reset_book_entries(old);
+        Ok(files.len())
+    }
+
+    /// point connections using a removed book back to `default`, which always exists
+    fn reset_book_entries(&mut self, removed: &BookName) {
+        let default = BookName::from("default").unwrap();
+        if !self.books.contains_key(&default) {
+            self.books.insert(default, Book::new("default", self.settings.clone()));
+        }
+        for conn in self.connections.values_mut() {
+            if conn.book_entry.as_str() == removed.as_str() {
+                conn.book_entry = Arc::new(default);
+            }
+        }
+    }
+
+    /// Load a datastore file into memory, evicting least recently used
+    /// loaded books when it doesn't fit into `memory_limit_mb`.
+    pub fn load_db(&mut self, book_name: &BookName) -> std::result::Result<(), String> {
+        let book = self.books.get(book_name).ok_or_else(|| format!("No db named `{}`", book_name))?;
+        book.touch();
+        if book.in_memory {
+            return Ok(());
+        }
+        let fname = format!("{}/{}.dtf", self.settings.dtf_folder, book_name);
+        let needed = dtf::file_format::get_size(&fname).unwrap_or(0) as usize * mem::size_of::<Update>();
+        if !self.make_room(needed, book_name) {
+            return Err(format!(
+                "Not enough memory to load `{}`: needs {:.1} MB of {} MB.",
+                book_name, needed as f64 / (1 << 20) as f64, self.settings.memory_limit_mb));
+        }
+        self.books.get_mut(book_name).unwrap().load();
+        Ok(())
+    }
+
+    /// approximate bytes held by every book
+    pub fn memory_usage(&self) -> usize {
+        self.books.values().map(Book::memory_usage).sum()
+    }
+
+    /// Evict loaded updates of least recently used books other than `keep`
+    /// until `needed` more bytes fit into the memory limit. Unflushed updates are
+    /// never evicted, so this fails when they alone exceed the limit.
+    pub fn make_room(&mut self, needed: usize, keep: &str) -> bool {
+        let limit = match self.settings.memory_limit_mb {
+            0 => return true,
+            mb => mb << 20,
+        };
+        let mut usage = self.memory_usage();
+        let reclaimable: usize = self.books.values()
+            .filter(|book| book.name != keep)
+            .map(|book| book.loaded_count() * mem::size_of::<Update>())
+            .sum();
+        if usage.saturating_sub(reclaimable) + needed > limit {
+            return false;
+        }
+        while usage + needed > limit {
+            let lru = self.books.values_mut()
+                .filter(|book| book.name != keep && book.loaded_count() > 0)
+                .min_by_key(|book| book.last_access.get());
+            match lru {
+                Some(book) => {
+                    info!("Evicting {} loaded updates of {}", book.loaded_count(), book.name);
+                    usage -= book.evict();
+                    self.metrics.evictions += 1;
+                }
+                None => return false,
+            }
         }
+        true
     }
 
     /// load a datastore file into memory
@@ -526,27 +1111,27 @@ impl TectonicServer {
         Some(ret)
     }
 
-    /// Returns the total count
-    pub fn countall_in_mem(&self) -> u64 {
-        self.books.values().fold(
+    /// Returns the total count of books visible to the user behind `addr`
+    pub fn countall_in_mem(&self, addr: Option<SocketAddr>) -> u64 {
+        self.books.iter().filter(|(name, _)| self.can_see(addr, name)).fold(
             0,
-            |acc, book| acc + book.vec.len(),
+            |acc, (_name, book)| acc + book.vec.len(),
         ) as u64
     }
 
-    /// Returns the total count
-    pub fn countall(&self) -> u64 {
-        self.books.values().fold(
+    /// Returns the total count of books visible to the user behind `addr`
+    pub fn countall(&self, addr: Option<SocketAddr>) -> u64 {
+        self.books.iter().filter(|(name, _)| self.can_see(addr, name)).fold(
             0,
-            |acc, book| acc + book.nominal_count,
+            |acc, (_name, book)| acc + book.nominal_count,
         )
     }
 
-    pub fn sub(&mut self, book_name: &BookName, addr: Option<SocketAddr>) -> Option<()> {
+    pub fn sub(&mut self, book_name: &BookName, addr: Option<SocketAddr>, mode: SubscriptionMode) -> Option<()> {
         let outbound = self.conn_mut(addr)?.outbound.clone();
         let book_sub = self.subscriptions.entry(book_name.to_owned())
             .or_insert_with(HashMap::new);
-        book_sub.insert(addr.unwrap(), outbound);
+        book_sub.insert(addr.unwrap(), Subscriber { outbound, mode });
         Some(())
     }
 
@@ -563,6 +1148,7 @@ impl TectonicServer {
         let book = self.book_mut(addr)?;
         book.vec.clear();
         // vecs.1 = 0;
+        book.loaded = 0..0;
         book.in_memory = false;
         book.load_size_from_file();
         Some(())
@@ -573,6 +1159,7 @@ impl TectonicServer {
         for book in self.books.values_mut() {
             book.vec.clear();
             // vecs.1 = 0;
+            book.loaded = 0..0;
             book.in_memory = false;
             book.load_size_from_file();
         }
@@ -670,11 +1257,174 @@ impl TectonicServer {
         }
     }
 
+    /// Stream the updates of the current book in `range` (everything when none)
+    /// in chunks. Only the updates in memory are copied here, files are read
+    /// by the chunk producer.
+    pub fn get_stream(&self, count: ReqCount, format: GetFormat, range: Option<(u64, u64)>, loc: ReadLocation,
+        addr: Option<SocketAddr>) -> Option<ReturnType>
+    {
+        let book = self.book(addr)?;
+        let (min_ts, max_ts) = range.unwrap_or((0, u64::MAX));
+        let folder = match loc {
+            ReadLocation::Fs if Path::new(&self.settings.dtf_folder).exists() => Some(self.settings.dtf_folder.clone()),
+            _ => None,
+        };
+        let mem = book.vec.iter()
+            .filter(|up| up.ts >= min_ts && up.ts <= max_ts)
+            .cloned()
+            .collect();
+        let req = crate::chunked::StreamRequest {
+            folder,
+            symbol: book.name.clone(),
+            range: (min_ts, max_ts),
+            count: match count {
+                ReqCount::All => None,
+                ReqCount::Count(c) => Some(c as usize),
+            },
+            mem,
+            format,
+        };
+        Some(ReturnType::Stream(crate::chunked::spawn(req)))
+    }
+
+    /// Rebuild the orderbook of `book_name` as of `ts` by replaying updates
+    /// from disk and memory, starting at the nearest checkpoint.
+    pub fn orderbook_at(&self, book_name: &str, ts: u64) -> Option<Orderbook> {
+        let book = self.books.get(book_name)?;
+        let folder = &self.settings.dtf_folder;
+        let (mut ob, mut last) = checkpoint(folder, book_name, book.config, ts);
+        if Path::new(folder).exists() {
+            last = replay_from_disk(folder, book_name, last, ts, &mut |up| { ob.process_update(up); true })
+                .unwrap_or_else(|e| { error!("{}", e); None })
+                .or(last);
+        }
+        book.vec.iter()
+            .filter(|up| up.ts <= ts && last.map(|last| up.ts > last).unwrap_or(true))
+            .for_each(|up| ob.process_update(up));
+        Some(ob)
+    }
+
+    /// What `SUBSCRIBE book FROM ts` sends before live updates: the levels of
+    /// the orderbook at `ts` as updates, then every update after `ts`.
+    pub fn subscription_backlog(&self, book_name: &str, ts: u64) -> Option<Vec<Update>> {
+        let book = self.books.get(book_name)?;
+        let ob = self.orderbook_at(book_name, ts)?;
+        let level = |(&price, &size): (&u64, &f64), is_bid| Update {
+            ts,
+            seq: 0,
+            is_trade: false,
+            is_bid,
+            price: ob.undiscretize(price),
+            size: size as f32,
+        };
+        let mut ups: Vec<Update> = ob.bids.iter().map(|l| level(l, true))
+            .chain(ob.asks.iter().map(|l| level(l, false)))
+            .collect();
+        let mut last = Some(ts);
+        if Path::new(&self.settings.dtf_folder).exists() {
+            last = replay_from_disk(&self.settings.dtf_folder, book_name, last, u64::MAX, &mut |up| { ups.push(*up); true })
+                .unwrap_or_else(|e| { error!("{}", e); None })
+                .or(last);
+        }
+        ups.extend(book.vec.iter().filter(|up| last.map(|last| up.ts > last).unwrap_or(true)));
+        Some(ups)
+    }
+
+    /// Folder to read files from, none when it does not exist yet
+    fn dtf_folder(&self) -> Option<String> {
+        Some(self.settings.dtf_folder.clone()).filter(|folder| Path::new(folder).exists())
+    }
+
+    /// aggregate trades of `book_name` in range into candles. Updates are
+    /// read and fed to the candles on their own thread, see `chunked::spawn_reply`.
+    pub fn candles(&self, book_name: &str, bar_type: BarType, min_ts: u64, max_ts: u64, format: GetFormat)
+        -> Option<ReturnType>
+    {
+        let book = self.books.get(book_name)?;
+        let folder = self.dtf_folder();
+        let symbol = book_name.to_owned();
+        let mem: Vec<Update> = book.vec.iter()
+            .filter(|up| up.ts >= min_ts && up.ts <= max_ts)
+            .cloned()
+            .collect();
+        Some(crate::chunked::spawn_reply(move || {
+            let sampler: Box<dyn Sampler> = match bar_type {
+                BarType::Time(secs) => Box::new(TimeSampler::new(secs)),
+                BarType::Tick(n) => Box::new(TickSampler::trades(n)),
+                BarType::Volume(v) => Box::new(VolumeSampler::new(v)),
+                BarType::Dollar(d) => Box::new(DollarSampler::new(d)),
+            };
+            let mut builder = BarBuilder::new(sampler);
+            let mut candles = vec![];
+            let read = replay_range(folder.as_deref(), &symbol, (min_ts, max_ts), &mem, &mut |up| {
+                candles.extend(builder.push(up));
+                true
+            });
+            if let Err(e) = read {
+                return ReturnType::error(e);
+            }
+            candles.extend(builder.finish());
+            // unlike `TimeBarsIter` the last candle of the range is kept and
+            // timestamps stay in ms, candles start at the interval boundary
+            if let BarType::Time(secs) = bar_type {
+                candles.iter_mut().for_each(|c| c.start = c.start / (secs * 1000) * secs * 1000);
+            }
+            let mut ret = match format {
+                GetFormat::Json => {
+                    let objs: Vec<String> = candles.iter().map(Candle::as_json).collect();
+                    format!("[{}]", objs.join(", "))
+                }
+                GetFormat::Csv | GetFormat::Dtf => {
+                    let rows: Vec<String> = candles.iter().map(Candle::to_csv).collect();
+                    rows.join("\n")
+                }
+            };
+            ret.push('\n');
+            ReturnType::string(ret)
+        }))
+    }
+
+    /// vwap, twap, realized volatility, spread and trade size statistics of `book_name` in range,
+    /// starting from the book as it was before `min_ts`. The updates from the
+    /// nearest checkpoint on are read once, on their own thread.
+    pub fn stats(&self, book_name: &str, min_ts: u64, max_ts: u64, sampling: u64) -> Option<ReturnType> {
+        let book = self.books.get(book_name)?;
+        if max_ts.saturating_sub(min_ts) / sampling.max(1) > MAX_STATS_SAMPLES {
+            return Some(ReturnType::error(format!(
+                "STATS samples the book over {} times, use a shorter range or a larger EVERY", MAX_STATS_SAMPLES)));
+        }
+        let folder = self.settings.dtf_folder.clone();
+        let on_disk = self.dtf_folder();
+        let symbol = book_name.to_owned();
+        let config = book.config;
+        let mem: Vec<Update> = book.vec.iter().filter(|up| up.ts <= max_ts).cloned().collect();
+        Some(crate::chunked::spawn_reply(move || {
+            let (ob, last) = checkpoint(&folder, &symbol, config, min_ts.saturating_sub(1));
+            let mut builder = stats::StatsBuilder::new(ob, sampling);
+            let from = last.map(|last| last + 1).unwrap_or(0);
+            let read = replay_range(on_disk.as_deref(), &symbol, (from, max_ts), &mem, &mut |up| {
+                if up.ts < min_ts {
+                    builder.warm_up(up);
+                } else {
+                    builder.push(up);
+                }
+                true
+            });
+            if let Err(e) = read {
+                return ReturnType::error(e);
+            }
+            serde_json::to_string(&builder.finish())
+                .map(ReturnType::string)
+                .unwrap_or_else(|_| ReturnType::error("Unable to serialize stats"))
+        }))
+    }
+
     pub fn new_connection(&mut self, client_sender: Sender<ReturnType>, addr: SocketAddr) -> bool {
         match self.connections.entry(addr) {
             Entry::Occupied(..) => false,
             Entry::Vacant(entry) => {
                 entry.insert(Connection::new(client_sender));
+                self.metrics.connections_total += 1;
                 true
             }
         }
@@ -682,10 +1432,43 @@ impl TectonicServer {
 
     #[cfg_attr(feature = "count_alloc", count_alloc)]
     pub async fn command(&mut self, cmd: Command, addr: Option<SocketAddr>) {
+        let name = cmd.name();
+        let replay_from = match cmd {
+            Command::Subscribe(book_name, Some(ts)) => Some((book_name, ts)),
+            _ => None,
+        };
+        let bbo_of = match cmd {
+            Command::SubscribeBbo(book_name) => Some(book_name),
+            _ => None,
+        };
+        let start = Instant::now();
         let ret = self.process_command(cmd, addr).await;
+        // the backlog follows the reply so clients see `Subscribed to` first
+        let backlog = match (&ret, replay_from) {
+            (ReturnType::Error(_), _) | (_, None) => None,
+            (_, Some((book_name, ts))) => self.subscription_backlog(&book_name, ts).map(|ups| (book_name, ups)),
+        };
+        // BBO subscribers start from the current top of the book
+        let bbo = match (&ret, bbo_of) {
+            (ReturnType::Error(_), _) | (_, None) => None,
+            (_, Some(book_name)) => self.books.get(&book_name)
+                .map(|book| Bbo::of(book.last_ts.unwrap_or(0), &book.orderbook)),
+        };
+        self.metrics.observe_command(name, start.elapsed());
         if let Some(addr) = addr {
-            if self.connections.contains_key(&addr) {
-                self.connections.get_mut(&addr).unwrap().outbound.send(ret).await.unwrap();
+            if let Some(conn) = self.connections.get_mut(&addr) {
+                conn.outbound.send(ret).await.unwrap();
+                if let Some(bbo) = bbo {
+                    let _ = conn.outbound.send(ReturnType::string(bbo.as_json() + "\n")).await;
+                }
+                if let Some((book_name, ups)) = backlog {
+                    for up in &ups {
+                        let bytes = tdb_core::utils::encode_insert_into(Some(&book_name), up).unwrap();
+                        if conn.outbound.send(ReturnType::Bytes(bytes)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
             }
         }
     }
@@ -700,11 +1483,15 @@ impl TectonicServer {
 
     pub fn book_mut(&mut self, addr: Option<SocketAddr>) -> Option<&mut Book> {
         let book_name = Arc::clone(&self.conn(addr)?.book_entry);
-        self.books.get_mut(book_name.as_str())
+        let book = self.books.get_mut(book_name.as_str())?;
+        book.touch();
+        Some(book)
     }
 
     pub fn book(&self, addr: Option<SocketAddr>) -> Option<&Book> {
         let book_name = Arc::clone(&self.conn(addr)?.book_entry);
-        self.books.get(book_name.as_str())
+        let book = self.books.get(book_name.as_str())?;
+        book.touch();
+        Some(book)
     }
 }
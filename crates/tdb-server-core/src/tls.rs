@@ -0,0 +1,42 @@
+//! TLS termination for client connections.
+//!
+//! Enabled by setting both `TDB_TLS_CERT` and `TDB_TLS_KEY` (or `--tls-cert` and
+//! `--tls-key`) to PEM files. The key may be PKCS8 or RSA encoded.
+use crate::prelude::*;
+use crate::settings::TlsSettings;
+use async_tls::TlsAcceptor;
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig};
+use std::fs::File;
+use std::io::BufReader as StdBufReader;
+
+fn load_certs(fname: &str) -> Result<Vec<Certificate>> {
+    let mut rdr = StdBufReader::new(File::open(fname)
+        .map_err(|e| format!("Unable to open TLS certificate {}: {}", fname, e))?);
+    let certs = certs(&mut rdr)
+        .map_err(|()| format!("Invalid PEM certificate: {}", fname))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", fname).into());
+    }
+    Ok(certs)
+}
+
+fn load_key(fname: &str) -> Result<PrivateKey> {
+    let read_keys = |parse: fn(&mut dyn std::io::BufRead) -> std::result::Result<Vec<PrivateKey>, ()>| -> Result<Vec<PrivateKey>> {
+        let mut rdr = StdBufReader::new(File::open(fname)
+            .map_err(|e| format!("Unable to open TLS key {}: {}", fname, e))?);
+        parse(&mut rdr).map_err(|()| format!("Invalid PEM key: {}", fname).into())
+    };
+    let mut keys = read_keys(pkcs8_private_keys)?;
+    if keys.is_empty() {
+        keys = read_keys(rsa_private_keys)?;
+    }
+    keys.pop().ok_or_else(|| format!("No private key found in {}", fname).into())
+}
+
+/// build an acceptor from the certificate chain and private key in `settings`
+pub fn acceptor(settings: &TlsSettings) -> Result<TlsAcceptor> {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(load_certs(&settings.cert)?, load_key(&settings.key)?)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -16,7 +16,15 @@ fn it_works() {
         flush_interval: 1000,
         granularity: 1000,
         q_capacity: 1000,
+        memory_limit_mb: 0,
+        snapshot_interval: 0,
+        snapshot_secs: 0,
         influx: None,
+        auth: None,
+        tls: None,
+        metrics_addr: None,
+        books: Default::default(),
+        gstorage: None,
     });
 
     task::block_on(async move {